@@ -1,10 +1,15 @@
 use std::num::NonZeroUsize;
 use std::time::Duration;
 
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
 use shot_limit::FixedWindow;
 use shot_limit::Gcra;
+use shot_limit::Permit;
+use shot_limit::Quota;
+use shot_limit::QuotaError;
+use shot_limit::SharedGcra;
 use shot_limit::SlidingWindow;
 use shot_limit::Strategy;
 use shot_limit::TokenBucket;
@@ -16,19 +21,20 @@ struct PyTokenBucket(TokenBucket);
 impl PyTokenBucket {
     #[new]
     fn new(capacity: usize, increment: usize, period_secs: u64) -> PyResult<Self> {
-        let capacity = NonZeroUsize::new(capacity).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>("capacity must be non-zero")
-        })?;
-        let increment = NonZeroUsize::new(increment).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>("increment must be non-zero")
-        })?;
-        let period = Duration::from_secs(period_secs);
-        Ok(PyTokenBucket(TokenBucket::new(capacity, increment, period)))
+        let quota = quota(increment, period_secs)?
+            .with_burst(capacity)
+            .map_err(value_error)?;
+        Ok(PyTokenBucket(TokenBucket::from_quota(quota)))
     }
 
     fn process(&self) -> bool {
         self.0.process().is_continue()
     }
+
+    #[pyo3(signature = (cost=1, timeout_secs=None))]
+    fn acquire(&self, py: Python<'_>, cost: usize, timeout_secs: Option<f64>) -> PyResult<bool> {
+        acquire_blocking(py, &self.0, cost, timeout_secs)
+    }
 }
 
 #[pyclass(name = "FixedWindow")]
@@ -38,16 +44,20 @@ struct PyFixedWindow(FixedWindow);
 impl PyFixedWindow {
     #[new]
     fn new(capacity: usize, period_secs: u64) -> PyResult<Self> {
-        let capacity = NonZeroUsize::new(capacity).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>("capacity must be non-zero")
-        })?;
-        let period = Duration::from_secs(period_secs);
-        Ok(PyFixedWindow(FixedWindow::new(capacity, period)))
+        Ok(PyFixedWindow(FixedWindow::from_quota(quota(
+            capacity,
+            period_secs,
+        )?)))
     }
 
     fn process(&self) -> bool {
         self.0.process().is_continue()
     }
+
+    #[pyo3(signature = (cost=1, timeout_secs=None))]
+    fn acquire(&self, py: Python<'_>, cost: usize, timeout_secs: Option<f64>) -> PyResult<bool> {
+        acquire_blocking(py, &self.0, cost, timeout_secs)
+    }
 }
 
 #[pyclass(name = "Gcra")]
@@ -57,16 +67,17 @@ struct PyGcra(Gcra);
 impl PyGcra {
     #[new]
     fn new(limit: usize, period_secs: u64) -> PyResult<Self> {
-        let limit = NonZeroUsize::new(limit).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>("limit must be non-zero")
-        })?;
-        let period = Duration::from_secs(period_secs);
-        Ok(PyGcra(Gcra::new(limit, period)))
+        Ok(PyGcra(Gcra::from_quota(quota(limit, period_secs)?)))
     }
 
     fn process(&self) -> bool {
         self.0.process().is_continue()
     }
+
+    #[pyo3(signature = (cost=1, timeout_secs=None))]
+    fn acquire(&self, py: Python<'_>, cost: usize, timeout_secs: Option<f64>) -> PyResult<bool> {
+        acquire_blocking(py, &self.0, cost, timeout_secs)
+    }
 }
 
 #[pyclass(name = "SlidingWindow")]
@@ -76,16 +87,70 @@ struct PySlidingWindow(SlidingWindow);
 impl PySlidingWindow {
     #[new]
     fn new(capacity: usize, period_secs: u64) -> PyResult<Self> {
-        let capacity = NonZeroUsize::new(capacity).ok_or_else(|| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>("capacity must be non-zero")
-        })?;
-        let period = Duration::from_secs(period_secs);
-        Ok(PySlidingWindow(SlidingWindow::new(capacity, period)))
+        Ok(PySlidingWindow(SlidingWindow::from_quota(quota(
+            capacity,
+            period_secs,
+        )?)))
     }
 
     fn process(&self) -> bool {
         self.0.process().is_continue()
     }
+
+    #[pyo3(signature = (cost=1, timeout_secs=None))]
+    fn acquire(&self, py: Python<'_>, cost: usize, timeout_secs: Option<f64>) -> PyResult<bool> {
+        acquire_blocking(py, &self.0, cost, timeout_secs)
+    }
+}
+
+#[pyclass(name = "SharedGcra")]
+struct PySharedGcra(SharedGcra);
+
+#[pymethods]
+impl PySharedGcra {
+    #[new]
+    fn new(path: std::path::PathBuf, limit: usize, period_secs: u64) -> PyResult<Self> {
+        Ok(PySharedGcra(SharedGcra::open(
+            path,
+            quota(limit, period_secs)?,
+        )?))
+    }
+
+    fn process(&self) -> bool {
+        self.0.process().is_continue()
+    }
+
+    #[pyo3(signature = (cost=1, timeout_secs=None))]
+    fn acquire(&self, py: Python<'_>, cost: usize, timeout_secs: Option<f64>) -> PyResult<bool> {
+        acquire_blocking(py, &self.0, cost, timeout_secs)
+    }
+}
+
+/// Validates a limit per `period_secs`, raising `ValueError` for invalid ones.
+fn quota(limit: usize, period_secs: u64) -> PyResult<Quota> {
+    Quota::new(limit, Duration::from_secs(period_secs)).map_err(value_error)
+}
+
+/// Blocks until `strategy` admits `cost` units, or `timeout_secs` passes, without
+/// holding the GIL.
+fn acquire_blocking<S: Strategy>(
+    py: Python<'_>,
+    strategy: &S,
+    cost: usize,
+    timeout_secs: Option<f64>,
+) -> PyResult<bool> {
+    let cost = NonZeroUsize::new(cost)
+        .ok_or_else(|| PyValueError::new_err("cost must be greater than zero"))?;
+    let timeout = timeout_secs
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(format!("invalid timeout: {e}")))?;
+
+    Ok(py.detach(|| Permit::acquire_blocking(strategy, cost, timeout).is_continue()))
+}
+
+fn value_error(e: QuotaError) -> PyErr {
+    PyValueError::new_err(e.to_string())
 }
 
 pub fn init_python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
@@ -93,5 +158,6 @@ pub fn init_python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyFixedWindow>()?;
     m.add_class::<PyGcra>()?;
     m.add_class::<PySlidingWindow>()?;
+    m.add_class::<PySharedGcra>()?;
     Ok(())
 }
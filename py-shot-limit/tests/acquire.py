@@ -0,0 +1,26 @@
+import time
+import py_shot_limit as shot_limit
+import pytest
+
+
+# Two requests per second are spaced half a second apart once the burst is used,
+# so acquire() waits for the next one unless its timeout is shorter
+def test_with_acquire():
+    gcra = shot_limit.Gcra(limit=2, period_secs=1)
+    assert gcra.acquire()
+    assert gcra.acquire(cost=1)
+
+    start = time.monotonic()
+    assert gcra.acquire(timeout_secs=0.1) is False
+    assert time.monotonic() - start < 0.1
+
+    assert gcra.acquire(timeout_secs=1.0)
+    assert time.monotonic() - start >= 0.4
+
+
+def test_acquire_rejects_invalid_arguments():
+    bucket = shot_limit.TokenBucket(capacity=1, increment=1, period_secs=1)
+    with pytest.raises(ValueError):
+        bucket.acquire(cost=0)
+    with pytest.raises(ValueError):
+        bucket.acquire(timeout_secs=-1.0)
@@ -0,0 +1,17 @@
+import os
+import tempfile
+import py_shot_limit as shot_limit
+
+
+# Two limiters opened on the same file (as two worker processes would)
+# share a single budget of 2 requests per minute
+def test_with_shared_gcra():
+    with tempfile.TemporaryDirectory() as tmp:
+        path = os.path.join(tmp, "limit")
+        first = shot_limit.SharedGcra(path=path, limit=2, period_secs=60)
+        second = shot_limit.SharedGcra(path=path, limit=2, period_secs=60)
+
+        assert first.process()
+        assert second.process()
+        assert first.process() is False
+        assert second.process() is False
@@ -23,7 +23,9 @@ use governor::state::direct::NotKeyed;
 use shot_limit::FixedWindow;
 use shot_limit::Gcra;
 use shot_limit::Reason;
+use shot_limit::ShardedTokenBucket;
 use shot_limit::SlidingWindow;
+use shot_limit::State;
 use shot_limit::Strategy;
 use shot_limit::TokenBucket;
 
@@ -31,18 +33,50 @@ use shot_limit::TokenBucket;
 #[derive(Debug)]
 struct GovernorStrategy {
     limiter: Arc<RateLimiter<NotKeyed, InMemoryState, QuantaClock>>,
+    quota: Quota,
     clock: QuantaClock,
 }
 
 impl Strategy for GovernorStrategy {
-    fn process(&self) -> ControlFlow<Reason> {
-        match self.limiter.check() {
-            Ok(_) => ControlFlow::Continue(()),
-            Err(negative) => {
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        // Governor can never admit more than u32::MAX cells at once
+        let Ok(n) = NonZeroU32::try_from(cost) else {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: cost.get(),
+                capacity: u32::MAX as usize,
+                policy: "governor",
+            });
+        };
+        match self.limiter.check_n(n) {
+            Ok(Ok(_)) => ControlFlow::Continue(()),
+            Ok(Err(negative)) => {
                 let now = self.clock.now();
                 let wait: Duration = negative.wait_time_from(now);
-                ControlFlow::Break(Reason::Overloaded { retry_after: wait })
+                ControlFlow::Break(Reason::Overloaded {
+                    retry_after: wait,
+                    limit: self.quota.burst_size().get() as usize,
+                    remaining: 0,
+                    reset_after: wait,
+                    policy: "governor",
+                })
             }
+            Err(insufficient) => ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: cost.get(),
+                capacity: insufficient.0 as usize,
+                policy: "governor",
+            }),
+        }
+    }
+
+    // Governor can't be inspected without consuming, so report it as exhausted
+    fn state(&self) -> State {
+        let burst = self.quota.burst_size().get();
+        let interval = self.quota.replenish_interval();
+        State {
+            limit: burst as usize,
+            remaining: 0,
+            reset_after: interval * burst,
+            retry_after: interval,
         }
     }
 }
@@ -131,6 +165,9 @@ fn run_all_benches(c: &mut Criterion) {
     let fw = Arc::new(FixedWindow::new(limit, period));
     let sw = Arc::new(SlidingWindow::new(limit, period));
     let tb = Arc::new(TokenBucket::new(limit, limit, period));
+    let stb = Arc::new(ShardedTokenBucket::new(limit, limit, period));
+    // Only ever touches its own shard, trading accuracy for scaling
+    let stb_local = Arc::new(ShardedTokenBucket::new(limit, limit, period).with_steal(0));
     let gcra = Arc::new(Gcra::new(limit, period));
 
     // Governor setup
@@ -139,6 +176,7 @@ fn run_all_benches(c: &mut Criterion) {
     let gov_limiter = Arc::new(RateLimiter::direct_with_clock(gov_quota, gov_clock.clone()));
     let gov = Arc::new(GovernorStrategy {
         limiter: gov_limiter,
+        quota: gov_quota,
         clock: gov_clock,
     });
 
@@ -156,6 +194,11 @@ fn run_all_benches(c: &mut Criterion) {
     bench_single_strategy("TokenBucket-Static", c, Arc::clone(&tb));
     bench_parallel_strategy("TokenBucket-Static", c, tb.clone());
 
+    // ShardedTokenBucket
+    bench_single_strategy("ShardedTokenBucket-Static", c, Arc::clone(&stb));
+    bench_parallel_strategy("ShardedTokenBucket-Static", c, stb.clone());
+    bench_parallel_strategy("ShardedTokenBucket-NoSteal-Static", c, stb_local);
+
     // Gcra
     bench_single_strategy("Gcra-Static", c, Arc::clone(&gcra));
     bench_parallel_strategy("Gcra-Static", c, gcra.clone());
@@ -171,6 +214,7 @@ fn run_all_benches(c: &mut Criterion) {
         ("FixedWindow", fw),
         ("SlidingWindow", sw),
         ("TokenBucket", tb),
+        ("ShardedTokenBucket", stb),
         ("Gcra", gcra),
         ("Governor", gov),
     ];
@@ -0,0 +1,273 @@
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio::time::Sleep;
+use tokio::time::sleep;
+use tokio::time::sleep_until;
+
+use crate::Permit;
+use crate::Reason;
+use crate::Strategy;
+
+/// Waits for capacity from any [`Strategy`], with the `tokio` feature.
+///
+/// This is for code outside `tower`, such as background jobs, crawlers and SDK
+/// clients, which would rather wait for the limit than be rejected by it.
+///
+/// ```rust
+/// use shot_limit::AsyncAcquire;
+/// use shot_limit::Gcra;
+/// use shot_limit::Quota;
+///
+/// # async fn crawl() -> Result<(), shot_limit::QuotaError> {
+/// let limit = Gcra::from_quota(Quota::per_second(10)?);
+/// for _page in 0..100 {
+///     let _permit = limit.until_ready().await;
+///     // Fetch the page
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub trait AsyncAcquire: Strategy {
+    /// Waits until the strategy admits a request which consumes `cost` units.
+    fn acquire(&self, cost: NonZeroUsize) -> Acquire<&Self> {
+        Acquire::new(self, cost)
+    }
+
+    /// Waits until the strategy admits a single request.
+    fn until_ready(&self) -> Acquire<&Self> {
+        self.acquire(NonZeroUsize::MIN)
+    }
+}
+
+impl<S: Strategy + ?Sized> AsyncAcquire for S {}
+
+/// A future which waits for a strategy to admit a request, resolving to its
+/// [`Permit`].
+///
+/// Between attempts it sleeps for the `retry_after` of each rejection, and it sleeps
+/// out the delay handed out by shaping strategies such as
+/// [`LeakyBucket`](crate::LeakyBucket) before resolving.
+///
+/// It holds nothing while waiting to retry. Dropping it while waiting for a delay
+/// gives the reservation back with [`Strategy::refund_n`], so it is only returned as
+/// far as the strategy's refunds go: a `LeakyBucket` keeps the abandoned request's
+/// place in its queue, while the other strategies of an [`AllOf`](crate::AllOf) get
+/// their capacity back.
+///
+/// Resolves to [`ControlFlow::Break`] if waiting will never help, or, with a
+/// deadline, if the request would not be admitted by then. Build one with
+/// [`AsyncAcquire`], or with [`Acquire::new`] to own the pointer, e.g. an `Arc` for
+/// a spawned task.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless awaited"]
+pub struct Acquire<P>
+where
+    P: Deref<Target: Strategy>,
+{
+    strategy: P,
+    cost: NonZeroUsize,
+    deadline: Option<Instant>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    /// Reserved, but waiting for its delay to pass.
+    permit: Option<Permit<P>>,
+}
+
+impl<P> Acquire<P>
+where
+    P: Deref<Target: Strategy>,
+{
+    /// Waits until `strategy` admits a request which consumes `cost` units.
+    pub fn new(strategy: P, cost: NonZeroUsize) -> Self {
+        Self {
+            strategy,
+            cost,
+            deadline: None,
+            sleep: None,
+            permit: None,
+        }
+    }
+
+    /// Gives up, rather than wait past `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
+// Neither the strategy pointer nor the permit is ever pinned
+impl<P> Unpin for Acquire<P> where P: Deref<Target: Strategy> {}
+
+impl<P> Future for Acquire<P>
+where
+    P: Deref<Target: Strategy> + Clone,
+{
+    type Output = ControlFlow<Reason, Permit<P>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(sleep) = this.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.sleep = None;
+
+                if let Some(permit) = this.permit.take() {
+                    return Poll::Ready(ControlFlow::Continue(permit));
+                }
+            }
+
+            let now = Instant::now();
+            let max_delay = this.deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_duration_since(now)
+            });
+
+            match Permit::reserve_n(this.strategy.clone(), this.cost, max_delay) {
+                ControlFlow::Continue((permit, delay)) if delay.is_zero() => {
+                    return Poll::Ready(ControlFlow::Continue(permit));
+                }
+                ControlFlow::Continue((permit, delay)) => {
+                    this.permit = Some(permit);
+                    this.sleep = Some(Box::pin(sleep(delay)));
+                }
+                ControlFlow::Break(reason) => {
+                    // Give up if waiting will never help, or will take too long
+                    let retry_at = match reason.retry_after() {
+                        Some(retry_after) => now.checked_add(retry_after),
+                        None => return Poll::Ready(ControlFlow::Break(reason)),
+                    };
+                    // A retry too far off to represent will never come
+                    let Some(retry_at) = retry_at else {
+                        return Poll::Ready(ControlFlow::Break(reason));
+                    };
+                    if this.deadline.is_some_and(|deadline| retry_at > deadline) {
+                        return Poll::Ready(ControlFlow::Break(reason));
+                    }
+                    this.sleep = Some(Box::pin(sleep_until(retry_at)));
+                }
+            }
+        }
+    }
+}
+
+impl<P> Drop for Acquire<P>
+where
+    P: Deref<Target: Strategy>,
+{
+    fn drop(&mut self) {
+        // Cancelled while waiting for the delay, so the request never happens
+        if let Some(permit) = self.permit.take() {
+            permit.refund();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::AllOf;
+    use crate::LeakyBucket;
+    use crate::Quota;
+    use crate::State;
+    use crate::TokenBucket;
+    use crate::TokioClock;
+
+    #[tokio::test(start_paused = true)]
+    async fn it_waits_for_the_limit() {
+        let rl = TokenBucket::from_quota_with_clock(Quota::per_second(1).unwrap(), TokioClock);
+        let start = Instant::now();
+
+        drop(rl.until_ready().await.continue_value().unwrap());
+        drop(rl.until_ready().await.continue_value().unwrap());
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        // The next permit is a second away, which is past the deadline
+        let reason = rl
+            .until_ready()
+            .with_deadline(Instant::now() + Duration::from_millis(500))
+            .await
+            .break_value()
+            .unwrap();
+        assert_eq!(reason.retry_after(), Some(Duration::from_secs(1)));
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        // Asking for more than the capacity will never succeed
+        let reason = rl
+            .acquire(NonZeroUsize::new(2).unwrap())
+            .await
+            .break_value()
+            .unwrap();
+        assert_eq!(reason.retry_after(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_refunds_a_cancelled_delay() {
+        let tokens = Arc::new(TokenBucket::from_quota_with_clock(
+            Quota::per_second(1).unwrap().with_burst(2).unwrap(),
+            TokioClock,
+        ));
+        let shaper = Arc::new(LeakyBucket::from_quota_with_clock(
+            Quota::per_second(1).unwrap().with_burst(2).unwrap(),
+            TokioClock,
+        ));
+        let rl = Arc::new(AllOf::new([
+            Arc::clone(&tokens) as Arc<dyn Strategy>,
+            shaper as Arc<dyn Strategy>,
+        ]));
+
+        drop(Acquire::new(Arc::clone(&rl), NonZeroUsize::MIN).await);
+        assert_eq!(tokens.state().remaining, 1);
+
+        // The second request is queued a second behind the first, then abandoned
+        let waiting = tokio::spawn(Acquire::new(Arc::clone(&rl), NonZeroUsize::MIN));
+        tokio::task::yield_now().await;
+        assert_eq!(tokens.state().remaining, 0);
+
+        // The bucket gets its token back, though the shaper keeps the request's place
+        waiting.abort();
+        assert!(waiting.await.unwrap_err().is_cancelled());
+        assert_eq!(tokens.state().remaining, 1);
+    }
+
+    /// Rejects everything, with a `retry_after` too long to wait for.
+    #[derive(Debug)]
+    struct Stalled;
+
+    impl Strategy for Stalled {
+        fn process_n(&self, _cost: NonZeroUsize) -> ControlFlow<Reason> {
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::MAX,
+                limit: 1,
+                remaining: 0,
+                reset_after: Duration::MAX,
+                policy: "stalled",
+            })
+        }
+
+        fn state(&self) -> State {
+            State {
+                limit: 1,
+                remaining: 0,
+                reset_after: Duration::MAX,
+                retry_after: Duration::MAX,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_gives_up_on_a_retry_too_far_off_to_represent() {
+        let reason = Stalled.until_ready().await.break_value().unwrap();
+        assert_eq!(reason.policy(), "stalled");
+    }
+}
@@ -0,0 +1,439 @@
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use crate::Backoff;
+use crate::Feedback;
+use crate::Gcra;
+use crate::LeakyBucket;
+use crate::Outcome;
+use crate::Quota;
+use crate::QuotaError;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::TimeSource;
+use crate::TokenBucket;
+use crate::float;
+use crate::sync::AtomicU128;
+
+/// Marks `Aimd::applied` while a limit is being passed on to the inner strategy.
+const APPLYING: u64 = u64::MAX;
+
+/// A strategy whose rate can be changed while it is in use.
+pub trait AdjustableRate: Strategy {
+    /// Changes the rate to `quota.limit()` requests every `quota.period()`, keeping
+    /// current usage.
+    fn set_rate(&self, quota: Quota);
+}
+
+impl<C: TimeSource> AdjustableRate for TokenBucket<C> {
+    fn set_rate(&self, quota: Quota) {
+        TokenBucket::set_rate(self, quota);
+    }
+}
+
+impl<C: TimeSource> AdjustableRate for Gcra<C> {
+    fn set_rate(&self, quota: Quota) {
+        Gcra::set_rate(self, quota);
+    }
+}
+
+impl<C: TimeSource> AdjustableRate for LeakyBucket<C> {
+    fn set_rate(&self, quota: Quota) {
+        LeakyBucket::set_rate(self, quota);
+    }
+}
+
+/// Adapts the rate of another strategy with Additive Increase, Multiplicative
+/// Decrease (AIMD).
+///
+/// Report how each admitted request went with [`Aimd::record`]. Every failure, or
+/// success slower than the latency threshold, multiplies the limit by the decrease
+/// factor (0.5 by default). Once a whole limit's worth of requests has succeeded, the
+/// limit grows by the increase (1 by default). The limit always stays between the
+/// bounds, which default to 1 and the initial limit.
+///
+/// Each failure backs off on its own, so when many requests in flight fail together
+/// the limit drops quickly. Use a decrease factor closer to 1 under high concurrency.
+/// Racing changes reach the inner strategy in the order they were made, so its rate
+/// always ends up at [`Aimd::limit`].
+#[derive(Debug)]
+pub struct Aimd<S> {
+    inner: S,
+    period: Duration,
+    min: usize,
+    max: usize,
+    increase: usize,
+    decrease_factor: f64,
+    latency_threshold: Option<Duration>,
+    /// The current [`Limit`], packed.
+    limit: AtomicU128,
+    /// The generation of the last limit passed on to `inner`, or [`APPLYING`] while
+    /// one is being passed on.
+    applied: AtomicU64,
+    /// Successes recorded since the limit last changed.
+    successes: AtomicUsize,
+}
+
+/// A limit, and how many times the limit had changed when it was set.
+#[derive(Clone, Copy)]
+struct Limit {
+    generation: u64,
+    value: usize,
+}
+
+impl Limit {
+    #[inline]
+    fn pack(self) -> u128 {
+        ((self.generation as u128) << 64) | self.value as u128
+    }
+
+    #[inline]
+    fn unpack(word: u128) -> Self {
+        Self {
+            generation: (word >> 64) as u64,
+            value: word as u64 as usize,
+        }
+    }
+}
+
+impl<S: AdjustableRate> Aimd<S> {
+    /// Creates a new `Aimd` strategy, starting `inner` at `quota.limit()` requests per
+    /// `quota.period()`.
+    ///
+    /// Every limit is applied with a burst of the whole limit, so the quota's burst is
+    /// ignored.
+    pub fn new(inner: S, quota: Quota) -> Self {
+        let limit = quota.limit().get();
+        let aimd = Self {
+            inner,
+            period: quota.period(),
+            min: 1,
+            max: limit,
+            increase: 1,
+            decrease_factor: 0.5,
+            latency_threshold: None,
+            limit: AtomicU128::new(
+                Limit {
+                    generation: 0,
+                    value: limit,
+                }
+                .pack(),
+            ),
+            applied: AtomicU64::new(0),
+            successes: AtomicUsize::new(0),
+        };
+        aimd.set_rate(limit);
+        aimd
+    }
+
+    /// Sets the lowest and highest limits to adapt between.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`QuotaError`] if `min` is greater than `max`, or `max` requests can't
+    /// be spread across the period (see [`Quota::new`]).
+    pub fn with_bounds(mut self, min: NonZeroUsize, max: NonZeroUsize) -> Result<Self, QuotaError> {
+        if min > max {
+            return Err(QuotaError::InvertedBounds {
+                min: min.get(),
+                max: max.get(),
+            });
+        }
+        // Every limit up to `max` then makes a valid quota
+        Quota::new(max.get(), self.period)?;
+        self.min = min.get();
+        self.max = max.get();
+
+        self.update(|limit| limit);
+        Ok(self)
+    }
+
+    /// Sets how much the limit grows once a whole limit's worth of requests succeeds.
+    pub fn with_increase(mut self, increase: NonZeroUsize) -> Self {
+        self.increase = increase.get();
+        self
+    }
+
+    /// Sets what the limit is multiplied by on failure.
+    ///
+    /// # Panics
+    ///
+    /// Panics unless `0 < factor < 1`.
+    pub fn with_decrease_factor(mut self, factor: f64) -> Self {
+        assert!(
+            factor > 0.0 && factor < 1.0,
+            "decrease factor must be between 0 and 1"
+        );
+        self.decrease_factor = factor;
+        self
+    }
+
+    /// Treats successes which take longer than `threshold` as failures.
+    pub fn with_latency_threshold(mut self, threshold: Duration) -> Self {
+        self.latency_threshold = Some(threshold);
+        self
+    }
+
+    /// The current limit, in requests per period.
+    pub fn limit(&self) -> usize {
+        Limit::unpack(self.limit.load(Ordering::Acquire)).value
+    }
+
+    /// The strategy whose rate is being adapted.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Records how an admitted request went, which took `latency` to complete.
+    pub fn record(&self, outcome: Outcome, latency: Duration) {
+        let slow = self
+            .latency_threshold
+            .is_some_and(|threshold| latency > threshold);
+
+        if outcome == Outcome::Failure || slow {
+            self.decrease();
+        } else {
+            self.succeed();
+        }
+    }
+
+    fn succeed(&self) {
+        let successes = self.successes.fetch_add(1, Ordering::AcqRel) + 1;
+        if successes < self.limit() {
+            return;
+        }
+
+        self.successes.store(0, Ordering::Release);
+        self.update(|limit| limit.saturating_add(self.increase));
+    }
+
+    fn decrease(&self) {
+        self.successes.store(0, Ordering::Release);
+        self.update(|limit| float::floor(limit as f64 * self.decrease_factor) as usize);
+    }
+
+    /// Applies `f` to the limit, within the bounds, and passes any change on.
+    fn update(&self, f: impl Fn(usize) -> usize) {
+        let mut changed = None;
+        let _ = self
+            .limit
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |limit| {
+                let limit = Limit::unpack(limit);
+                let value = f(limit.value).clamp(self.min, self.max);
+                changed = (value != limit.value).then_some(Limit {
+                    generation: limit.generation + 1,
+                    value,
+                });
+                changed.map(Limit::pack)
+            });
+
+        if let Some(limit) = changed {
+            self.apply(limit);
+        }
+    }
+
+    /// Passes `limit` on to the inner strategy, unless a later one already has been.
+    fn apply(&self, limit: Limit) {
+        let backoff = Backoff::default();
+        let mut failures = 0;
+        loop {
+            let applied = self.applied.load(Ordering::Acquire);
+            if applied != APPLYING {
+                if applied >= limit.generation {
+                    return;
+                }
+                if self
+                    .applied
+                    .compare_exchange_weak(applied, APPLYING, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+            }
+            failures += 1;
+            backoff.snooze(failures);
+        }
+
+        self.set_rate(limit.value);
+        self.applied.store(limit.generation, Ordering::Release);
+    }
+
+    fn set_rate(&self, limit: usize) {
+        // The bounds keep the limit within a valid quota
+        if let Ok(quota) = Quota::new(limit, self.period) {
+            self.inner.set_rate(quota);
+        }
+    }
+}
+
+impl<S: AdjustableRate> Feedback for Aimd<S> {
+    fn record(&self, outcome: Outcome, latency: Duration) {
+        Aimd::record(self, outcome, latency);
+    }
+}
+
+impl<S: Strategy> Strategy for Aimd<S> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        self.inner.process_n(cost)
+    }
+
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        self.inner.reserve_n(cost, max_delay)
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        self.inner.release_n(cost);
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.inner.refund_n(cost);
+    }
+
+    fn state(&self) -> State {
+        self.inner.state()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use quanta::Clock;
+
+    use super::*;
+
+    fn gcra(clock: Clock) -> Aimd<Gcra> {
+        let limit = NonZeroUsize::new(8).unwrap();
+        let period = Duration::from_secs(1);
+        let quota = Quota::new(limit.get(), period).unwrap();
+        Aimd::new(Gcra::with_clock(limit, period, clock), quota)
+            .with_bounds(
+                NonZeroUsize::new(2).unwrap(),
+                NonZeroUsize::new(10).unwrap(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn it_backs_off_multiplicatively() {
+        let (clock, _mock) = Clock::mock();
+        let rl = gcra(clock);
+
+        rl.record(Outcome::Failure, Duration::ZERO);
+        assert_eq!(rl.limit(), 4);
+        assert_eq!(rl.state().limit, 4);
+
+        rl.record(Outcome::Failure, Duration::ZERO);
+        rl.record(Outcome::Failure, Duration::ZERO);
+        assert_eq!(rl.limit(), 2, "Never drops below the minimum");
+    }
+
+    #[test]
+    fn it_grows_additively_after_a_window_of_successes() {
+        let (clock, _mock) = Clock::mock();
+        let rl = gcra(clock).with_increase(NonZeroUsize::new(2).unwrap());
+
+        for _ in 0..7 {
+            rl.record(Outcome::Success, Duration::ZERO);
+        }
+        assert_eq!(rl.limit(), 8);
+
+        rl.record(Outcome::Success, Duration::ZERO);
+        assert_eq!(rl.limit(), 10);
+        assert_eq!(rl.state().limit, 10);
+
+        for _ in 0..10 {
+            rl.record(Outcome::Success, Duration::ZERO);
+        }
+        assert_eq!(rl.limit(), 10, "Never grows above the maximum");
+    }
+
+    #[test]
+    fn it_treats_slow_successes_as_failures() {
+        let (clock, _mock) = Clock::mock();
+        let rl = gcra(clock).with_latency_threshold(Duration::from_millis(100));
+
+        rl.record(Outcome::Success, Duration::from_millis(100));
+        assert_eq!(rl.limit(), 8);
+
+        rl.record(Outcome::Success, Duration::from_millis(101));
+        assert_eq!(rl.limit(), 4);
+    }
+
+    #[test]
+    fn it_keeps_usage_when_adapting() {
+        let (clock, _mock) = Clock::mock();
+        let limit = NonZeroUsize::new(10).unwrap();
+        let period = Duration::from_secs(1);
+        let rl = Aimd::new(
+            TokenBucket::with_clock(limit, limit, period, clock),
+            Quota::new(limit.get(), period).unwrap(),
+        );
+
+        assert!(rl.process_n(NonZeroUsize::new(10).unwrap()).is_continue());
+        rl.record(Outcome::Failure, Duration::ZERO);
+
+        // The rate halves, but the bucket is not refilled
+        assert!(rl.process().is_break());
+        assert_eq!(rl.state().retry_after, Duration::from_millis(200));
+    }
+
+    #[test]
+    fn it_leaves_the_inner_rate_at_the_limit_after_racing_changes() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let (clock, _mock) = Clock::mock();
+        let rl = Arc::new(gcra(clock));
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        let outcome = if (thread + i) % 3 == 0 {
+                            Outcome::Failure
+                        } else {
+                            Outcome::Success
+                        };
+                        rl.record(outcome, Duration::ZERO);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(rl.state().limit, rl.limit());
+    }
+
+    #[test]
+    fn it_refuses_bounds_which_cant_be_spread_across_the_period() {
+        let (clock, _mock) = Clock::mock();
+        let limit = NonZeroUsize::new(8).unwrap();
+        let period = Duration::from_nanos(8);
+        let quota = Quota::new(limit.get(), period).unwrap();
+        let rl = Aimd::new(Gcra::with_clock(limit, period, clock), quota);
+
+        assert!(matches!(
+            rl.with_bounds(limit, NonZeroUsize::new(9).unwrap()),
+            Err(QuotaError::PeriodTooShort { limit: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn it_refuses_inverted_bounds() {
+        let (clock, _mock) = Clock::mock();
+        let rl = gcra(clock);
+
+        assert!(matches!(
+            rl.with_bounds(NonZeroUsize::new(5).unwrap(), NonZeroUsize::new(4).unwrap()),
+            Err(QuotaError::InvertedBounds { min: 5, max: 4 })
+        ));
+    }
+}
@@ -0,0 +1,77 @@
+/// How a strategy waits when its Compare-And-Swap loop keeps losing races with
+/// other threads.
+///
+/// The first `spins` retries happen straight away, since the race is usually won
+/// on the next attempt. After that, each retry first calls `wait`. With the `std`
+/// feature the default waits with `std::thread::yield_now` after 10 spins, giving
+/// the scheduler a chance to run the threads being raced with. Without it, the
+/// default only hints the CPU with `core::hint::spin_loop`.
+///
+/// ```rust
+/// use shot_limit::Backoff;
+/// use shot_limit::Gcra;
+/// use std::num::NonZeroUsize;
+/// use std::time::Duration;
+///
+/// let limit = NonZeroUsize::new(100).unwrap();
+/// let gcra = Gcra::new(limit, Duration::from_secs(1)).with_backoff(Backoff::spin());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    spins: u32,
+    wait: fn(),
+}
+
+impl Backoff {
+    /// Retries `spins` times straight away, then calls `wait` before each retry.
+    pub const fn new(spins: u32, wait: fn()) -> Self {
+        Self { spins, wait }
+    }
+
+    /// Hints the CPU before every retry, and never gives up the thread.
+    pub const fn spin() -> Self {
+        Self::new(0, core::hint::spin_loop)
+    }
+
+    /// Waits, if need be, before retrying after `failures` lost races in a row.
+    #[inline]
+    pub(crate) fn snooze(&self, failures: u32) {
+        if failures > self.spins {
+            (self.wait)();
+        }
+    }
+}
+
+impl Default for Backoff {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        Self::new(10, std::thread::yield_now)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        Self::spin()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    static WAITS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn it_waits_once_the_spins_are_used_up() {
+        let backoff = Backoff::new(2, || {
+            WAITS.fetch_add(1, Ordering::Relaxed);
+        });
+
+        for failures in 0..5 {
+            backoff.snooze(failures);
+        }
+        assert_eq!(WAITS.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,591 @@
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+
+#[cfg(feature = "tz")]
+use jiff::Timestamp;
+#[cfg(feature = "tz")]
+use jiff::tz::Offset;
+#[cfg(feature = "tz")]
+use jiff::tz::TimeZone;
+use quanta::Clock;
+
+use crate::Backoff;
+use crate::DefaultClock;
+use crate::Persist;
+use crate::Reason;
+use crate::Snapshot;
+use crate::SnapshotError;
+use crate::State;
+use crate::Strategy;
+use crate::TimeSource;
+use crate::snapshot::mismatch;
+use crate::snapshot::ns_until;
+use crate::snapshot::wall_after;
+use crate::sync::AtomicU128;
+
+const MINUTE: i64 = 60;
+const HOUR: i64 = 60 * MINUTE;
+const DAY: i64 = 24 * HOUR;
+
+/// A calendar period, whose boundaries fall at fixed wall-clock times.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Calendar {
+    /// Resets at the start of every minute.
+    Minute,
+    /// Resets on the hour.
+    Hour,
+    /// Resets at midnight.
+    Day,
+    /// Resets at midnight on the first of every month.
+    Month,
+}
+
+impl Calendar {
+    /// The first boundary after `secs`, both in seconds since the Unix epoch.
+    fn next_boundary(self, secs: i64) -> i64 {
+        match self {
+            Self::Minute => (secs.div_euclid(MINUTE) + 1) * MINUTE,
+            Self::Hour => (secs.div_euclid(HOUR) + 1) * HOUR,
+            Self::Day => (secs.div_euclid(DAY) + 1) * DAY,
+            Self::Month => {
+                let (year, month) = civil_from_days(secs.div_euclid(DAY));
+                let (year, month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                days_from_civil(year, month) * DAY
+            }
+        }
+    }
+}
+
+/// Where the local times at which boundaries fall are kept.
+#[derive(Clone, Debug)]
+enum Zone {
+    /// A fixed number of seconds east of UTC.
+    Offset(i32),
+    /// A time zone, whose offset from UTC may change during the year.
+    #[cfg(feature = "tz")]
+    Tz(TimeZone),
+}
+
+impl Zone {
+    /// The first boundary of `calendar` after `secs`, both in seconds since the Unix
+    /// epoch.
+    fn next_boundary(&self, calendar: Calendar, secs: i64) -> i64 {
+        match self {
+            Self::Offset(offset) => {
+                // Find the boundary in local time, then convert it back to UTC
+                let offset = i64::from(*offset);
+                calendar.next_boundary(secs + offset) - offset
+            }
+            #[cfg(feature = "tz")]
+            Self::Tz(zone) => {
+                let Ok(at) = Timestamp::from_second(secs) else {
+                    return calendar.next_boundary(secs);
+                };
+                let offset = i64::from(zone.to_offset(at).seconds());
+                let local = calendar.next_boundary(secs + offset);
+                match calendar {
+                    // Offsets change on the hour, so the next minute or hour is as far
+                    // away as at the current offset, even when the clocks change then
+                    Calendar::Minute | Calendar::Hour => local - offset,
+                    // A day or month may start at a different offset, or, if the
+                    // clocks skip midnight, as soon as they are past it
+                    Calendar::Day | Calendar::Month => Timestamp::from_second(local)
+                        .map(|local| Offset::UTC.to_datetime(local))
+                        .and_then(|local| zone.to_ambiguous_timestamp(local).compatible())
+                        .map_or(local - offset, |boundary| boundary.as_second()),
+                }
+            }
+        }
+    }
+}
+
+/// The year and month (1-12) of the day `days` after 1970-01-01.
+///
+/// This is Howard Hinnant's `civil_from_days`, for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month as u32)
+}
+
+/// The number of days from 1970-01-01 to the first of `month` (1-12) in `year`.
+///
+/// This is Howard Hinnant's `days_from_civil`, for the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// A quota which resets at calendar boundaries, such as 1,000 requests per day
+/// resetting at midnight UTC, or per month resetting on the first.
+///
+/// Unlike [`FixedWindow`](crate::FixedWindow), whose windows start whenever it was
+/// created, periods are aligned to the wall clock. Boundaries fall at UTC times by
+/// default, or at the local times of a fixed offset from UTC given to
+/// [`CalendarQuota::with_utc_offset`]. With the `tz` feature,
+/// `CalendarQuota::with_time_zone` aligns them to a time zone instead, following its
+/// daylight saving time, so a day may last 23 or 25 hours.
+///
+/// Rejections are [`Reason::QuotaExhausted`], whose `retry_after` is the time until the
+/// next boundary. The wall-clock time is read whenever a period ends, to find the end
+/// of the next one, and the period is then timed with the [`TimeSource`]. Corrections
+/// to the system clock are seen from the next period on.
+///
+/// The usage and the end of the current period are packed into a single 128-bit word,
+/// so each request is decided by a single atomic update.
+#[derive(Debug)]
+pub struct CalendarQuota<C: TimeSource = DefaultClock> {
+    limit: AtomicUsize,
+    calendar: Calendar,
+    /// The local time at which boundaries fall.
+    zone: Zone,
+    /// The current [`Period`], packed.
+    period: AtomicU128,
+    clock: C,
+    anchor: C::Instant,
+    /// The wall-clock time at `anchor`, if it was fixed rather than read from the
+    /// system clock.
+    wall_anchor: Option<SystemTime>,
+    backoff: Backoff,
+}
+
+/// The usage of the current period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Period {
+    /// Timestamp (nanos from anchor) for the end of the period
+    end: u64,
+    /// Requests admitted in the period
+    used: u64,
+}
+
+impl Period {
+    #[inline]
+    fn pack(self) -> u128 {
+        ((self.end as u128) << 64) | self.used as u128
+    }
+
+    #[inline]
+    fn unpack(word: u128) -> Self {
+        Self {
+            end: (word >> 64) as u64,
+            used: word as u64,
+        }
+    }
+}
+
+impl CalendarQuota {
+    /// Creates a new `CalendarQuota` which admits `limit` requests in each `calendar`
+    /// period, aligned to UTC.
+    pub fn new(limit: NonZeroUsize, calendar: Calendar) -> Self {
+        Self::with_clock(limit, calendar, Clock::new())
+    }
+}
+
+impl<C: TimeSource> CalendarQuota<C> {
+    const POLICY: &'static str = "calendar_quota";
+
+    /// Creates a new `CalendarQuota` which follows the current wall-clock time with the
+    /// supplied [`TimeSource`].
+    pub fn with_clock(limit: NonZeroUsize, calendar: Calendar, clock: C) -> Self {
+        let anchor = clock.now();
+
+        Self {
+            limit: AtomicUsize::new(limit.get()),
+            calendar,
+            zone: Zone::Offset(0),
+            // The first request finds the period ended, and starts the current one
+            period: AtomicU128::new(0),
+            clock,
+            anchor,
+            wall_anchor: None,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Aligns boundaries to the local time `seconds` east of UTC, e.g. `-5 * 3600` for
+    /// midnight in New York during winter. The offset never changes; to follow a time
+    /// zone's daylight saving time, use `with_time_zone` with the `tz` feature.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the offset is a day or more.
+    pub fn with_utc_offset(mut self, seconds: i32) -> Self {
+        assert!(
+            i64::from(seconds).abs() < DAY,
+            "UTC offset must be less than a day"
+        );
+        self.zone = Zone::Offset(seconds);
+        self
+    }
+
+    /// Aligns boundaries to the local time of `zone`, following its changes of offset,
+    /// e.g. midnight in New York all year round.
+    #[cfg(feature = "tz")]
+    pub fn with_time_zone(mut self, zone: TimeZone) -> Self {
+        self.zone = Zone::Tz(zone);
+        self
+    }
+
+    /// Treats the current time of the [`TimeSource`] as the wall-clock time `now`, and
+    /// follows the wall clock with the `TimeSource` from then on, rather than reading
+    /// the system clock.
+    ///
+    /// Use this with `Clock::mock()` to test behaviour at boundaries.
+    pub fn with_wall_time(mut self, now: SystemTime) -> Self {
+        let elapsed = Duration::from_nanos(self.clock.elapsed_ns(self.anchor));
+        self.wall_anchor = Some(now.checked_sub(elapsed).unwrap_or(now));
+        self.period = AtomicU128::new(0);
+        self
+    }
+
+    /// Sets how the strategy waits when it keeps losing races with other threads.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Changes the number of requests allowed per period.
+    ///
+    /// Requests already admitted in the current period keep counting against the new
+    /// limit, so lowering it below them rejects requests until the next boundary.
+    pub fn set_limit(&self, limit: NonZeroUsize) {
+        self.limit.store(limit.get(), Ordering::Release);
+    }
+
+    #[inline]
+    fn limit(&self) -> usize {
+        self.limit.load(Ordering::Acquire)
+    }
+
+    /// The period at `now`, which starts afresh once the stored one has ended.
+    #[inline]
+    fn period_at(&self, word: u128, now: u64) -> Period {
+        let period = Period::unpack(word);
+        if now < period.end {
+            return period;
+        }
+
+        Period {
+            end: self.end_after(now),
+            used: 0,
+        }
+    }
+
+    /// The end of the period containing `now`, both in nanos from the anchor.
+    fn end_after(&self, now: u64) -> u64 {
+        let wall = match self.wall_anchor {
+            Some(wall_anchor) => wall_anchor + Duration::from_nanos(now),
+            None => SystemTime::now(),
+        };
+        let secs = wall
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+
+        let boundary = self.zone.next_boundary(self.calendar, secs);
+        let boundary = SystemTime::UNIX_EPOCH + Duration::from_secs(boundary.max(0) as u64);
+
+        now.saturating_add(ns_until(wall, boundary))
+    }
+
+    /// The state of the quota at `now`, given the usage of `period`.
+    fn state_at(&self, now: u64, period: Period) -> State {
+        let limit = self.limit();
+        let remaining = limit.saturating_sub(period.used as usize);
+        let until_end = Duration::from_nanos(period.end.saturating_sub(now));
+
+        State {
+            limit,
+            remaining,
+            reset_after: if period.used > 0 {
+                until_end
+            } else {
+                Duration::ZERO
+            },
+            retry_after: if remaining > 0 {
+                Duration::ZERO
+            } else {
+                until_end
+            },
+        }
+    }
+}
+
+impl<C: TimeSource> Strategy for CalendarQuota<C> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let cost = cost.get();
+        let limit = self.limit();
+
+        // No period will ever admit more than the limit
+        if cost > limit {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost,
+                capacity: limit,
+                policy: Self::POLICY,
+            });
+        }
+
+        let now = self.clock.elapsed_ns(self.anchor);
+        let mut spins = 0;
+
+        loop {
+            self.backoff.snooze(spins);
+
+            let current = self.period.load(Ordering::Acquire);
+            let period = self.period_at(current, now);
+
+            if period.used as usize + cost > limit {
+                return ControlFlow::Break(Reason::QuotaExhausted {
+                    retry_after: Duration::from_nanos(period.end - now),
+                    limit,
+                    policy: Self::POLICY,
+                });
+            }
+
+            let next = Period {
+                used: period.used + cost as u64,
+                ..period
+            };
+            if self
+                .period
+                .compare_exchange_weak(current, next.pack(), Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                return ControlFlow::Continue(());
+            }
+            // If CAS fails, another thread updated the period; loop and recalculate.
+            spins += 1;
+        }
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        let _ = self
+            .period
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |word| {
+                let mut period = Period::unpack(word);
+                period.used = period.used.saturating_sub(cost.get() as u64);
+                Some(period.pack())
+            });
+    }
+
+    fn state(&self) -> State {
+        let now = self.clock.elapsed_ns(self.anchor);
+        let period = self.period_at(self.period.load(Ordering::Acquire), now);
+        self.state_at(now, period)
+    }
+}
+
+impl<C: TimeSource> Persist for CalendarQuota<C> {
+    fn snapshot_at(&self, now: SystemTime) -> Snapshot {
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let period = self.period_at(self.period.load(Ordering::Acquire), elapsed);
+
+        Snapshot::CalendarQuota {
+            used: period.used as usize,
+            resets_at: wall_after(now, period.end - elapsed),
+        }
+    }
+
+    fn restore_at(&self, snapshot: &Snapshot, now: SystemTime) -> Result<(), SnapshotError> {
+        let Snapshot::CalendarQuota { used, resets_at } = *snapshot else {
+            return Err(mismatch(Self::POLICY, snapshot));
+        };
+
+        let left = ns_until(now, resets_at);
+        if left == 0 {
+            // The period has ended, so nothing it admitted counts any more
+            return Ok(());
+        }
+
+        let period = Period {
+            end: self.clock.elapsed_ns(self.anchor).saturating_add(left),
+            used: used as u64,
+        };
+        self.period.store(period.pack(), Ordering::Release);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn quota(clock: Clock, limit: usize, calendar: Calendar, now: SystemTime) -> CalendarQuota {
+        CalendarQuota::with_clock(NonZeroUsize::new(limit).unwrap(), calendar, clock)
+            .with_wall_time(now)
+    }
+
+    #[test]
+    fn it_finds_calendar_boundaries() {
+        // 2024-03-10T23:59:00Z
+        let secs = 1_710_115_140;
+        assert_eq!(Calendar::Minute.next_boundary(secs), secs + 60);
+        assert_eq!(Calendar::Hour.next_boundary(secs), secs + 60);
+        assert_eq!(Calendar::Day.next_boundary(secs), secs + 60);
+        // 2024-04-01T00:00:00Z
+        assert_eq!(Calendar::Month.next_boundary(secs), 1_711_929_600);
+
+        // Through a leap day, and into a new year
+        assert_eq!(Calendar::Month.next_boundary(1_709_208_000), 1_709_251_200);
+        assert_eq!(Calendar::Month.next_boundary(1_704_063_600), 1_704_067_200);
+
+        for days in [-719_468, -1, 0, 59, 10_000, 19_782, 2_932_896] {
+            let (year, month) = civil_from_days(days);
+            assert!(days_from_civil(year, month) <= days);
+            assert!(days_from_civil(year, month) > days - 31);
+        }
+    }
+
+    #[test]
+    fn it_resets_at_utc_midnight() {
+        let (clock, mock) = Clock::mock();
+        // 2024-03-10T23:59:00Z
+        let rl = quota(clock, 2, Calendar::Day, at(1_710_115_140));
+
+        assert!(rl.process().is_continue());
+        assert!(rl.process().is_continue());
+        assert_eq!(
+            rl.process(),
+            ControlFlow::Break(Reason::QuotaExhausted {
+                retry_after: Duration::from_secs(60),
+                limit: 2,
+                policy: "calendar_quota",
+            })
+        );
+
+        let state = rl.state();
+        assert_eq!(state.remaining, 0);
+        assert_eq!(state.reset_after, Duration::from_secs(60));
+        assert_eq!(state.retry_after, Duration::from_secs(60));
+
+        mock.increment(Duration::from_secs(59));
+        assert!(rl.process().is_break());
+        mock.increment(Duration::from_secs(1));
+        assert!(rl.process().is_continue());
+        assert_eq!(rl.state().reset_after, Duration::from_secs(24 * 60 * 60));
+    }
+
+    #[test]
+    fn it_resets_on_the_first_of_the_month() {
+        let (clock, mock) = Clock::mock();
+        // Noon on 2024-02-29, a leap day
+        let rl = quota(clock, 1, Calendar::Month, at(1_709_208_000));
+
+        assert!(rl.process().is_continue());
+        let reason = rl.process().break_value().unwrap();
+        assert_eq!(
+            reason.retry_after(),
+            Some(Duration::from_secs(12 * 60 * 60))
+        );
+
+        mock.increment(Duration::from_secs(12 * 60 * 60));
+        assert!(rl.process().is_continue());
+        assert_eq!(
+            rl.state().reset_after,
+            Duration::from_secs(31 * 24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn it_aligns_boundaries_to_a_utc_offset() {
+        let (clock, _mock) = Clock::mock();
+        // 2024-01-01T18:00:00Z is 23:30 in India, at UTC+05:30
+        let rl =
+            quota(clock, 1, Calendar::Day, at(1_704_132_000)).with_utc_offset(5 * 3600 + 30 * 60);
+
+        assert!(rl.process().is_continue());
+        let reason = rl.process().break_value().unwrap();
+        assert_eq!(reason.retry_after(), Some(Duration::from_secs(30 * 60)));
+    }
+
+    #[cfg(feature = "tz")]
+    #[test]
+    fn it_follows_daylight_saving_time_in_a_time_zone() {
+        let zone = TimeZone::get("America/New_York").unwrap();
+        let (clock, mock) = Clock::mock();
+        // 2024-03-10T04:00:00Z is 23:00 on the 9th in New York, before the clocks go
+        // forward at 02:00
+        let rl = quota(clock, 1, Calendar::Day, at(1_710_043_200)).with_time_zone(zone);
+
+        assert!(rl.process().is_continue());
+        let reason = rl.process().break_value().unwrap();
+        assert_eq!(reason.retry_after(), Some(Duration::from_secs(60 * 60)));
+
+        // The 10th is only 23 hours long
+        mock.increment(Duration::from_secs(60 * 60));
+        assert!(rl.process().is_continue());
+        assert_eq!(rl.state().reset_after, Duration::from_secs(23 * 60 * 60));
+    }
+
+    #[cfg(feature = "tz")]
+    #[test]
+    fn it_keeps_hours_an_hour_long_when_the_clocks_go_back() {
+        let zone = TimeZone::get("America/New_York").unwrap();
+        let (clock, mock) = Clock::mock();
+        // 2024-11-03T05:30:00Z is 01:30 EDT, half an hour before the clocks go back to
+        // 01:00 EST
+        let rl = quota(clock, 1, Calendar::Hour, at(1_730_611_800)).with_time_zone(zone);
+
+        assert!(rl.process().is_continue());
+        let reason = rl.process().break_value().unwrap();
+        assert_eq!(reason.retry_after(), Some(Duration::from_secs(30 * 60)));
+
+        mock.increment(Duration::from_secs(30 * 60));
+        assert!(rl.process().is_continue());
+        assert_eq!(rl.state().reset_after, Duration::from_secs(60 * 60));
+    }
+
+    #[test]
+    fn it_restores_snapshots_across_restarts() {
+        let now = at(1_710_115_140);
+        let (clock, _mock) = Clock::mock();
+        let rl = quota(clock, 10, Calendar::Day, now);
+        assert!(rl.process_n(NonZeroUsize::new(7).unwrap()).is_continue());
+        let snapshot = rl.snapshot_at(now);
+        assert_eq!(
+            snapshot,
+            Snapshot::CalendarQuota {
+                used: 7,
+                resets_at: at(1_710_115_200),
+            }
+        );
+
+        // Restarted before midnight, the usage still counts
+        let later = now + Duration::from_secs(30);
+        let (clock, _mock) = Clock::mock();
+        let restored = quota(clock, 10, Calendar::Day, later);
+        restored.restore_at(&snapshot, later).unwrap();
+        assert_eq!(restored.state().remaining, 3);
+        assert_eq!(restored.state().reset_after, Duration::from_secs(30));
+
+        // After midnight, it has been forgotten
+        let tomorrow = now + Duration::from_secs(60);
+        let (clock, _mock) = Clock::mock();
+        let restored = quota(clock, 10, Calendar::Day, tomorrow);
+        restored.restore_at(&snapshot, tomorrow).unwrap();
+        assert_eq!(restored.state().remaining, 10);
+    }
+}
@@ -0,0 +1,190 @@
+use core::fmt::Debug;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+/// A source of monotonic time for strategies.
+///
+/// Strategies only ever need to know how much time has passed since a fixed
+/// `anchor` taken at construction, so implementations are free to pick any
+/// representation for their instants.
+///
+/// `quanta::Clock` is the default, offering very cheap TSC-based timestamps (and
+/// `Clock::mock()` for tests). With the `tokio` feature, [`TokioClock`] follows
+/// tokio's (possibly paused) virtual time instead. Without the `std` feature,
+/// implement this for the target's monotonic timer, or use a [`ManualClock`].
+pub trait TimeSource: Debug + Send + Sync {
+    /// A point in time as measured by this source.
+    type Instant: Copy + Debug + Send + Sync;
+
+    /// Returns the current time.
+    fn now(&self) -> Self::Instant;
+
+    /// Returns the nanoseconds elapsed since `earlier`, saturating at zero.
+    fn elapsed_ns(&self, earlier: Self::Instant) -> u64;
+}
+
+/// The [`TimeSource`] strategies use when none is named.
+///
+/// This is `quanta::Clock`, or without the `std` feature, a reference to a
+/// [`ManualClock`].
+#[cfg(feature = "std")]
+pub type DefaultClock = quanta::Clock;
+
+/// The [`TimeSource`] strategies use when none is named.
+///
+/// This is `quanta::Clock`, or without the `std` feature, a reference to a
+/// [`ManualClock`].
+#[cfg(not(feature = "std"))]
+pub type DefaultClock = &'static ManualClock;
+
+impl<T: TimeSource + ?Sized> TimeSource for &T {
+    type Instant = T::Instant;
+
+    #[inline]
+    fn now(&self) -> Self::Instant {
+        T::now(self)
+    }
+
+    #[inline]
+    fn elapsed_ns(&self, earlier: Self::Instant) -> u64 {
+        T::elapsed_ns(self, earlier)
+    }
+}
+
+#[cfg(feature = "std")]
+impl TimeSource for quanta::Clock {
+    type Instant = quanta::Instant;
+
+    #[inline]
+    fn now(&self) -> Self::Instant {
+        quanta::Clock::now(self)
+    }
+
+    #[inline]
+    fn elapsed_ns(&self, earlier: Self::Instant) -> u64 {
+        quanta::Clock::now(self).duration_since(earlier).as_nanos() as u64
+    }
+}
+
+/// A [`TimeSource`] which only moves when it is advanced.
+///
+/// This suits targets without an operating system clock: keep it in a `static`,
+/// advance it from a timer interrupt, and pass strategies a reference to it.
+///
+/// ```rust
+/// use shot_limit::ManualClock;
+/// use shot_limit::Quota;
+/// use shot_limit::Strategy;
+/// use shot_limit::TokenBucket;
+/// use std::time::Duration;
+///
+/// static CLOCK: ManualClock = ManualClock::new();
+///
+/// let bucket = TokenBucket::from_quota_with_clock(Quota::per_second(1)?, &CLOCK);
+/// assert!(bucket.process().is_continue());
+/// assert!(bucket.process().is_break());
+///
+/// CLOCK.advance(Duration::from_secs(1));
+/// assert!(bucket.process().is_continue());
+/// # Ok::<(), shot_limit::QuotaError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now_ns: AtomicU64,
+}
+
+impl ManualClock {
+    /// Creates a clock which reads zero until advanced.
+    pub const fn new() -> Self {
+        Self {
+            now_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the clock forwards by `by`.
+    pub fn advance(&self, by: Duration) {
+        let by_ns = u64::try_from(by.as_nanos()).unwrap_or(u64::MAX);
+        let _ = self
+            .now_ns
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |now| {
+                Some(now.saturating_add(by_ns))
+            });
+    }
+}
+
+impl TimeSource for ManualClock {
+    type Instant = u64;
+
+    #[inline]
+    fn now(&self) -> Self::Instant {
+        self.now_ns.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    fn elapsed_ns(&self, earlier: Self::Instant) -> u64 {
+        self.now().saturating_sub(earlier)
+    }
+}
+
+/// A [`TimeSource`] which reads `tokio::time::Instant`.
+///
+/// When the tokio runtime is paused (`tokio::time::pause()`), time only moves when
+/// the runtime advances it. Strategies using this clock then see exactly the same
+/// time as the sleeps in `tower-shot`'s `RateLimitService`, so tests are
+/// deterministic.
+///
+/// `tokio::time::Instant::now()` is slower than `quanta`, so prefer the default
+/// clock in production unless virtual time is needed.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl TimeSource for TokioClock {
+    type Instant = tokio::time::Instant;
+
+    #[inline]
+    fn now(&self) -> Self::Instant {
+        tokio::time::Instant::now()
+    }
+
+    #[inline]
+    fn elapsed_ns(&self, earlier: Self::Instant) -> u64 {
+        tokio::time::Instant::now()
+            .saturating_duration_since(earlier)
+            .as_nanos() as u64
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::num::NonZeroUsize;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Strategy;
+    use crate::TokenBucket;
+
+    #[tokio::test]
+    async fn test_tokio_clock_follows_paused_time() {
+        tokio::time::pause();
+
+        let rl = TokenBucket::with_clock(
+            NonZeroUsize::new(1).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_millis(100),
+            TokioClock,
+        );
+
+        assert!(rl.process().is_continue());
+        assert!(rl.process().is_break());
+
+        // No matter how long the test really takes, the bucket only sees virtual time
+        std::thread::sleep(Duration::from_millis(120));
+        assert!(rl.process().is_break());
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(rl.process().is_continue());
+    }
+}
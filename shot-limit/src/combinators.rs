@@ -0,0 +1,704 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+
+/// Gives a strategy a name which identifies it in rejections.
+///
+/// Combined policies often use several strategies of the same kind, e.g. a per-second
+/// and a per-hour `TokenBucket`. Naming them makes [`Reason::policy`] say which one
+/// was hit.
+#[derive(Debug)]
+pub struct Named<S> {
+    name: &'static str,
+    inner: S,
+}
+
+impl<S: Strategy> Named<S> {
+    /// Wraps `inner`, reporting `name` as the policy of its rejections.
+    pub fn new(name: &'static str, inner: S) -> Self {
+        Self { name, inner }
+    }
+
+    /// The name reported in rejections.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<S: Strategy> Strategy for Named<S> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        self.inner
+            .process_n(cost)
+            .map_break(|reason| reason.with_policy(self.name))
+    }
+
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        self.inner
+            .reserve_n(cost, max_delay)
+            .map_break(|reason| reason.with_policy(self.name))
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        self.inner.release_n(cost);
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.inner.refund_n(cost);
+    }
+
+    fn state(&self) -> State {
+        self.inner.state()
+    }
+}
+
+/// Admits a request only if every child strategy admits it.
+///
+/// This expresses layered policies such as 10/s AND 1,000/hour AND 50,000/day.
+/// Children are consulted in order. If one rejects, the children which had already
+/// admitted the request are refunded, so a rejection never leaks capacity, and the
+/// rejecting child's [`Reason`] is returned. Wrap children in [`Named`] to tell which
+/// limit was hit.
+///
+/// Put the child most likely to reject first, to avoid needless rollbacks, and any
+/// child which cannot be refunded (such as [`SlidingLog`](crate::SlidingLog)) last.
+#[derive(Debug)]
+pub struct AllOf {
+    children: Vec<Arc<dyn Strategy>>,
+}
+
+impl AllOf {
+    /// Creates a new `AllOf` over `children`, which are consulted in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `children` is empty.
+    pub fn new(children: impl IntoIterator<Item = Arc<dyn Strategy>>) -> Self {
+        let children: Vec<_> = children.into_iter().collect();
+        assert!(!children.is_empty(), "AllOf needs at least one child");
+        Self { children }
+    }
+
+    /// Refunds the first `admitted` children, most recent first.
+    fn roll_back(&self, admitted: usize, cost: NonZeroUsize) {
+        for child in self.children[..admitted].iter().rev() {
+            child.refund_n(cost);
+        }
+    }
+}
+
+impl Strategy for AllOf {
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        for (index, child) in self.children.iter().enumerate() {
+            if let ControlFlow::Break(reason) = child.process_n(cost) {
+                self.roll_back(index, cost);
+                return ControlFlow::Break(reason);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        // The request can only go once every child is ready for it
+        let mut delay = Duration::ZERO;
+        for (index, child) in self.children.iter().enumerate() {
+            match child.reserve_n(cost, max_delay) {
+                ControlFlow::Continue(child_delay) => delay = delay.max(child_delay),
+                ControlFlow::Break(reason) => {
+                    self.roll_back(index, cost);
+                    return ControlFlow::Break(reason);
+                }
+            }
+        }
+        ControlFlow::Continue(delay)
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        for child in &self.children {
+            child.release_n(cost);
+        }
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.roll_back(self.children.len(), cost);
+    }
+
+    fn state(&self) -> State {
+        // The most constrained child decides what is left, and every child must have
+        // recovered before the combination has.
+        self.children
+            .iter()
+            .map(|child| child.state())
+            .reduce(|a, b| {
+                let tightest = if b.remaining < a.remaining { b } else { a };
+                State {
+                    limit: tightest.limit,
+                    remaining: tightest.remaining,
+                    reset_after: a.reset_after.max(b.reset_after),
+                    retry_after: a.retry_after.max(b.retry_after),
+                }
+            })
+            .expect("AllOf has at least one child")
+    }
+}
+
+/// Admits a request if any child strategy admits it, charging every child which does.
+///
+/// Unlike [`FirstOf`], every child is asked, so each keeps count of all the traffic it
+/// could have admitted. If all of them reject, the rejection with the soonest
+/// `retry_after` is returned. When reserving, every child which can take the request
+/// within the allowed delay is charged, and the request goes as soon as the first of
+/// them is ready for it.
+///
+/// `AnyOf` counts what each child has been charged and not yet given back, and
+/// forwards [`Strategy::release_n`] and [`Strategy::refund_n`] to every child with
+/// charges outstanding, up to what it is owed. Requests are not told apart, so a
+/// concurrency child can get a slot back for a request it did not admit, while
+/// another it did admit is still in flight. Each child still gets back exactly what
+/// it was charged once every request has ended.
+#[derive(Debug)]
+pub struct AnyOf {
+    children: Vec<Arc<dyn Strategy>>,
+    charges: Charges,
+}
+
+impl AnyOf {
+    /// Creates a new `AnyOf` over `children`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `children` is empty.
+    pub fn new(children: impl IntoIterator<Item = Arc<dyn Strategy>>) -> Self {
+        let children: Vec<_> = children.into_iter().collect();
+        assert!(!children.is_empty(), "AnyOf needs at least one child");
+        Self {
+            charges: Charges::new(children.len()),
+            children,
+        }
+    }
+
+    /// Gives every child back what it is owed of `cost`, via `give_back`.
+    fn give_back(&self, cost: NonZeroUsize, give_back: impl Fn(&dyn Strategy, NonZeroUsize)) {
+        for (index, child) in self.children.iter().enumerate() {
+            if let Some(owed) = self.charges.take(index, cost.get()) {
+                give_back(child.as_ref(), owed);
+            }
+        }
+    }
+}
+
+impl Strategy for AnyOf {
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let mut admitted = false;
+        let mut rejection = None;
+        for (index, child) in self.children.iter().enumerate() {
+            match child.process_n(cost) {
+                ControlFlow::Continue(()) => {
+                    self.charges.charge(index, cost);
+                    admitted = true;
+                }
+                ControlFlow::Break(reason) => rejection = soonest(rejection, reason),
+            }
+        }
+
+        if admitted {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(rejection.expect("AnyOf has at least one child"))
+        }
+    }
+
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        let mut delay: Option<Duration> = None;
+        let mut rejection = None;
+        for (index, child) in self.children.iter().enumerate() {
+            match child.reserve_n(cost, max_delay) {
+                ControlFlow::Continue(child_delay) => {
+                    self.charges.charge(index, cost);
+                    delay = Some(delay.map_or(child_delay, |delay| delay.min(child_delay)));
+                }
+                ControlFlow::Break(reason) => rejection = soonest(rejection, reason),
+            }
+        }
+
+        match delay {
+            Some(delay) => ControlFlow::Continue(delay),
+            None => ControlFlow::Break(rejection.expect("AnyOf has at least one child")),
+        }
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        self.give_back(cost, |child, owed| child.release_n(owed));
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.give_back(cost, |child, owed| child.refund_n(owed));
+    }
+
+    fn state(&self) -> State {
+        // A request only needs one child to admit it
+        loosest(self.children.iter().map(|child| child.state()))
+    }
+}
+
+/// Admits a request with the first child strategy which has capacity for it.
+///
+/// Children are tried in order and only the one which admits the request is charged,
+/// e.g. a reserved allowance with an overflow pool behind it. If all of them reject,
+/// the rejection with the soonest `retry_after` is returned. When reserving, the first
+/// child which can take the request within the allowed delay is charged, and its delay
+/// is returned.
+///
+/// `FirstOf` counts what each child has been charged and not yet given back, and
+/// forwards [`Strategy::release_n`] and [`Strategy::refund_n`] to the children with
+/// charges outstanding, in order, until `cost` has been given back. Requests are not
+/// told apart, so a slot can go back to a different child than the one which admitted
+/// the request, but the total in flight across concurrency children stays exact.
+#[derive(Debug)]
+pub struct FirstOf {
+    children: Vec<Arc<dyn Strategy>>,
+    charges: Charges,
+}
+
+impl FirstOf {
+    /// Creates a new `FirstOf` over `children`, which are tried in order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `children` is empty.
+    pub fn new(children: impl IntoIterator<Item = Arc<dyn Strategy>>) -> Self {
+        let children: Vec<_> = children.into_iter().collect();
+        assert!(!children.is_empty(), "FirstOf needs at least one child");
+        Self {
+            charges: Charges::new(children.len()),
+            children,
+        }
+    }
+
+    /// Gives `cost` back to the children which are owed it, via `give_back`.
+    fn give_back(&self, cost: NonZeroUsize, give_back: impl Fn(&dyn Strategy, NonZeroUsize)) {
+        let mut left = cost.get();
+        for (index, child) in self.children.iter().enumerate() {
+            if let Some(owed) = self.charges.take(index, left) {
+                give_back(child.as_ref(), owed);
+                left -= owed.get();
+                if left == 0 {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Strategy for FirstOf {
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let mut rejection = None;
+        for (index, child) in self.children.iter().enumerate() {
+            match child.process_n(cost) {
+                ControlFlow::Continue(()) => {
+                    self.charges.charge(index, cost);
+                    return ControlFlow::Continue(());
+                }
+                ControlFlow::Break(reason) => rejection = soonest(rejection, reason),
+            }
+        }
+
+        ControlFlow::Break(rejection.expect("FirstOf has at least one child"))
+    }
+
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        let mut rejection = None;
+        for (index, child) in self.children.iter().enumerate() {
+            match child.reserve_n(cost, max_delay) {
+                ControlFlow::Continue(delay) => {
+                    self.charges.charge(index, cost);
+                    return ControlFlow::Continue(delay);
+                }
+                ControlFlow::Break(reason) => rejection = soonest(rejection, reason),
+            }
+        }
+
+        ControlFlow::Break(rejection.expect("FirstOf has at least one child"))
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        self.give_back(cost, |child, owed| child.release_n(owed));
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.give_back(cost, |child, owed| child.refund_n(owed));
+    }
+
+    fn state(&self) -> State {
+        // Capacity left in any child can be used
+        self.children
+            .iter()
+            .map(|child| child.state())
+            .reduce(|a, b| State {
+                limit: a.limit.saturating_add(b.limit),
+                remaining: a.remaining.saturating_add(b.remaining),
+                reset_after: a.reset_after.max(b.reset_after),
+                retry_after: a.retry_after.min(b.retry_after),
+            })
+            .expect("FirstOf has at least one child")
+    }
+}
+
+/// What each child of a combinator has been charged and not yet given back.
+#[derive(Debug)]
+struct Charges(Vec<AtomicUsize>);
+
+impl Charges {
+    fn new(children: usize) -> Self {
+        Self((0..children).map(|_| AtomicUsize::new(0)).collect())
+    }
+
+    fn charge(&self, child: usize, cost: NonZeroUsize) {
+        let _ = self.0[child].fetch_update(Ordering::AcqRel, Ordering::Acquire, |charged| {
+            Some(charged.saturating_add(cost.get()))
+        });
+    }
+
+    /// Takes up to `cost` of what `child` is owed, or `None` if it is owed nothing.
+    fn take(&self, child: usize, cost: usize) -> Option<NonZeroUsize> {
+        let charged = self.0[child]
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |charged| {
+                Some(charged.saturating_sub(cost))
+            })
+            .unwrap_or_else(|charged| charged);
+        NonZeroUsize::new(charged.min(cost))
+    }
+}
+
+/// Keeps whichever rejection can be retried soonest. Rejections which can't be
+/// retried lose to any which can.
+fn soonest(current: Option<Reason>, candidate: Reason) -> Option<Reason> {
+    match (&current, candidate.retry_after()) {
+        (None, _) => Some(candidate),
+        (Some(best), Some(retry_after))
+            if best.retry_after().is_none_or(|best| retry_after < best) =>
+        {
+            Some(candidate)
+        }
+        _ => current,
+    }
+}
+
+/// The state of the child with the most capacity remaining.
+fn loosest(states: impl Iterator<Item = State>) -> State {
+    states
+        .reduce(|a, b| {
+            let loosest = if b.remaining > a.remaining { b } else { a };
+            State {
+                retry_after: a.retry_after.min(b.retry_after),
+                ..loosest
+            }
+        })
+        .expect("AnyOf has at least one child")
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use quanta::Clock;
+
+    use super::*;
+    use crate::ConcurrencyLimit;
+    use crate::FixedWindow;
+    use crate::LeakyBucket;
+    use crate::Permit;
+    use crate::TokenBucket;
+
+    fn n(value: usize) -> NonZeroUsize {
+        NonZeroUsize::new(value).unwrap()
+    }
+
+    #[test]
+    fn all_of_rolls_back_and_reports_the_limit_hit() {
+        let (clock, _mock) = Clock::mock();
+        let per_hour = Arc::new(Named::new(
+            "per_hour",
+            TokenBucket::with_clock(n(5), n(5), Duration::from_secs(3600), clock.clone()),
+        ));
+        let per_second = Arc::new(Named::new(
+            "per_second",
+            FixedWindow::with_clock(n(2), Duration::from_secs(1), clock),
+        ));
+        let limit = AllOf::new([per_hour.clone() as Arc<dyn Strategy>, per_second.clone()]);
+
+        assert!(limit.process().is_continue());
+        assert!(limit.process().is_continue());
+
+        // The hourly bucket admits the third request, but the per-second window doesn't
+        match limit.process() {
+            ControlFlow::Break(Reason::Overloaded { policy, limit, .. }) => {
+                assert_eq!(policy, "per_second");
+                assert_eq!(limit, 2);
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+        // ...so the hourly bucket gets its token back
+        assert_eq!(per_hour.state().remaining, 3);
+        assert_eq!(limit.state().remaining, 0);
+        assert_eq!(limit.state().limit, 2);
+    }
+
+    #[test]
+    fn all_of_waits_for_the_slowest_child() {
+        let (clock, _mock) = Clock::mock();
+        let shaper = Arc::new(LeakyBucket::with_clock(
+            n(10),
+            Duration::from_secs(1),
+            n(2),
+            clock.clone(),
+        ));
+        let bucket = Arc::new(TokenBucket::with_clock(
+            n(3),
+            n(1),
+            Duration::from_secs(1),
+            clock,
+        ));
+        let limit = AllOf::new([bucket.clone() as Arc<dyn Strategy>, shaper]);
+        let max_delay = Duration::from_secs(1);
+
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::ZERO)
+        );
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::from_millis(100))
+        );
+
+        // The bucket admits a third request, but the shaper's queue is full
+        assert_eq!(bucket.state().remaining, 1);
+        assert!(matches!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Break(Reason::Overloaded {
+                policy: "leaky_bucket",
+                ..
+            })
+        ));
+        assert_eq!(bucket.state().remaining, 1);
+    }
+
+    #[test]
+    fn all_of_bounds_rate_and_concurrency_together() {
+        let concurrency = Arc::new(ConcurrencyLimit::new(n(1)));
+        let rate = Arc::new(FixedWindow::new(n(10), Duration::from_secs(60)));
+        let limit = AllOf::new([concurrency.clone() as Arc<dyn Strategy>, rate.clone()]);
+
+        let permit = Permit::acquire(&limit).continue_value().unwrap();
+        assert!(matches!(
+            limit.process(),
+            ControlFlow::Break(Reason::Overloaded {
+                policy: "concurrency",
+                ..
+            })
+        ));
+
+        drop(permit);
+        assert_eq!(concurrency.in_flight(), 0);
+        assert_eq!(rate.state().remaining, 9);
+
+        // A rate rejection gives back the concurrency slot
+        let exhausted = FixedWindow::new(n(1), Duration::from_secs(60));
+        assert!(exhausted.process().is_continue());
+        let limit = AllOf::new([
+            concurrency.clone() as Arc<dyn Strategy>,
+            Arc::new(exhausted),
+        ]);
+        assert!(limit.process().is_break());
+        assert_eq!(concurrency.in_flight(), 0);
+    }
+
+    #[test]
+    fn any_of_charges_every_child_which_admits() {
+        let (clock, _mock) = Clock::mock();
+        let small = Arc::new(FixedWindow::with_clock(
+            n(1),
+            Duration::from_secs(10),
+            clock.clone(),
+        ));
+        let large = Arc::new(FixedWindow::with_clock(
+            n(2),
+            Duration::from_secs(60),
+            clock,
+        ));
+        let limit = AnyOf::new([small.clone() as Arc<dyn Strategy>, large.clone()]);
+
+        assert!(limit.process().is_continue());
+        assert_eq!(small.state().remaining, 0);
+        assert_eq!(large.state().remaining, 1);
+
+        // Only the large window has room, which is enough
+        assert!(limit.process().is_continue());
+        assert_eq!(large.state().remaining, 0);
+
+        // Both are full; the small window resets first
+        match limit.process() {
+            ControlFlow::Break(Reason::Overloaded { retry_after, .. }) => {
+                assert_eq!(retry_after, Duration::from_secs(10));
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn first_of_charges_only_the_child_which_admits() {
+        let (clock, _mock) = Clock::mock();
+        let reserved = Arc::new(FixedWindow::with_clock(
+            n(1),
+            Duration::from_secs(60),
+            clock.clone(),
+        ));
+        let overflow = Arc::new(FixedWindow::with_clock(
+            n(1),
+            Duration::from_secs(10),
+            clock,
+        ));
+        let limit = FirstOf::new([reserved.clone() as Arc<dyn Strategy>, overflow.clone()]);
+
+        assert_eq!(limit.state().remaining, 2);
+        assert!(limit.process().is_continue());
+        assert_eq!(overflow.state().remaining, 1);
+
+        assert!(limit.process().is_continue());
+        assert_eq!(limit.state().remaining, 0);
+
+        match limit.process() {
+            ControlFlow::Break(Reason::Overloaded { retry_after, .. }) => {
+                assert_eq!(retry_after, Duration::from_secs(10));
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn first_of_gives_concurrency_slots_back() {
+        let reserved = Arc::new(ConcurrencyLimit::new(n(1)));
+        let overflow = Arc::new(ConcurrencyLimit::new(n(2)));
+        let limit = FirstOf::new([reserved.clone() as Arc<dyn Strategy>, overflow.clone()]);
+
+        let first = Permit::acquire(&limit).continue_value().unwrap();
+        let second = Permit::acquire_n(&limit, n(2)).continue_value().unwrap();
+        assert_eq!(reserved.in_flight(), 1);
+        assert_eq!(overflow.in_flight(), 2);
+        assert!(limit.process().is_break());
+
+        drop(first);
+        drop(second);
+        assert_eq!(reserved.in_flight(), 0);
+        assert_eq!(overflow.in_flight(), 0);
+    }
+
+    #[test]
+    fn any_of_gives_back_what_each_child_was_charged() {
+        let small = Arc::new(ConcurrencyLimit::new(n(1)));
+        let large = Arc::new(ConcurrencyLimit::new(n(2)));
+        let limit = AnyOf::new([small.clone() as Arc<dyn Strategy>, large.clone()]);
+
+        let both = Permit::acquire(&limit).continue_value().unwrap();
+        let large_only = Permit::acquire(&limit).continue_value().unwrap();
+        assert_eq!(small.in_flight(), 1);
+        assert_eq!(large.in_flight(), 2);
+
+        drop(large_only);
+        drop(both);
+        assert_eq!(small.in_flight(), 0);
+        assert_eq!(large.in_flight(), 0);
+    }
+
+    #[test]
+    fn first_of_reserves_with_the_first_child_which_can_wait() {
+        let (clock, _mock) = Clock::mock();
+        let shaper = Arc::new(LeakyBucket::with_clock(
+            n(10),
+            Duration::from_secs(1),
+            n(2),
+            clock.clone(),
+        ));
+        let bucket = Arc::new(TokenBucket::with_clock(
+            n(3),
+            n(1),
+            Duration::from_secs(1),
+            clock,
+        ));
+        let limit = FirstOf::new([shaper as Arc<dyn Strategy>, bucket.clone()]);
+        let max_delay = Duration::from_secs(1);
+
+        // The shaper's delay is passed on rather than dropped
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::ZERO)
+        );
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::from_millis(100))
+        );
+        assert_eq!(bucket.state().remaining, 3);
+
+        // Once the shaper's queue is full, the bucket takes over
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::ZERO)
+        );
+        assert_eq!(bucket.state().remaining, 2);
+    }
+
+    #[test]
+    fn any_of_reserves_with_the_soonest_child() {
+        let (clock, _mock) = Clock::mock();
+        let shaper = Arc::new(LeakyBucket::with_clock(
+            n(10),
+            Duration::from_secs(1),
+            n(2),
+            clock.clone(),
+        ));
+        let bucket = Arc::new(TokenBucket::with_clock(
+            n(1),
+            n(1),
+            Duration::from_secs(1),
+            clock,
+        ));
+        let limit = AnyOf::new([shaper as Arc<dyn Strategy>, bucket.clone()]);
+        let max_delay = Duration::from_secs(1);
+
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::ZERO)
+        );
+        assert_eq!(bucket.state().remaining, 0);
+
+        // Only the shaper can take the second request, after a delay
+        assert_eq!(
+            limit.reserve_n(n(1), max_delay),
+            ControlFlow::Continue(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "AllOf needs at least one child")]
+    fn all_of_needs_a_child() {
+        AllOf::new([]);
+    }
+
+    #[test]
+    #[should_panic(expected = "AnyOf needs at least one child")]
+    fn any_of_needs_a_child() {
+        AnyOf::new([]);
+    }
+
+    #[test]
+    #[should_panic(expected = "FirstOf needs at least one child")]
+    fn first_of_needs_a_child() {
+        FirstOf::new([]);
+    }
+}
@@ -0,0 +1,217 @@
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+
+/// Caps the amount of work in flight, rather than its rate.
+///
+/// Each admitted request holds `cost` units until they are returned with
+/// [`Strategy::release_n`], which normally happens by dropping a
+/// [`Permit`](crate::Permit). There is no way to know when work in flight will
+/// finish, so rejections carry a fixed `retry_after` hint instead.
+///
+/// The limit can be changed at runtime with [`ConcurrencyLimit::set_max`].
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    max: AtomicUsize,
+    in_flight: AtomicUsize,
+    retry_after: Duration,
+}
+
+impl ConcurrencyLimit {
+    const POLICY: &'static str = "concurrency";
+
+    /// Creates a new `ConcurrencyLimit` allowing up to `max` units in flight.
+    ///
+    /// Rejections suggest retrying after 1ms. Use
+    /// [`ConcurrencyLimit::with_retry_after`] to change this.
+    pub fn new(max: NonZeroUsize) -> Self {
+        Self {
+            max: AtomicUsize::new(max.get()),
+            in_flight: AtomicUsize::new(0),
+            retry_after: Duration::from_millis(1),
+        }
+    }
+
+    /// Sets how long rejected callers should wait before trying again.
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Changes the maximum number of units in flight.
+    ///
+    /// Work already in flight is unaffected. If it is now over the limit, new
+    /// requests are rejected until enough of it finishes.
+    pub fn set_max(&self, max: NonZeroUsize) {
+        self.max.store(max.get(), Ordering::Release);
+    }
+
+    /// The number of units currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    fn state_for(&self, in_flight: usize) -> State {
+        let max = self.max.load(Ordering::Acquire);
+        let remaining = max.saturating_sub(in_flight);
+        let hint = |busy: bool| {
+            if busy {
+                self.retry_after
+            } else {
+                Duration::ZERO
+            }
+        };
+
+        State {
+            limit: max,
+            remaining,
+            reset_after: hint(in_flight > 0),
+            retry_after: hint(remaining == 0),
+        }
+    }
+}
+
+impl Strategy for ConcurrencyLimit {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let cost = cost.get();
+        let max = self.max.load(Ordering::Acquire);
+        if cost > max {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost,
+                capacity: max,
+                policy: Self::POLICY,
+            });
+        }
+
+        match self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight + cost <= max).then_some(in_flight + cost)
+            }) {
+            Ok(_) => ControlFlow::Continue(()),
+            Err(in_flight) => ControlFlow::Break(
+                self.state_for(in_flight)
+                    .overloaded(self.retry_after, Self::POLICY),
+            ),
+        }
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        let _ = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                Some(in_flight.saturating_sub(cost.get()))
+            });
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.release_n(cost);
+    }
+
+    fn state(&self) -> State {
+        self.state_for(self.in_flight())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::Permit;
+
+    #[test]
+    fn it_limits_work_in_flight() {
+        let rl = ConcurrencyLimit::new(NonZeroUsize::new(2).unwrap());
+
+        let first = Permit::acquire(&rl).continue_value().unwrap();
+        let second = Permit::acquire(&rl).continue_value().unwrap();
+        assert_eq!(rl.in_flight(), 2);
+
+        assert_eq!(
+            Permit::acquire(&rl).break_value().unwrap(),
+            Reason::Overloaded {
+                retry_after: Duration::from_millis(1),
+                limit: 2,
+                remaining: 0,
+                reset_after: Duration::from_millis(1),
+                policy: "concurrency",
+            }
+        );
+
+        // Finishing any piece of work makes room for another
+        drop(first);
+        assert_eq!(rl.state().remaining, 1);
+        let _third = Permit::acquire(&rl).continue_value().unwrap();
+
+        drop(second);
+        assert_eq!(rl.in_flight(), 1);
+    }
+
+    #[test]
+    fn it_holds_weighted_costs_until_released() {
+        let rl = Arc::new(
+            ConcurrencyLimit::new(NonZeroUsize::new(4).unwrap())
+                .with_retry_after(Duration::from_millis(50)),
+        );
+
+        let permit = Permit::acquire_n(Arc::clone(&rl), NonZeroUsize::new(3).unwrap())
+            .continue_value()
+            .unwrap();
+        assert_eq!(permit.cost().get(), 3);
+
+        match rl.process_n(NonZeroUsize::new(2).unwrap()) {
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after,
+                remaining,
+                ..
+            }) => {
+                assert_eq!(retry_after, Duration::from_millis(50));
+                assert_eq!(remaining, 1);
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+
+        drop(permit);
+        assert_eq!(rl.in_flight(), 0);
+        assert!(rl.process_n(NonZeroUsize::new(4).unwrap()).is_continue());
+        assert!(matches!(
+            rl.process_n(NonZeroUsize::new(5).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity { capacity: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn it_changes_the_limit_with_work_in_flight() {
+        let rl = ConcurrencyLimit::new(NonZeroUsize::new(3).unwrap());
+        let first = Permit::acquire(&rl).continue_value().unwrap();
+        let _second = Permit::acquire(&rl).continue_value().unwrap();
+
+        rl.set_max(NonZeroUsize::new(1).unwrap());
+        assert_eq!(rl.state().limit, 1);
+        assert!(Permit::acquire(&rl).is_break());
+
+        // Still over the limit after one finishes
+        drop(first);
+        assert!(Permit::acquire(&rl).is_break());
+
+        rl.set_max(NonZeroUsize::new(2).unwrap());
+        assert!(Permit::acquire(&rl).is_continue());
+    }
+
+    #[test]
+    fn it_ignores_releases_for_rate_based_strategies() {
+        let rl = crate::FixedWindow::new(NonZeroUsize::new(1).unwrap(), Duration::from_secs(60));
+
+        drop(Permit::acquire(&rl).continue_value().unwrap());
+        // Releasing does not hand the permit back to a rate limit
+        assert!(Permit::acquire(&rl).is_break());
+    }
+}
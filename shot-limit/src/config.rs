@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::FixedWindow;
+use crate::Gcra;
+use crate::Quota;
+use crate::QuotaError;
+use crate::SlidingWindow;
+use crate::Strategy;
+use crate::TokenBucket;
+
+/// A declarative description of a strategy, e.g. loaded from a configuration file.
+///
+/// The `type` field selects the strategy, and periods are written as human-readable
+/// durations such as `"1s"` or `"15min"`:
+///
+/// ```toml
+/// type = "token_bucket"
+/// capacity = 100
+/// increment = 10
+/// period = "1s"
+/// ```
+///
+/// Use [`StrategyConfig::build`] to create the strategy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+#[non_exhaustive]
+pub enum StrategyConfig {
+    /// See [`TokenBucket::new`].
+    TokenBucket {
+        capacity: usize,
+        increment: usize,
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+    },
+    /// See [`Gcra::new`].
+    Gcra {
+        limit: usize,
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+    },
+    /// See [`FixedWindow::new`].
+    FixedWindow {
+        capacity: usize,
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+    },
+    /// See [`SlidingWindow::new`].
+    SlidingWindow {
+        capacity: usize,
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+    },
+}
+
+impl StrategyConfig {
+    /// Validates the configuration and creates the strategy it describes.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`QuotaError`] if any value is out of range. A
+    /// [`QuotaError::Zero`] names the configuration key which is zero.
+    pub fn build(&self) -> Result<Arc<dyn Strategy>, QuotaError> {
+        Ok(match *self {
+            Self::TokenBucket {
+                capacity,
+                increment,
+                period,
+            } => Arc::new(TokenBucket::from_quota(
+                Quota::new(increment, period)
+                    .map_err(zero_as("increment"))?
+                    .with_burst(capacity)
+                    .map_err(zero_as("capacity"))?,
+            )),
+            Self::Gcra { limit, period } => Arc::new(Gcra::from_quota(
+                Quota::new(limit, period).map_err(zero_as("limit"))?,
+            )),
+            Self::FixedWindow { capacity, period } => Arc::new(FixedWindow::from_quota(
+                Quota::new(capacity, period).map_err(zero_as("capacity"))?,
+            )),
+            Self::SlidingWindow { capacity, period } => Arc::new(SlidingWindow::from_quota(
+                Quota::new(capacity, period).map_err(zero_as("capacity"))?,
+            )),
+        })
+    }
+}
+
+/// Reports a zero count as the configuration key `key`, rather than as the argument
+/// of [`Quota`] it was passed to.
+fn zero_as(key: &'static str) -> impl Fn(QuotaError) -> QuotaError {
+    move |error| match error {
+        QuotaError::Zero { .. } => QuotaError::Zero { field: key },
+        error => error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_deserializes_each_strategy() {
+        let configs: Vec<StrategyConfig> = serde_json::from_str(
+            r#"[
+                {"type": "token_bucket", "capacity": 100, "increment": 10, "period": "1s"},
+                {"type": "gcra", "limit": 5, "period": "1h"},
+                {"type": "fixed_window", "capacity": 10, "period": "500ms"},
+                {"type": "sliding_window", "capacity": 10, "period": "1min"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            configs[0],
+            StrategyConfig::TokenBucket {
+                capacity: 100,
+                increment: 10,
+                period: Duration::from_secs(1),
+            }
+        );
+
+        let limits: Vec<_> = configs
+            .iter()
+            .map(|config| config.build().unwrap().state().limit)
+            .collect();
+        assert_eq!(limits, [100, 5, 10, 10]);
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields() {
+        let result = serde_json::from_str::<StrategyConfig>(
+            r#"{"type": "gcra", "limit": 5, "period": "1s", "burst": 10}"#,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_reports_invalid_values() {
+        let config = StrategyConfig::FixedWindow {
+            capacity: 0,
+            period: Duration::from_secs(1),
+        };
+        assert_eq!(
+            config.build().unwrap_err().to_string(),
+            "capacity must be greater than zero"
+        );
+
+        let config = StrategyConfig::TokenBucket {
+            capacity: 0,
+            increment: 10,
+            period: Duration::from_secs(1),
+        };
+        assert_eq!(
+            config.build().unwrap_err(),
+            QuotaError::Zero { field: "capacity" }
+        );
+
+        let config = StrategyConfig::TokenBucket {
+            capacity: 10,
+            increment: 0,
+            period: Duration::from_secs(1),
+        };
+        assert_eq!(
+            config.build().unwrap_err(),
+            QuotaError::Zero { field: "increment" }
+        );
+
+        let config = StrategyConfig::SlidingWindow {
+            capacity: 10,
+            period: Duration::ZERO,
+        };
+        assert_eq!(
+            config.build().unwrap_err(),
+            QuotaError::InvalidPeriod {
+                period: Duration::ZERO
+            }
+        );
+
+        let config = StrategyConfig::Gcra {
+            limit: 1_000,
+            period: Duration::from_nanos(999),
+        };
+        assert_eq!(
+            config.build().unwrap_err().to_string(),
+            "period 999ns is too short for a limit of 1000"
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use core::time::Duration;
+
+use crate::Strategy;
+
+/// How a request admitted by an adaptive strategy went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The request completed successfully.
+    Success,
+    /// The request failed, timed out or was cancelled, a sign that the service
+    /// behind the limit is overloaded.
+    Failure,
+}
+
+/// A strategy which adapts its limit to how admitted requests went, such as
+/// [`Aimd`](crate::Aimd) or [`Vegas`](crate::Vegas).
+pub trait Feedback: Strategy {
+    /// Records how an admitted request went, which took `latency` to complete.
+    fn record(&self, outcome: Outcome, latency: Duration);
+}
@@ -1,45 +1,79 @@
-use std::num::NonZeroUsize;
-use std::ops::ControlFlow;
-use std::sync::atomic::AtomicU64;
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
-use std::time::Duration;
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
+#[cfg(feature = "std")]
 use quanta::Clock;
-use quanta::Instant;
 
+use super::DefaultClock;
+#[cfg(feature = "std")]
+use super::Persist;
+use super::Quota;
 use super::Reason;
+#[cfg(feature = "std")]
+use super::Snapshot;
+#[cfg(feature = "std")]
+use super::SnapshotError;
+use super::State;
 use super::Strategy;
+use super::TimeSource;
+#[cfg(feature = "std")]
+use crate::snapshot::mismatch;
+#[cfg(feature = "std")]
+use crate::snapshot::ns_until;
+#[cfg(feature = "std")]
+use crate::snapshot::wall_after;
 
 /// A simple window-based limiter using high-performance TSC timing.
 ///
 /// Divides time into fixed periods. It is highly performant but can be
 /// susceptible to "boundary bursts" where double the limit is allowed
 /// in a short period spanning two windows.
+///
+/// The limit can be changed at runtime with [`FixedWindow::set_capacity`] and
+/// [`FixedWindow::set_rate`].
 #[derive(Debug)]
-pub struct FixedWindow {
-    capacity: usize,
+pub struct FixedWindow<C: TimeSource = DefaultClock> {
+    capacity: AtomicUsize,
     remaining: AtomicUsize,
     /// Absolute nanoseconds (relative to anchor) when the current window expires.
     expires: AtomicU64,
-    period: u64,
-    clock: Clock,
-    anchor: Instant,
+    period: AtomicU64,
+    clock: C,
+    anchor: C::Instant,
 }
 
-impl Strategy for FixedWindow {
+impl<C: TimeSource> Strategy for FixedWindow<C> {
     #[inline]
-    fn process(&self) -> ControlFlow<Reason> {
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let cost = cost.get();
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let period = self.period.load(Ordering::Acquire);
+
+        // No window will ever hold more than capacity
+        if cost > capacity {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost,
+                capacity,
+                policy: Self::POLICY,
+            });
+        }
+
         // High-performance timestamp retrieval
-        let now = self.clock.now().duration_since(self.anchor).as_nanos() as u64;
+        let now = self.clock.elapsed_ns(self.anchor);
         let mut expires = self.expires.load(Ordering::Acquire);
 
         // Check if the current window has expired
         if now > expires {
             // Calculate the start of the current window to avoid drift
             // during long idle periods.
-            let window_count = now / self.period;
-            let next_expires = (window_count + 1) * self.period;
+            let window_count = now / period;
+            let next_expires = (window_count + 1) * period;
 
             if self
                 .expires
@@ -47,7 +81,7 @@ impl Strategy for FixedWindow {
                 .is_ok()
             {
                 // Reset the bucket for the new window
-                self.remaining.store(self.capacity, Ordering::Release);
+                self.remaining.store(capacity, Ordering::Release);
                 expires = next_expires;
             } else {
                 // If we lost the race, reload the expires value set by the winner
@@ -59,18 +93,63 @@ impl Strategy for FixedWindow {
         let old_remaining =
             self.remaining
                 .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
-                    if val > 0 { Some(val - 1) } else { None }
+                    if val >= cost { Some(val - cost) } else { None }
                 });
 
         match old_remaining {
             Ok(_) => ControlFlow::Continue(()),
-            Err(_) => ControlFlow::Break(Reason::Overloaded {
-                retry_after: Duration::from_nanos(expires.saturating_sub(now)),
-            }),
+            Err(remaining) => {
+                let reset_after = Duration::from_nanos(expires.saturating_sub(now));
+                ControlFlow::Break(Reason::Overloaded {
+                    retry_after: reset_after,
+                    limit: capacity,
+                    remaining,
+                    reset_after,
+                    policy: Self::POLICY,
+                })
+            }
+        }
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+                Some(core::cmp::min(capacity, val.saturating_add(cost.get())))
+            });
+    }
+
+    fn state(&self) -> State {
+        let now = self.clock.elapsed_ns(self.anchor);
+        let expires = self.expires.load(Ordering::Acquire);
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let period = self.period.load(Ordering::Acquire);
+
+        // An expired window has not been rotated yet, but the next request will
+        // find it full.
+        let (remaining, expires) = if now > expires {
+            (capacity, (now / period + 1) * period)
+        } else {
+            (self.remaining.load(Ordering::Acquire), expires)
+        };
+
+        let reset_after = Duration::from_nanos(expires.saturating_sub(now));
+
+        State {
+            limit: capacity,
+            remaining,
+            reset_after,
+            retry_after: if remaining > 0 {
+                Duration::ZERO
+            } else {
+                reset_after
+            },
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl FixedWindow {
     /// Creates a new `FixedWindow` strategy.
     ///
@@ -78,23 +157,128 @@ impl FixedWindow {
     ///
     /// * `capacity` - The maximum number of requests allowed within a single window.
     /// * `period` - The duration of the fixed time window.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, too long, or too short for the limit. Use
+    /// [`FixedWindow::from_quota`] to handle invalid arguments instead.
     pub fn new(capacity: NonZeroUsize, period: Duration) -> Self {
-        let clock = Clock::new();
+        Self::with_clock(capacity, period, Clock::new())
+    }
+
+    /// Creates a new `FixedWindow` strategy which allows `quota.limit()` requests in
+    /// each window of `quota.period()`.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource> FixedWindow<C> {
+    const POLICY: &'static str = "fixed_window";
+
+    /// Creates a new `FixedWindow` strategy which reads time from the supplied [`TimeSource`].
+    ///
+    /// Use `Clock::mock()` to drive the window deterministically in tests.
+    ///
+    /// # Panics
+    ///
+    /// See [`FixedWindow::new`].
+    pub fn with_clock(capacity: NonZeroUsize, period: Duration, clock: C) -> Self {
+        Self::from_quota_with_clock(Quota::expect_valid(capacity, period, capacity), clock)
+    }
+
+    /// Creates a new `FixedWindow` strategy from a [`Quota`], which reads time from the
+    /// supplied [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
         let anchor = clock.now();
-        let period_ns = period.as_nanos() as u64;
+        let capacity = quota.limit().get();
+        let period_ns = quota.period().as_nanos() as u64;
 
         Self {
-            capacity: capacity.get(),
-            remaining: AtomicUsize::new(capacity.get()),
-            period: period_ns,
+            capacity: AtomicUsize::new(capacity),
+            remaining: AtomicUsize::new(capacity),
+            period: AtomicU64::new(period_ns),
             expires: AtomicU64::new(period_ns),
             clock,
             anchor,
         }
     }
+
+    /// Changes the number of requests allowed per window.
+    ///
+    /// Requests already admitted in the current window keep counting against the new
+    /// capacity, so tightening the limit takes effect immediately.
+    pub fn set_capacity(&self, capacity: NonZeroUsize) {
+        let old_capacity = self.capacity.swap(capacity.get(), Ordering::AcqRel);
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |val| {
+                let used = old_capacity.saturating_sub(val);
+                Some(capacity.get().saturating_sub(used))
+            });
+    }
+
+    /// Changes the limit to `quota.limit()` requests per window of `quota.period()`.
+    ///
+    /// The capacity changes as with [`FixedWindow::set_capacity`]. The current window
+    /// keeps its start time and its count, and ends a new period after it started.
+    /// Later windows are aligned to multiples of the new period.
+    pub fn set_rate(&self, quota: Quota) {
+        self.set_capacity(quota.limit());
+
+        let period_ns = quota.period().as_nanos() as u64;
+        let old_period = self.period.swap(period_ns, Ordering::AcqRel);
+        let _ = self
+            .expires
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |expires| {
+                Some(expires.saturating_sub(old_period).saturating_add(period_ns))
+            });
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: TimeSource> Persist for FixedWindow<C> {
+    fn snapshot_at(&self, now: SystemTime) -> Snapshot {
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let expires = self.expires.load(Ordering::Acquire);
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let period = self.period.load(Ordering::Acquire);
+
+        let (used, expires) = if elapsed > expires {
+            (0, (elapsed / period + 1) * period)
+        } else {
+            let remaining = self.remaining.load(Ordering::Acquire);
+            (capacity.saturating_sub(remaining), expires)
+        };
+
+        Snapshot::FixedWindow {
+            used,
+            expires_at: wall_after(now, expires - elapsed),
+        }
+    }
+
+    fn restore_at(&self, snapshot: &Snapshot, now: SystemTime) -> Result<(), SnapshotError> {
+        let Snapshot::FixedWindow { used, expires_at } = *snapshot else {
+            return Err(mismatch(Self::POLICY, snapshot));
+        };
+
+        let left = ns_until(now, expires_at);
+        if left == 0 {
+            // The window has ended, so nothing it admitted counts any more
+            return Ok(());
+        }
+
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let capacity = self.capacity.load(Ordering::Acquire);
+        self.expires
+            .store(elapsed.saturating_add(left), Ordering::Release);
+        self.remaining
+            .store(capacity.saturating_sub(used), Ordering::Release);
+        Ok(())
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -123,7 +307,7 @@ mod tests {
         assert_eq!(rl.process(), ControlFlow::Continue(()));
 
         // Check if the NEW expiry is actually in the future, not still in the past
-        let now = Instant::now().duration_since(rl.anchor).as_nanos() as u64;
+        let now = rl.clock.elapsed_ns(rl.anchor);
         let expires = rl.expires.load(Ordering::Acquire);
         assert!(now < expires, "Expiry should have jumped to the future");
     }
@@ -171,4 +355,149 @@ mod tests {
         std::thread::sleep(Duration::from_millis(15));
         assert_eq!(rl.process(), ControlFlow::Continue(()));
     }
+
+    #[test]
+    fn it_charges_weighted_costs() {
+        let rl = FixedWindow::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60));
+
+        assert!(rl.process_n(NonZeroUsize::new(6).unwrap()).is_continue());
+        // Only 4 left, so 5 is rejected without consuming anything
+        assert!(matches!(
+            rl.process_n(NonZeroUsize::new(5).unwrap()),
+            ControlFlow::Break(Reason::Overloaded { .. })
+        ));
+        assert!(rl.process_n(NonZeroUsize::new(4).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+    }
+
+    #[test]
+    fn it_rejects_costs_above_capacity() {
+        let rl = FixedWindow::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60));
+
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(11).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: 11,
+                capacity: 10,
+                policy: "fixed_window",
+            })
+        );
+    }
+
+    #[test]
+    fn it_reports_state_without_consuming() {
+        let period = Duration::from_millis(50);
+        let rl = FixedWindow::new(NonZeroUsize::new(2).unwrap(), period);
+
+        let state = rl.state();
+        assert_eq!(state.limit, 2);
+        assert_eq!(state.remaining, 2);
+        assert_eq!(state.retry_after, Duration::ZERO);
+        assert!(state.reset_after <= period);
+
+        let _ = rl.process();
+        let _ = rl.process();
+        let state = rl.state();
+        assert_eq!(state.remaining, 0);
+        assert_eq!(state.retry_after, state.reset_after);
+
+        // Once the window has passed the full capacity is reported, even before
+        // a request rotates the window
+        std::thread::sleep(period * 2);
+        assert_eq!(rl.state().remaining, 2);
+        assert!(rl.state().reset_after <= period);
+    }
+
+    #[test]
+    fn test_deterministic_with_mock_clock() {
+        let (clock, mock) = Clock::mock();
+        let rl = FixedWindow::with_clock(
+            NonZeroUsize::new(2).unwrap(),
+            Duration::from_millis(100),
+            clock,
+        );
+
+        assert!(rl.process().is_continue());
+        mock.increment(Duration::from_millis(40));
+        assert!(rl.process().is_continue());
+        assert_eq!(
+            rl.process(),
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::from_millis(60),
+                limit: 2,
+                remaining: 0,
+                reset_after: Duration::from_millis(60),
+                policy: "fixed_window",
+            })
+        );
+
+        // The window expires strictly after 100ms
+        mock.increment(Duration::from_millis(60));
+        assert!(rl.process().is_break());
+        mock.increment(Duration::from_nanos(1));
+        assert!(rl.process().is_continue());
+    }
+
+    #[test]
+    fn it_reconfigures_without_losing_state() {
+        let (clock, mock) = Clock::mock();
+        let rl = FixedWindow::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+        assert!(rl.process_n(NonZeroUsize::new(6).unwrap()).is_continue());
+
+        // The 6 requests already admitted still count
+        rl.set_capacity(NonZeroUsize::new(8).unwrap());
+        assert_eq!(rl.state().limit, 8);
+        assert_eq!(rl.state().remaining, 2);
+        rl.set_capacity(NonZeroUsize::new(5).unwrap());
+        assert!(rl.process().is_break());
+
+        // Shortening the period ends the current window sooner
+        mock.increment(Duration::from_secs(10));
+        rl.set_rate(Quota::new(5, Duration::from_secs(30)).unwrap());
+        assert_eq!(rl.state().reset_after, Duration::from_secs(20));
+        mock.increment(Duration::from_secs(21));
+        assert_eq!(rl.state().remaining, 5);
+        assert!(rl.process_n(NonZeroUsize::new(5).unwrap()).is_continue());
+    }
+
+    #[test]
+    fn it_restores_snapshots_until_the_window_ends() {
+        let capacity = NonZeroUsize::new(5).unwrap();
+        let period = Duration::from_secs(60);
+        let (clock, mock) = Clock::mock();
+        let rl = FixedWindow::with_clock(capacity, period, clock);
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        mock.increment(Duration::from_secs(10));
+
+        let taken_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let snapshot = rl.snapshot_at(taken_at);
+        assert_eq!(
+            snapshot,
+            Snapshot::FixedWindow {
+                used: 3,
+                expires_at: taken_at + Duration::from_secs(50),
+            }
+        );
+
+        let (clock, _mock) = Clock::mock();
+        let restored = FixedWindow::with_clock(capacity, period, clock);
+        restored
+            .restore_at(&snapshot, taken_at + Duration::from_secs(20))
+            .unwrap();
+        let state = restored.state();
+        assert_eq!(state.remaining, 2);
+        assert_eq!(state.reset_after, Duration::from_secs(30));
+
+        // Once the window has ended, the snapshot no longer counts
+        let (clock, _mock) = Clock::mock();
+        let restored = FixedWindow::with_clock(capacity, period, clock);
+        restored
+            .restore_at(&snapshot, taken_at + Duration::from_secs(50))
+            .unwrap();
+        assert_eq!(restored.state().remaining, 5);
+    }
 }
@@ -0,0 +1,30 @@
+// Rounding for the non-negative `f64`s strategies work with, since `core` has no
+// `f64::floor` or `f64::ceil`.
+
+/// Rounds `x` down to a whole number. Negative values round to zero.
+#[inline]
+pub(crate) fn floor(x: f64) -> f64 {
+    x as u64 as f64
+}
+
+/// Rounds `x` up to a whole number. Negative values round to zero.
+#[inline]
+pub(crate) fn ceil(x: f64) -> f64 {
+    let floor = floor(x);
+    if floor < x { floor + 1.0 } else { floor }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rounds_like_std() {
+        for x in [0.0, 0.25, 1.0, 1.5, 2.999, 1e12 + 0.5] {
+            assert_eq!(floor(x), x.floor());
+            assert_eq!(ceil(x), x.ceil());
+        }
+        assert_eq!(floor(-0.5), 0.0);
+        assert_eq!(ceil(-0.5), 0.0);
+    }
+}
@@ -1,101 +1,233 @@
-use std::num::NonZeroUsize;
-use std::ops::ControlFlow;
-use std::sync::atomic::AtomicU64;
-use std::sync::atomic::Ordering;
-use std::time::Duration;
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
+#[cfg(feature = "std")]
 use quanta::Clock;
-use quanta::Instant;
 
+use crate::Backoff;
+use crate::DefaultClock;
+#[cfg(feature = "std")]
+use crate::Persist;
+use crate::Quota;
 use crate::Reason;
+#[cfg(feature = "std")]
+use crate::Snapshot;
+#[cfg(feature = "std")]
+use crate::SnapshotError;
+use crate::State;
 use crate::Strategy;
+use crate::TimeSource;
+#[cfg(feature = "std")]
+use crate::snapshot::mismatch;
+#[cfg(feature = "std")]
+use crate::snapshot::ns_until;
+#[cfg(feature = "std")]
+use crate::snapshot::wall_after;
+use crate::sync::AtomicU64;
 
 /// Generic Cell Rate Algorithm
+///
+/// The limit can be changed at runtime with [`Gcra::set_rate`].
 #[derive(Debug)]
-pub struct Gcra {
+pub struct Gcra<C: TimeSource = DefaultClock> {
     /// Theoretical Arrival Time (TAT) in nanoseconds.
     tat: AtomicU64,
-    emission_interval_ns: u64,
-    delay_tolerance_ns: u64,
-    clock: Clock,
-    /// A fixed point in time (TSC tick) to calculate deltas from.
-    anchor: Instant,
+    emission_interval_ns: AtomicU64,
+    delay_tolerance_ns: AtomicU64,
+    clock: C,
+    /// A fixed point in time to calculate deltas from.
+    anchor: C::Instant,
+    backoff: Backoff,
 }
 
+#[cfg(feature = "std")]
 impl Gcra {
+    /// Creates a new `Gcra` strategy.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The number of requests allowed per `period`.
+    /// * `period` - The duration over which `limit` requests are spread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, too long, or too short for the limit. Use
+    /// [`Gcra::from_quota`] to handle invalid arguments instead.
     pub fn new(limit: NonZeroUsize, period: Duration) -> Self {
         Self::with_clock(limit, period, Clock::new())
     }
 
-    pub fn with_clock(limit: NonZeroUsize, period: Duration, clock: Clock) -> Self {
+    /// Creates a new `Gcra` strategy which spreads `quota.limit()` requests across
+    /// `quota.period()`, and admits up to `quota.burst()` at once.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource> Gcra<C> {
+    /// Creates a new `Gcra` strategy which reads time from the supplied [`TimeSource`].
+    ///
+    /// Use `Clock::mock()` to drive the strategy deterministically in tests.
+    ///
+    /// # Panics
+    ///
+    /// See [`Gcra::new`].
+    pub fn with_clock(limit: NonZeroUsize, period: Duration, clock: C) -> Self {
+        Self::from_quota_with_clock(Quota::expect_valid(limit, period, limit), clock)
+    }
+
+    /// Creates a new `Gcra` strategy from a [`Quota`], which reads time from the
+    /// supplied [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
         let anchor = clock.now();
-        let period_ns = period.as_nanos() as u64;
+        let limits = Limits::from_quota(quota);
 
         Self {
             tat: AtomicU64::new(0),
-            emission_interval_ns: period_ns / limit.get() as u64,
-            delay_tolerance_ns: period_ns,
+            emission_interval_ns: AtomicU64::new(limits.emission_interval_ns),
+            delay_tolerance_ns: AtomicU64::new(limits.delay_tolerance_ns),
             clock,
             anchor,
+            backoff: Backoff::default(),
         }
     }
 
-    #[cfg(test)]
-    pub(crate) fn remaining_capacity(&self) -> usize {
-        let now_instant = self.clock.now();
-        let now = now_instant.duration_since(self.anchor).as_nanos() as u64;
-        let tat = self.tat.load(Ordering::Acquire);
+    /// Sets how the strategy waits when it keeps losing races with other threads.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
 
-        // Total capacity based on your new() math: period / (period/limit) = limit
-        let total_capacity = self.delay_tolerance_ns / self.emission_interval_ns;
+impl<C: TimeSource> Gcra<C> {
+    const POLICY: &'static str = "gcra";
 
-        if tat <= now {
-            return total_capacity as usize;
-        }
+    /// Changes the limit to `quota.limit()` requests per `quota.period()`, with bursts
+    /// of `quota.burst()`.
+    ///
+    /// Requests already admitted keep counting against the new limit, e.g. tightening
+    /// 10/s to 5/s after 6 requests leaves the strategy over its limit until one
+    /// expires. Requests racing with the change may be judged by either limit.
+    pub fn set_rate(&self, quota: Quota) {
+        let limits = Limits::from_quota(quota);
 
-        let diff = tat - now;
+        self.delay_tolerance_ns
+            .store(limits.delay_tolerance_ns, Ordering::Release);
+        let old_interval_ns = self
+            .emission_interval_ns
+            .swap(limits.emission_interval_ns, Ordering::AcqRel);
 
-        // Use ceiling division to ensure that even a partial interval
-        // counts as a 'used' slot.
-        let used_slots = diff.div_ceil(self.emission_interval_ns);
+        // Scale the outstanding backlog from old intervals to new ones
+        limits.rescale(
+            &self.tat,
+            self.clock.elapsed_ns(self.anchor),
+            old_interval_ns,
+        );
+    }
 
-        if used_slots >= total_capacity {
-            0
-        } else {
-            (total_capacity - used_slots) as usize
+    #[inline]
+    fn limits(&self) -> Limits {
+        Limits {
+            emission_interval_ns: self.emission_interval_ns.load(Ordering::Acquire),
+            delay_tolerance_ns: self.delay_tolerance_ns.load(Ordering::Acquire),
         }
     }
 }
 
-impl Strategy for Gcra {
+impl<C: TimeSource> Strategy for Gcra<C> {
     #[inline]
-    fn process(&self) -> ControlFlow<Reason> {
-        let now_instant = self.clock.now();
-        let now = now_instant.duration_since(self.anchor).as_nanos() as u64;
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let now = self.clock.elapsed_ns(self.anchor);
+        self.limits()
+            .process_n(&self.tat, now, cost, self.backoff, Self::POLICY)
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.limits().refund_n(&self.tat, cost);
+    }
+
+    fn state(&self) -> State {
+        let now = self.clock.elapsed_ns(self.anchor);
+        let tat = self.tat.load(Ordering::Acquire);
+
+        self.limits().state_at(now, tat)
+    }
+}
+
+/// The emission interval and delay tolerance of a GCRA, in nanoseconds.
+///
+/// The whole state of a GCRA is its theoretical arrival time (TAT), so the arithmetic
+/// works on any `AtomicU64` holding one, wherever it lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) emission_interval_ns: u64,
+    pub(crate) delay_tolerance_ns: u64,
+}
+
+impl Limits {
+    pub(crate) fn from_quota(quota: Quota) -> Self {
+        let period_ns = quota.period().as_nanos() as u64;
+        let limit = quota.limit().get() as u128;
+        // A burst of `limit` tolerates a whole period
+        let delay_tolerance_ns = period_ns as u128 * quota.burst().get() as u128 / limit;
+
+        Self {
+            emission_interval_ns: period_ns / limit as u64,
+            delay_tolerance_ns: u64::try_from(delay_tolerance_ns).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// Admits a request costing `cost` at `now` by advancing `tat`, if it conforms.
+    #[inline]
+    pub(crate) fn process_n(
+        self,
+        tat: &AtomicU64,
+        now: u64,
+        cost: NonZeroUsize,
+        backoff: Backoff,
+        policy: &'static str,
+    ) -> ControlFlow<Reason> {
+        let Self {
+            emission_interval_ns,
+            delay_tolerance_ns,
+        } = self;
+        let increment_ns = emission_interval_ns.saturating_mul(cost.get() as u64);
+
+        // A cost whose emission time exceeds the tolerance can never conform.
+        if increment_ns > delay_tolerance_ns {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: cost.get(),
+                capacity: (delay_tolerance_ns / emission_interval_ns) as usize,
+                policy,
+            });
+        }
+
         let mut spins = 0;
 
         loop {
-            // After a number of spins, yield to the scheduler to let other threads run.
+            // After a number of spins, back off to let other threads run.
             // This helps resolve contention on highly-loaded systems.
-            if spins > 10 {
-                std::thread::yield_now();
-            }
+            backoff.snooze(spins);
 
-            let tat = self.tat.load(Ordering::Acquire);
+            let current = tat.load(Ordering::Acquire);
 
-            let arrival = if now > tat { now } else { tat };
-            let next_tat = arrival + self.emission_interval_ns;
+            let arrival = if now > current { now } else { current };
+            let next_tat = arrival + increment_ns;
 
-            if next_tat > now + self.delay_tolerance_ns {
-                let wait_ns = next_tat - (now + self.delay_tolerance_ns);
-                return ControlFlow::Break(Reason::Overloaded {
-                    retry_after: Duration::from_nanos(wait_ns),
-                });
+            if next_tat > now + delay_tolerance_ns {
+                let wait_ns = next_tat - (now + delay_tolerance_ns);
+                return ControlFlow::Break(
+                    self.state_at(now, current)
+                        .overloaded(Duration::from_nanos(wait_ns), policy),
+                );
             }
 
-            if self
-                .tat
-                .compare_exchange_weak(tat, next_tat, Ordering::Release, Ordering::Relaxed)
+            if tat
+                .compare_exchange_weak(current, next_tat, Ordering::Release, Ordering::Relaxed)
                 .is_ok()
             {
                 return ControlFlow::Continue(());
@@ -104,9 +236,91 @@ impl Strategy for Gcra {
             spins += 1;
         }
     }
+
+    /// Scales the backlog held in `tat` at `now` from intervals of `old_interval_ns` to
+    /// the current emission interval, so it holds the same number of requests.
+    pub(crate) fn rescale(self, tat: &AtomicU64, now: u64, old_interval_ns: u64) {
+        let _ = tat.fetch_update(Ordering::AcqRel, Ordering::Acquire, |tat| {
+            let backlog = tat.checked_sub(now).filter(|backlog| *backlog > 0)?;
+            let scaled = backlog as u128 * self.emission_interval_ns as u128
+                / old_interval_ns.max(1) as u128;
+            Some(now.saturating_add(u64::try_from(scaled).unwrap_or(u64::MAX)))
+        });
+    }
+
+    /// Pulls `tat` back, as if a request costing `cost` never arrived.
+    pub(crate) fn refund_n(self, tat: &AtomicU64, cost: NonZeroUsize) {
+        let increment_ns = self.emission_interval_ns.saturating_mul(cost.get() as u64);
+        let _ = tat.fetch_update(Ordering::AcqRel, Ordering::Acquire, |tat| {
+            Some(tat.saturating_sub(increment_ns))
+        });
+    }
+
+    /// The state of the strategy at `now` given the current `tat`.
+    pub(crate) fn state_at(self, now: u64, tat: u64) -> State {
+        let Self {
+            emission_interval_ns,
+            delay_tolerance_ns,
+        } = self;
+        // Total capacity based on the new() math: period / (period/limit) = limit
+        let limit = delay_tolerance_ns / emission_interval_ns;
+
+        if tat <= now {
+            return State {
+                limit: limit as usize,
+                remaining: limit as usize,
+                reset_after: Duration::ZERO,
+                retry_after: Duration::ZERO,
+            };
+        }
+
+        // A request conforms while tat + emission_interval <= now + tolerance, so the
+        // number of permits left is how many whole intervals fit in the slack.
+        let diff = tat - now;
+        let slack = delay_tolerance_ns.saturating_sub(diff);
+        let remaining = slack / emission_interval_ns;
+
+        let retry_after_ns = if remaining > 0 {
+            0
+        } else {
+            (tat + emission_interval_ns).saturating_sub(now + delay_tolerance_ns)
+        };
+
+        State {
+            limit: limit as usize,
+            remaining: remaining as usize,
+            reset_after: Duration::from_nanos(diff),
+            retry_after: Duration::from_nanos(retry_after_ns),
+        }
+    }
 }
 
-#[cfg(test)]
+#[cfg(feature = "std")]
+impl<C: TimeSource> Persist for Gcra<C> {
+    fn snapshot_at(&self, now: SystemTime) -> Snapshot {
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let tat = self.tat.load(Ordering::Acquire);
+
+        Snapshot::Gcra {
+            tat: wall_after(now, tat.saturating_sub(elapsed)),
+        }
+    }
+
+    fn restore_at(&self, snapshot: &Snapshot, now: SystemTime) -> Result<(), SnapshotError> {
+        let Snapshot::Gcra { tat } = *snapshot else {
+            return Err(mismatch(Self::POLICY, snapshot));
+        };
+
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        self.tat.store(
+            elapsed.saturating_add(ns_until(now, tat)),
+            Ordering::Release,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -116,13 +330,13 @@ mod tests {
         let period = Duration::from_millis(500);
         let rl = Gcra::new(limit, period);
 
-        assert_eq!(rl.remaining_capacity(), 5);
+        assert_eq!(rl.state().remaining, 5);
 
         for _ in 0..3 {
             let _ = rl.process();
         }
         // If it returns 3, it means the 3rd request hasn't "aged" into a full interval yet.
-        let rem = rl.remaining_capacity();
+        let rem = rl.state().remaining;
         assert!(
             rem == 2 || rem == 3,
             "Expected 2 or 3 slots remaining, got {}",
@@ -132,7 +346,7 @@ mod tests {
         for _ in 0..2 {
             let _ = rl.process();
         }
-        assert_eq!(rl.remaining_capacity(), 0);
+        assert_eq!(rl.state().remaining, 0);
     }
 
     #[tokio::test]
@@ -149,7 +363,7 @@ mod tests {
         for _ in 0..10 {
             let _ = rl.process();
         }
-        assert_eq!(rl.remaining_capacity(), 0);
+        assert_eq!(rl.state().remaining, 0);
         assert!(rl.process().is_break());
 
         // 4. Advance time exactly 250ms
@@ -157,10 +371,203 @@ mod tests {
 
         // 5. Now capacity should be exactly 2
         // (250ms / 100ms interval = 2 slots)
-        let rem = rl.remaining_capacity();
+        let rem = rl.state().remaining;
         assert_eq!(rem, 2, "Expected exactly 2 slots, found {}", rem);
 
         assert!(rl.process().is_continue());
-        assert_eq!(rl.remaining_capacity(), 1);
+        assert_eq!(rl.state().remaining, 1);
+    }
+
+    #[test]
+    fn test_gcra_refund_pulls_back_tat() {
+        let (clock, _mock) = Clock::mock();
+        let limit = NonZeroUsize::new(10).unwrap();
+        let rl = Gcra::with_clock(limit, Duration::from_secs(1), clock);
+
+        assert!(rl.process_n(NonZeroUsize::new(10).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+
+        rl.refund_n(NonZeroUsize::new(3).unwrap());
+        assert_eq!(rl.state().remaining, 3);
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+
+        // Refunds never push the TAT into the past
+        rl.refund_n(NonZeroUsize::new(20).unwrap());
+        assert_eq!(rl.state().remaining, 10);
+    }
+
+    #[test]
+    fn test_gcra_set_rate_keeps_usage() {
+        let (clock, mock) = Clock::mock();
+        let rl = Gcra::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(1),
+            clock,
+        );
+        assert!(rl.process_n(NonZeroUsize::new(6).unwrap()).is_continue());
+
+        // Tightening to 5/s leaves the 6 requests counted, so nothing is left
+        rl.set_rate(Quota::per_second(5).unwrap());
+        let state = rl.state();
+        assert_eq!(state.limit, 5);
+        assert_eq!(state.remaining, 0);
+        assert_eq!(state.retry_after, Duration::from_millis(400));
+        assert_eq!(state.reset_after, Duration::from_millis(1200));
+
+        mock.increment(Duration::from_millis(400));
+        assert!(rl.process().is_continue());
+        assert!(rl.process().is_break());
+
+        // Loosening again doesn't hand out a fresh burst: 5 requests are outstanding
+        rl.set_rate(Quota::per_second(10).unwrap());
+        assert_eq!(rl.state().remaining, 5);
+    }
+
+    #[test]
+    fn test_gcra_weighted_costs() {
+        let (clock, mock) = Clock::mock();
+        let limit = NonZeroUsize::new(10).unwrap();
+        let rl = Gcra::with_clock(limit, Duration::from_secs(1), clock);
+
+        assert!(rl.process_n(NonZeroUsize::new(8).unwrap()).is_continue());
+        assert_eq!(rl.state().remaining, 2);
+
+        // 3 slots need one more emission interval (100ms) to free up
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(3).unwrap()),
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::from_millis(100),
+                limit: 10,
+                remaining: 2,
+                reset_after: Duration::from_millis(800),
+                policy: "gcra",
+            })
+        );
+
+        mock.increment(Duration::from_millis(100));
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        assert_eq!(rl.state().remaining, 0);
+    }
+
+    #[test]
+    fn test_gcra_rejects_costs_above_capacity() {
+        let (clock, _mock) = Clock::mock();
+        let rl = Gcra::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(1),
+            clock,
+        );
+
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(11).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: 11,
+                capacity: 10,
+                policy: "gcra",
+            })
+        );
+        assert_eq!(rl.state().remaining, 10);
+    }
+
+    #[test]
+    fn test_gcra_state_reports_recovery_times() {
+        let (clock, mock) = Clock::mock();
+        let rl = Gcra::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(1),
+            clock,
+        );
+
+        for _ in 0..10 {
+            let _ = rl.process();
+        }
+
+        let state = rl.state();
+        assert_eq!(state.limit, 10);
+        assert_eq!(state.remaining, 0);
+        assert_eq!(state.retry_after, Duration::from_millis(100));
+        assert_eq!(state.reset_after, Duration::from_secs(1));
+
+        mock.increment(Duration::from_millis(150));
+        let state = rl.state();
+        assert_eq!(state.remaining, 1);
+        assert_eq!(state.retry_after, Duration::ZERO);
+        assert_eq!(state.reset_after, Duration::from_millis(850));
+
+        // Inspecting never consumes
+        assert_eq!(rl.state(), state);
+        assert_eq!(
+            rl.process(),
+            ControlFlow::Continue(()),
+            "The permit reported by state() should still be available"
+        );
+    }
+
+    #[test]
+    fn test_gcra_quota_limits_bursts() {
+        let (clock, mock) = Clock::mock();
+        let quota = Quota::per_second(10).unwrap().with_burst(2).unwrap();
+        let rl = Gcra::from_quota_with_clock(quota, clock);
+
+        assert_eq!(rl.state().limit, 2);
+        assert!(rl.process().is_continue());
+        assert!(rl.process().is_continue());
+        assert!(rl.process().is_break());
+
+        // The steady rate is still 10/s
+        mock.increment(Duration::from_millis(100));
+        assert!(rl.process().is_continue());
+        assert!(rl.process().is_break());
+    }
+
+    #[test]
+    fn test_gcra_restores_snapshots() {
+        let limit = NonZeroUsize::new(10).unwrap();
+        let period = Duration::from_secs(1);
+        let (clock, _mock) = Clock::mock();
+        let rl = Gcra::with_clock(limit, period, clock);
+        assert!(rl.process_n(limit).is_continue());
+
+        let taken_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let snapshot = rl.snapshot_at(taken_at);
+
+        let (clock, _mock) = Clock::mock();
+        let restored = Gcra::with_clock(limit, period, clock);
+        restored
+            .restore_at(&snapshot, taken_at + Duration::from_millis(300))
+            .unwrap();
+        let state = restored.state();
+        assert_eq!(state.remaining, 3);
+        assert_eq!(state.reset_after, Duration::from_millis(700));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+    use crate::ManualClock;
+
+    #[test]
+    fn it_admits_each_request_once() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let limit = NonZeroUsize::new(2).unwrap();
+            let rl = Arc::new(
+                Gcra::with_clock(limit, Duration::from_secs(1), &CLOCK)
+                    .with_backoff(Backoff::new(0, thread::yield_now)),
+            );
+            let racer = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.process().is_continue())
+            };
+
+            let admitted = [rl.process().is_continue(), racer.join().unwrap()];
+            assert_eq!(admitted, [true, true]);
+            assert!(rl.process().is_break());
+        });
     }
 }
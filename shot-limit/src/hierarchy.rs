@@ -0,0 +1,546 @@
+use std::fmt;
+use std::hash::BuildHasher;
+use std::hash::Hash;
+use std::hash::RandomState;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use quanta::Clock;
+
+use crate::Backoff;
+use crate::Keyed;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::TimeSource;
+
+/// Nested budgets, such as a tenant's limit shared by its users, each of whom is
+/// limited too, in the style of a hierarchical token bucket (HTB).
+///
+/// A request names one key per level, from the root down, e.g. `[tenant, user,
+/// endpoint]`. Each distinct path prefix is a node with its own strategies, built on
+/// first use by its level's factories, so user `alice` of one tenant is a different
+/// node from `alice` of another.
+///
+/// Every node has a ceiling, which every request through it is charged to: a tenant's
+/// 1,000 req/s caps the traffic of all its users together. A node may also have an
+/// assured rate, which it can use without borrowing. Beyond it, the node borrows
+/// capacity that its parent's assured rate leaves unused, and so on up to the root,
+/// up to the node's own ceiling. A node without an assured rate lends from its
+/// ceiling, so siblings under it simply share what their ancestors leave them.
+///
+/// Unlike in HTB, traffic within a node's assured rate is not charged to its parent's
+/// assured rate, so a parent's assured rate is what it can lend on top of its
+/// children's. Keep the assured rates of a node's children within its ceiling, or
+/// borrowers can take capacity that was assured to others.
+///
+/// Admission is atomic: a request is either charged at every level or at none. Every
+/// level's [`State`] is checked before anything is charged, while requests through the
+/// same root take turns, so a request is never rejected for a charge which is then
+/// given back. The rejection's [`Reason::policy`] is the name of the level which
+/// rejected it.
+///
+/// Roots are spread over a fixed set of gates, so requests through unrelated roots
+/// rarely wait for each other. Nodes served from a level's overflow strategy, once it
+/// tracks `max_keys` nodes, are shared across roots. Should such a node, or a strategy
+/// whose state overstates its room, reject a request after all, the levels already
+/// charged are refunded as with [`AllOf`](crate::AllOf). Each level tracks up to
+/// `max_keys` nodes and evicts them as [`Keyed`] does.
+pub struct Hierarchy<K, C: TimeSource + Clone = Clock> {
+    levels: Vec<Level<K, C>>,
+    max_keys: NonZeroUsize,
+    idle_timeout: Duration,
+    clock: C,
+    /// Held by the request being admitted through the roots which hash to each gate.
+    gates: Box<[AtomicBool]>,
+    hasher: RandomState,
+    backoff: Backoff,
+}
+
+/// The number of gates the roots of a [`Hierarchy`] are spread over.
+const GATES: usize = 64;
+
+/// Holds one of the gates of a [`Hierarchy`], and opens it again when dropped.
+struct Gate<'a>(&'a AtomicBool);
+
+impl Drop for Gate<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// The nodes at one depth of a [`Hierarchy`], keyed by their paths.
+struct Level<K, C: TimeSource> {
+    name: &'static str,
+    ceiling: Keyed<Vec<K>, Node, C>,
+    assured: Option<Keyed<Vec<K>, Node, C>>,
+}
+
+/// A node's strategy, of whichever kind its level's factory builds.
+#[derive(Debug)]
+struct Node(Box<dyn Strategy>);
+
+impl Strategy for Node {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        self.0.process_n(cost)
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.0.refund_n(cost);
+    }
+
+    fn state(&self) -> State {
+        self.0.state()
+    }
+}
+
+impl<K, C> fmt::Debug for Hierarchy<K, C>
+where
+    K: Hash + Eq,
+    C: TimeSource + Clone,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let levels: Vec<_> = self.levels.iter().map(|level| level.name).collect();
+        f.debug_struct("Hierarchy")
+            .field("levels", &levels)
+            .field("max_keys", &self.max_keys)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K> Hierarchy<K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Creates a new `Hierarchy` without any levels. Add them, from the root down, with
+    /// [`Hierarchy::with_level`] and [`Hierarchy::with_assured_level`].
+    ///
+    /// # Arguments
+    ///
+    /// * `max_keys` - The maximum number of nodes tracked at each level.
+    /// * `idle_timeout` - How long a node must go unused before it may be evicted.
+    pub fn new(max_keys: NonZeroUsize, idle_timeout: Duration) -> Self {
+        Self::with_clock(max_keys, idle_timeout, Clock::new())
+    }
+}
+
+impl<K, C> Hierarchy<K, C>
+where
+    K: Hash + Eq + Clone,
+    C: TimeSource + Clone,
+{
+    /// Creates a new `Hierarchy` which tracks idleness with the supplied [`TimeSource`].
+    pub fn with_clock(max_keys: NonZeroUsize, idle_timeout: Duration, clock: C) -> Self {
+        Self {
+            levels: Vec::new(),
+            max_keys,
+            idle_timeout,
+            clock,
+            gates: (0..GATES).map(|_| AtomicBool::new(false)).collect(),
+            hasher: RandomState::new(),
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Sets how requests wait for the gate of their root while another request through
+    /// it is being admitted.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Adds a level below the existing ones, whose nodes are limited by the strategy
+    /// `ceiling` builds and share what their ancestors leave them.
+    ///
+    /// `name` is reported as the policy of the level's rejections.
+    pub fn with_level<F, S>(mut self, name: &'static str, ceiling: F) -> Self
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+        S: Strategy + 'static,
+    {
+        let ceiling = self.keyed(ceiling);
+        self.levels.push(Level {
+            name,
+            ceiling,
+            assured: None,
+        });
+        self
+    }
+
+    /// Adds a level below the existing ones, whose nodes are assured the rate of the
+    /// strategy `assured` builds, and borrow from their ancestors beyond it up to the
+    /// strategy `ceiling` builds.
+    ///
+    /// `name` is reported as the policy of the level's rejections.
+    pub fn with_assured_level<F, S, G, T>(
+        mut self,
+        name: &'static str,
+        assured: F,
+        ceiling: G,
+    ) -> Self
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+        S: Strategy + 'static,
+        G: Fn() -> T + Send + Sync + 'static,
+        T: Strategy + 'static,
+    {
+        let assured = Some(self.keyed(assured));
+        let ceiling = self.keyed(ceiling);
+        self.levels.push(Level {
+            name,
+            ceiling,
+            assured,
+        });
+        self
+    }
+
+    fn keyed<F, S>(&self, factory: F) -> Keyed<Vec<K>, Node, C>
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+        S: Strategy + 'static,
+    {
+        Keyed::with_clock(
+            self.max_keys,
+            self.idle_timeout,
+            move || Node(Box::new(factory())),
+            self.clock.clone(),
+        )
+    }
+
+    /// The number of levels, and so of keys in a path.
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Attempts to process a single request for the node at `path`.
+    ///
+    /// # Errors
+    ///
+    /// See [`Hierarchy::process_n`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` does not have one key per level.
+    #[inline]
+    pub fn process(&self, path: &[K]) -> ControlFlow<Reason> {
+        self.process_n(path, NonZeroUsize::MIN)
+    }
+
+    /// Attempts to process a request for the node at `path` which consumes `cost` units
+    /// at every level.
+    ///
+    /// Either every level is charged or none is; see [`Hierarchy`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the `Reason` of the first level, from the leaf up, whose ceiling rejects
+    /// the request. If no node on the path can cover it from its assured rate, returns
+    /// the rejection of the outermost node which could not lend. Either way the
+    /// `policy` is the rejecting level's name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` does not have one key per level.
+    pub fn process_n(&self, path: &[K], cost: NonZeroUsize) -> ControlFlow<Reason> {
+        assert_eq!(
+            path.len(),
+            self.levels.len(),
+            "a path names one key per level"
+        );
+
+        let Some(root) = path.first() else {
+            // Without levels there is nothing to limit
+            return ControlFlow::Continue(());
+        };
+        let _gate = self.enter(root);
+
+        // Every node on the path is charged to its ceiling, so check those first
+        for (depth, level) in self.levels.iter().enumerate().rev() {
+            let state = level
+                .ceiling
+                .with_strategy(&path[..=depth], Strategy::state);
+            if let Some(reason) = refusal(state, cost, level.name) {
+                return ControlFlow::Break(reason);
+            }
+        }
+
+        let lender = match self.lender(path, cost) {
+            Ok(lender) => lender,
+            Err(reason) => return ControlFlow::Break(reason),
+        };
+
+        // Only now charge the path, which should find the room it was shown
+        for (depth, level) in self.levels.iter().enumerate().rev() {
+            if let ControlFlow::Break(reason) = level.ceiling.process_n(&path[..=depth], cost) {
+                self.roll_back(path, depth + 1, cost);
+                return ControlFlow::Break(reason.with_policy(level.name));
+            }
+        }
+        if let Some(depth) = lender
+            && let Some(assured) = &self.levels[depth].assured
+            && let ControlFlow::Break(reason) = assured.process_n(&path[..=depth], cost)
+        {
+            self.roll_back(path, 0, cost);
+            return ControlFlow::Break(reason.with_policy(self.levels[depth].name));
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Finds the depth of the nearest node on `path` with assured capacity to cover the
+    /// request, or `None` if it is a node without an assured rate, which lends from its
+    /// ceiling.
+    ///
+    /// If no node can lend, returns the rejection of the outermost one.
+    fn lender(&self, path: &[K], cost: NonZeroUsize) -> Result<Option<usize>, Reason> {
+        let mut refused = None;
+        for (depth, level) in self.levels.iter().enumerate().rev() {
+            let Some(assured) = &level.assured else {
+                return Ok(None);
+            };
+            let state = assured.with_strategy(&path[..=depth], Strategy::state);
+            match refusal(state, cost, level.name) {
+                None => return Ok(Some(depth)),
+                Some(reason) => refused = Some(reason),
+            }
+        }
+        // There is at least one level, so one of them refused
+        Err(refused.expect("a path names a key per level"))
+    }
+
+    /// Waits for and holds the gate of the requests through `root`.
+    fn enter(&self, root: &K) -> Gate<'_> {
+        let gate = &self.gates[self.hasher.hash_one(root) as usize % GATES];
+        let mut failures = 0;
+        while gate
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            failures += 1;
+            self.backoff.snooze(failures);
+        }
+        Gate(gate)
+    }
+
+    /// Refunds the ceilings charged below `depth`, i.e. from `depth` to the leaf.
+    fn roll_back(&self, path: &[K], depth: usize, cost: NonZeroUsize) {
+        for (depth, level) in self.levels.iter().enumerate().skip(depth) {
+            level.ceiling.refund_n(&path[..=depth], cost);
+        }
+    }
+
+    /// Inspects the ceiling of each node on `path`, from the root down, without
+    /// consuming anything.
+    ///
+    /// A node which is not currently tracked is `None`, in which case it would start
+    /// with a fresh strategy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` has more keys than there are levels.
+    pub fn state(&self, path: &[K]) -> Vec<Option<State>> {
+        assert!(
+            path.len() <= self.levels.len(),
+            "a path names one key per level"
+        );
+        (0..path.len())
+            .map(|depth| self.levels[depth].ceiling.state(&path[..=depth]))
+            .collect()
+    }
+
+    /// Removes idle and fully recovered nodes at every level. See [`Keyed::evict_idle`].
+    ///
+    /// Returns the number of strategies evicted.
+    pub fn evict_idle(&self) -> usize {
+        self.levels
+            .iter()
+            .map(|level| {
+                level.ceiling.evict_idle() + level.assured.as_ref().map_or(0, Keyed::evict_idle)
+            })
+            .sum()
+    }
+}
+
+/// The rejection of a request costing `cost` by a node in `state`, if it has no room.
+fn refusal(state: State, cost: NonZeroUsize, policy: &'static str) -> Option<Reason> {
+    if cost.get() > state.limit {
+        Some(Reason::CostExceedsCapacity {
+            cost: cost.get(),
+            capacity: state.limit,
+            policy,
+        })
+    } else if cost.get() > state.remaining {
+        // A node with some room left reports no wait, but once it has recovered it has
+        // room for any cost within its limit
+        let retry_after = match state.retry_after {
+            Duration::ZERO => state.reset_after,
+            retry_after => retry_after,
+        };
+        Some(state.overloaded(retry_after, policy))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+    use crate::FixedWindow;
+    use crate::ManualClock;
+    use crate::SlidingLog;
+
+    fn n(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    fn window(limit: usize, clock: &'static ManualClock) -> FixedWindow<&'static ManualClock> {
+        FixedWindow::with_clock(n(limit), Duration::from_secs(1), clock)
+    }
+
+    fn policy(decision: ControlFlow<Reason>) -> &'static str {
+        match decision {
+            ControlFlow::Break(reason) => reason.policy(),
+            ControlFlow::Continue(()) => panic!("expected a rejection"),
+        }
+    }
+
+    #[test]
+    fn it_shares_a_parent_between_capped_children() {
+        static CLOCK: ManualClock = ManualClock::new();
+        let hierarchy = Hierarchy::with_clock(n(100), Duration::from_secs(60), &CLOCK)
+            .with_level("tenant", || window(10, &CLOCK))
+            .with_level("user", || window(4, &CLOCK));
+
+        for _ in 0..4 {
+            assert!(hierarchy.process(&["acme", "alice"]).is_continue());
+        }
+        assert_eq!(policy(hierarchy.process(&["acme", "alice"])), "user");
+
+        // alice's rejection cost the tenant nothing
+        for _ in 0..4 {
+            assert!(hierarchy.process(&["acme", "bob"]).is_continue());
+        }
+        for _ in 0..2 {
+            assert!(hierarchy.process(&["acme", "carol"]).is_continue());
+        }
+        assert_eq!(policy(hierarchy.process(&["acme", "carol"])), "tenant");
+        assert_eq!(
+            hierarchy.state(&["acme", "carol"]),
+            vec![
+                Some(State {
+                    limit: 10,
+                    remaining: 0,
+                    reset_after: Duration::from_secs(1),
+                    retry_after: Duration::from_secs(1),
+                }),
+                Some(State {
+                    limit: 4,
+                    remaining: 2,
+                    reset_after: Duration::from_secs(1),
+                    retry_after: Duration::ZERO,
+                }),
+            ]
+        );
+
+        // Users of another tenant are other nodes, even with the same name
+        assert!(hierarchy.process(&["globex", "alice"]).is_continue());
+    }
+
+    #[test]
+    fn it_borrows_what_siblings_leave_unused() {
+        static CLOCK: ManualClock = ManualClock::new();
+        // 4 req/s to lend on top of 2 req/s assured to each user, who may reach 5
+        let hierarchy = Hierarchy::with_clock(n(100), Duration::from_secs(60), &CLOCK)
+            .with_assured_level("tenant", || window(4, &CLOCK), || window(8, &CLOCK))
+            .with_assured_level("user", || window(2, &CLOCK), || window(5, &CLOCK));
+
+        // alice uses the assured rate, then borrows up to the ceiling
+        for _ in 0..5 {
+            assert!(hierarchy.process(&["acme", "alice"]).is_continue());
+        }
+        assert_eq!(policy(hierarchy.process(&["acme", "alice"])), "user");
+
+        // bob's assured rate is untouched by alice's borrowing
+        for _ in 0..2 {
+            assert!(hierarchy.process(&["acme", "bob"]).is_continue());
+        }
+        // bob can also borrow the last token alice left in the tenant's pool
+        assert!(hierarchy.process(&["acme", "bob"]).is_continue());
+        assert_eq!(policy(hierarchy.process(&["acme", "bob"])), "tenant");
+
+        // Nothing was charged for the rejections
+        let states = hierarchy.state(&["acme", "bob"]);
+        assert_eq!(states[0].unwrap().remaining, 0);
+        assert_eq!(states[1].unwrap().remaining, 2);
+
+        CLOCK.advance(Duration::from_secs(2));
+        assert!(hierarchy.process(&["acme", "bob"]).is_continue());
+    }
+
+    #[test]
+    fn it_rejects_costs_above_a_levels_ceiling() {
+        static CLOCK: ManualClock = ManualClock::new();
+        let hierarchy = Hierarchy::with_clock(n(100), Duration::from_secs(60), &CLOCK)
+            .with_level("tenant", || window(10, &CLOCK))
+            .with_level("user", || window(4, &CLOCK));
+
+        assert!(matches!(
+            hierarchy.process_n(&["acme", "alice"], n(5)),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                capacity: 4,
+                policy: "user",
+                ..
+            })
+        ));
+        assert!(hierarchy.process_n(&["acme", "alice"], n(4)).is_continue());
+    }
+
+    #[test]
+    fn it_charges_no_level_for_a_rejection() {
+        static CLOCK: ManualClock = ManualClock::new();
+        // A sliding log cannot be refunded, so it must not be charged at all
+        let hierarchy = Hierarchy::with_clock(n(100), Duration::from_secs(60), &CLOCK)
+            .with_level("tenant", || window(2, &CLOCK))
+            .with_level("user", || {
+                SlidingLog::with_clock(n(5), Duration::from_secs(1), &CLOCK)
+            });
+
+        for _ in 0..2 {
+            assert!(hierarchy.process(&["acme", "alice"]).is_continue());
+        }
+        assert_eq!(policy(hierarchy.process(&["acme", "alice"])), "tenant");
+        assert_eq!(hierarchy.state(&["acme", "alice"])[1].unwrap().remaining, 3);
+    }
+
+    #[test]
+    fn it_admits_exactly_the_parents_limit_under_contention() {
+        static CLOCK: ManualClock = ManualClock::new();
+        let hierarchy = Arc::new(
+            Hierarchy::with_clock(n(100), Duration::from_secs(60), &CLOCK)
+                .with_level("tenant", || window(100, &CLOCK))
+                .with_level("user", || window(30, &CLOCK)),
+        );
+
+        let handles: Vec<_> = (0..8)
+            .map(|user| {
+                let hierarchy = Arc::clone(&hierarchy);
+                thread::spawn(move || {
+                    (0..50)
+                        .filter(|_| hierarchy.process(&[0, user]).is_continue())
+                        .count()
+                })
+            })
+            .collect();
+        let admitted: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert_eq!(admitted.iter().sum::<usize>(), 100);
+        assert!(admitted.iter().all(|&admitted| admitted <= 30));
+    }
+}
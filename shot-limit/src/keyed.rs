@@ -0,0 +1,613 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry as MapEntry;
+use quanta::Clock;
+
+use super::Persist;
+use super::Reason;
+use super::Snapshot;
+use super::SnapshotError;
+use super::State;
+use super::Strategy;
+use super::TimeSource;
+
+/// The default shortest time between two sweeps of a full map.
+const DEFAULT_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A strategy instance together with the last time it was used.
+struct Entry<S> {
+    strategy: S,
+    /// Nanoseconds (relative to anchor) of the most recent request for this key.
+    last_seen: AtomicU64,
+}
+
+/// A keyed limiter which lazily creates one strategy per key.
+///
+/// Each key (e.g. an API key or client IP) gets its own strategy instance, built
+/// on first use by the supplied factory. Entries live in a sharded concurrent map,
+/// so requests for different keys rarely contend with each other.
+///
+/// To keep memory bounded under key-cardinality attacks, the number of tracked keys
+/// is capped at `max_keys`. When the cap is reached, a sweep evicts keys which have
+/// been idle for longer than `idle_timeout`, or whose strategy has fully recovered.
+/// Sweeps scan every key, so they run at most once per
+/// [`Keyed::with_sweep_interval`]: a flood of new keys costs one scan per interval,
+/// not one per key. While the map is full, new keys share one overflow strategy, also
+/// built by the factory, until a sweep makes room for them. Active keys are never
+/// evicted (which would hand an attacker a way to reset other clients' limits), and
+/// an attacker holding every slot only competes with new clients for the overflow.
+///
+/// Choose an `idle_timeout` of at least the time the strategy takes to fully recover
+/// (e.g. its period). An evicted key is then indistinguishable from a new one.
+pub struct Keyed<K, S, C: TimeSource = Clock> {
+    entries: DashMap<K, Entry<S>>,
+    factory: Box<dyn Fn() -> S + Send + Sync>,
+    /// Shared by new keys which arrive while the map is full.
+    overflow: S,
+    /// Keys tracked, counting those about to be inserted, so the cap holds under races.
+    len: AtomicUsize,
+    max_keys: usize,
+    idle_timeout_ns: u64,
+    sweep_interval_ns: u64,
+    /// Nanoseconds (relative to anchor) before which the map is not swept again.
+    next_sweep: AtomicU64,
+    clock: C,
+    anchor: C::Instant,
+}
+
+impl<K, S, C> fmt::Debug for Keyed<K, S, C>
+where
+    K: Hash + Eq,
+    C: TimeSource,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyed")
+            .field("keys", &self.entries.len())
+            .field("max_keys", &self.max_keys)
+            .field("idle_timeout_ns", &self.idle_timeout_ns)
+            .field("sweep_interval_ns", &self.sweep_interval_ns)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, S> Keyed<K, S>
+where
+    K: Hash + Eq,
+    S: Strategy,
+{
+    /// Creates a new `Keyed` limiter.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_keys` - The maximum number of keys tracked at once.
+    /// * `idle_timeout` - How long a key must go unused before it may be evicted.
+    /// * `factory` - Builds the strategy for a newly seen key.
+    pub fn new<F>(max_keys: NonZeroUsize, idle_timeout: Duration, factory: F) -> Self
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        Self::with_clock(max_keys, idle_timeout, factory, Clock::new())
+    }
+}
+
+impl<K, S, C> Keyed<K, S, C>
+where
+    K: Hash + Eq,
+    S: Strategy,
+    C: TimeSource,
+{
+    /// Creates a new `Keyed` limiter which tracks idleness with the supplied [`TimeSource`].
+    pub fn with_clock<F>(
+        max_keys: NonZeroUsize,
+        idle_timeout: Duration,
+        factory: F,
+        clock: C,
+    ) -> Self
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        let anchor = clock.now();
+
+        Self {
+            entries: DashMap::new(),
+            overflow: factory(),
+            factory: Box::new(factory),
+            len: AtomicUsize::new(0),
+            max_keys: max_keys.get(),
+            idle_timeout_ns: idle_timeout.as_nanos() as u64,
+            sweep_interval_ns: DEFAULT_SWEEP_INTERVAL.as_nanos() as u64,
+            next_sweep: AtomicU64::new(0),
+            clock,
+            anchor,
+        }
+    }
+
+    /// Sets the shortest time between two sweeps for evictable keys once the key limit
+    /// is reached. The default is 100ms.
+    ///
+    /// A sweep scans every key, locking each shard of the map in turn. Shorter intervals
+    /// make room for new keys sooner, at the cost of more scanning under a flood of them.
+    pub fn with_sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval_ns = interval.as_nanos() as u64;
+        self
+    }
+
+    /// Attempts to process a single request for `key`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Reason` if the rate limit for `key` has been reached, or, if `key` is
+    /// new and no more keys can be tracked, the rate limit of the overflow strategy.
+    #[inline]
+    pub fn process<Q>(&self, key: &Q) -> ControlFlow<Reason>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.process_n(key, NonZeroUsize::MIN)
+    }
+
+    /// Attempts to process a request for `key` which consumes `cost` units.
+    ///
+    /// # Errors
+    ///
+    /// See [`Strategy::process_n`]. If `key` is new and no more keys can be tracked, the
+    /// request is charged to the overflow strategy instead.
+    pub fn process_n<Q>(&self, key: &Q, cost: NonZeroUsize) -> ControlFlow<Reason>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.with_strategy(key, |strategy| strategy.process_n(cost))
+    }
+
+    /// Calls `f` with the strategy for `key`, creating it if `key` is new, or with the
+    /// overflow strategy if `key` is new and no more keys can be tracked.
+    pub(crate) fn with_strategy<Q, R>(&self, key: &Q, f: impl FnOnce(&S) -> R) -> R
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let now = self.now();
+
+        // Fast path: the key already exists, only a shard read lock is needed.
+        if let Some(entry) = self.entries.get(key) {
+            entry.last_seen.store(now, Ordering::Relaxed);
+            return f(&entry.strategy);
+        }
+
+        if !self.reserve(now) {
+            return f(&self.overflow);
+        }
+
+        // Another thread may have inserted the key since we looked, so go through
+        // the entry API rather than inserting blindly.
+        let entry = match self.entries.entry(key.to_owned()) {
+            MapEntry::Occupied(entry) => {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                entry.into_ref().downgrade()
+            }
+            MapEntry::Vacant(entry) => entry
+                .insert(Entry {
+                    strategy: (self.factory)(),
+                    last_seen: AtomicU64::new(now),
+                })
+                .downgrade(),
+        };
+        entry.last_seen.store(now, Ordering::Relaxed);
+        f(&entry.strategy)
+    }
+
+    /// Gives back `cost` units consumed by a request for `key` which was admitted but
+    /// never performed. See [`Strategy::refund_n`].
+    ///
+    /// If `key` is not tracked, the overflow strategy it was charged to is refunded.
+    pub fn refund_n<Q>(&self, key: &Q, cost: NonZeroUsize)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.entries.get(key) {
+            Some(entry) => entry.strategy.refund_n(cost),
+            None => self.overflow.refund_n(cost),
+        }
+    }
+
+    /// Inspects the state of the strategy for `key` without consuming anything.
+    ///
+    /// Returns `None` if `key` is not currently tracked, in which case it would
+    /// start with a fresh strategy.
+    pub fn state<Q>(&self, key: &Q) -> Option<State>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.entries.get(key).map(|entry| entry.strategy.state())
+    }
+
+    /// Removes every key which has been idle for longer than the idle timeout, or
+    /// whose strategy has fully recovered.
+    ///
+    /// This happens automatically when the key limit is reached, but may also be
+    /// called periodically to release memory sooner. Returns the number of keys
+    /// evicted.
+    pub fn evict_idle(&self) -> usize {
+        self.evict_idle_at(self.now())
+    }
+
+    /// The number of keys currently tracked.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no keys are currently tracked.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Claims room for a new key, sweeping for evictable keys if the map is full and
+    /// has not been swept recently.
+    fn reserve(&self, now: u64) -> bool {
+        if self.try_reserve() {
+            return true;
+        }
+
+        // Only one thread sweeps per interval, the others overflow straight away
+        let next_sweep = self.next_sweep.load(Ordering::Acquire);
+        if now < next_sweep
+            || self
+                .next_sweep
+                .compare_exchange(
+                    next_sweep,
+                    now.saturating_add(self.sweep_interval_ns),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_err()
+        {
+            return false;
+        }
+        self.evict_idle_at(now);
+        self.try_reserve()
+    }
+
+    fn try_reserve(&self) -> bool {
+        self.len
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < self.max_keys).then_some(len + 1)
+            })
+            .is_ok()
+    }
+
+    fn evict_idle_at(&self, now: u64) -> usize {
+        let mut evicted = 0;
+        self.entries.retain(|_, entry| {
+            let idle =
+                now.saturating_sub(entry.last_seen.load(Ordering::Relaxed)) > self.idle_timeout_ns;
+            // A fully recovered strategy is indistinguishable from a fresh one
+            let state = entry.strategy.state();
+            let recovered = state.remaining == state.limit;
+            evicted += usize::from(idle || recovered);
+            !idle && !recovered
+        });
+        self.len.fetch_sub(evicted, Ordering::AcqRel);
+        evicted
+    }
+
+    #[inline]
+    fn now(&self) -> u64 {
+        self.clock.elapsed_ns(self.anchor)
+    }
+}
+
+impl<K, S, C> Keyed<K, S, C>
+where
+    K: Hash + Eq + Clone,
+    S: Persist,
+    C: TimeSource,
+{
+    /// Snapshots the strategy of every tracked key, e.g. to save them before a
+    /// restart.
+    ///
+    /// Keys whose strategy has fully recovered are left out, since they would be
+    /// restored exactly as a new key starts.
+    pub fn snapshot(&self) -> Vec<(K, Snapshot)> {
+        let now = SystemTime::now();
+        self.entries
+            .iter()
+            .filter(|entry| {
+                let state = entry.strategy.state();
+                state.remaining < state.limit
+            })
+            .map(|entry| (entry.key().clone(), entry.strategy.snapshot_at(now)))
+            .collect()
+    }
+
+    /// Restores snapshots taken by [`Keyed::snapshot`], creating a strategy for each
+    /// key with the factory.
+    ///
+    /// Restored keys count as just seen. Keys beyond the key limit are skipped. Returns
+    /// the number of keys restored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`SnapshotError`] if a snapshot was taken from a different kind of
+    /// strategy than the factory builds. Keys restored before it are kept.
+    pub fn restore(
+        &self,
+        snapshots: impl IntoIterator<Item = (K, Snapshot)>,
+    ) -> Result<usize, SnapshotError> {
+        let now = SystemTime::now();
+        let last_seen = self.now();
+        let mut restored = 0;
+
+        for (key, snapshot) in snapshots {
+            let strategy = (self.factory)();
+            strategy.restore_at(&snapshot, now)?;
+            let entry = Entry {
+                strategy,
+                last_seen: AtomicU64::new(last_seen),
+            };
+
+            match self.entries.entry(key) {
+                MapEntry::Occupied(mut occupied) => {
+                    occupied.insert(entry);
+                }
+                MapEntry::Vacant(vacant) => {
+                    if !self.try_reserve() {
+                        continue;
+                    }
+                    vacant.insert(entry);
+                }
+            }
+            restored += 1;
+        }
+
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::FixedWindow;
+    use crate::Gcra;
+
+    #[test]
+    fn it_limits_each_key_independently() {
+        let keyed: Keyed<String, FixedWindow> = Keyed::new(
+            NonZeroUsize::new(100).unwrap(),
+            Duration::from_secs(60),
+            || FixedWindow::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(60)),
+        );
+
+        assert!(keyed.process("alice").is_continue());
+        assert!(keyed.process("alice").is_continue());
+        assert!(keyed.process("alice").is_break());
+
+        // bob has a separate budget
+        assert!(keyed.process("bob").is_continue());
+        assert!(
+            keyed
+                .process_n("bob", NonZeroUsize::new(1).unwrap())
+                .is_continue()
+        );
+        assert!(keyed.process("bob").is_break());
+
+        assert_eq!(keyed.len(), 2);
+    }
+
+    #[test]
+    fn it_evicts_idle_keys_when_full() {
+        let (clock, mock) = Clock::mock();
+        let strategy_clock = clock.clone();
+        let keyed: Keyed<u64, Gcra> = Keyed::with_clock(
+            NonZeroUsize::new(2).unwrap(),
+            Duration::from_secs(1),
+            move || {
+                Gcra::with_clock(
+                    NonZeroUsize::new(1).unwrap(),
+                    Duration::from_secs(10),
+                    strategy_clock.clone(),
+                )
+            },
+            clock,
+        );
+
+        assert!(keyed.process(&1).is_continue());
+        assert!(keyed.process(&2).is_continue());
+
+        // Full, and nobody is idle yet: new keys share the overflow, existing keys still
+        // have their own budgets
+        assert!(keyed.process(&3).is_continue());
+        assert!(matches!(
+            keyed.process(&4),
+            ControlFlow::Break(Reason::Overloaded { .. })
+        ));
+        assert!(keyed.process(&1).is_break());
+        assert_eq!(keyed.len(), 2);
+        assert_eq!(keyed.state(&3), None);
+
+        mock.increment(Duration::from_millis(600));
+        // Key 2 stays active
+        assert!(keyed.process(&2).is_break());
+        mock.increment(Duration::from_millis(600));
+
+        // Key 1 has now been idle for 1.2s and is evicted to make room
+        assert!(keyed.process(&3).is_continue());
+        assert_eq!(keyed.len(), 2);
+
+        // Key 1 comes back with a fresh budget
+        mock.increment(Duration::from_secs(2));
+        assert_eq!(keyed.evict_idle(), 2);
+        assert!(keyed.is_empty());
+        assert!(keyed.process(&1).is_continue());
+    }
+
+    #[test]
+    fn it_creates_one_strategy_per_key_under_contention() {
+        use std::thread;
+
+        let keyed = Arc::new(Keyed::new(
+            NonZeroUsize::new(16).unwrap(),
+            Duration::from_secs(60),
+            || FixedWindow::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60)),
+        ));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let keyed = Arc::clone(&keyed);
+                thread::spawn(move || {
+                    (0..20)
+                        .filter(|_| keyed.process(&7u32).is_continue())
+                        .count()
+                })
+            })
+            .collect();
+
+        let allowed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+        // A racing insert must not hand out a second budget for the same key
+        assert_eq!(allowed, 10);
+        assert_eq!(keyed.len(), 1);
+    }
+
+    #[test]
+    fn it_evicts_recovered_keys_and_reports_state() {
+        let (clock, mock) = Clock::mock();
+        let strategy_clock = clock.clone();
+        let keyed: Keyed<u64, Gcra> = Keyed::with_clock(
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(3600),
+            move || {
+                Gcra::with_clock(
+                    NonZeroUsize::new(2).unwrap(),
+                    Duration::from_secs(1),
+                    strategy_clock.clone(),
+                )
+            },
+            clock,
+        );
+
+        assert_eq!(keyed.state(&1), None);
+        assert!(keyed.process(&1).is_continue());
+        assert_eq!(keyed.state(&1).unwrap().remaining, 1);
+
+        // Key 1 is far from idle, so key 2 overflows until key 1 has recovered
+        assert!(keyed.process(&2).is_continue());
+        assert_eq!(keyed.state(&2), None);
+        mock.increment(Duration::from_millis(500));
+        assert!(keyed.process(&2).is_continue());
+        assert_eq!(keyed.state(&1), None);
+        assert_eq!(keyed.state(&2).unwrap().remaining, 1);
+    }
+
+    #[test]
+    fn it_sweeps_a_full_map_at_most_once_per_interval() {
+        let (clock, mock) = Clock::mock();
+        let strategy_clock = clock.clone();
+        let keyed: Keyed<u64, Gcra> = Keyed::with_clock(
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(3600),
+            move || {
+                Gcra::with_clock(
+                    NonZeroUsize::new(2).unwrap(),
+                    Duration::from_secs(1),
+                    strategy_clock.clone(),
+                )
+            },
+            clock,
+        )
+        .with_sweep_interval(Duration::from_secs(1));
+
+        assert!(keyed.process(&1).is_continue());
+        assert!(keyed.process(&2).is_continue());
+        assert_eq!(keyed.state(&2), None);
+
+        // Key 1 has recovered, but the map was swept too recently to look again
+        mock.increment(Duration::from_millis(500));
+        assert!(keyed.process(&2).is_continue());
+        assert_eq!(keyed.state(&2), None);
+        assert_eq!(keyed.len(), 1);
+
+        mock.increment(Duration::from_millis(500));
+        assert!(keyed.process(&2).is_continue());
+        assert_eq!(keyed.state(&1), None);
+        assert!(keyed.state(&2).is_some());
+    }
+
+    #[test]
+    fn it_caps_keys_inserted_concurrently() {
+        use std::thread;
+
+        let keyed = Arc::new(Keyed::new(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(60),
+            || FixedWindow::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60)),
+        ));
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                let keyed = Arc::clone(&keyed);
+                thread::spawn(move || {
+                    (0..20)
+                        .filter(|key| keyed.process(&(thread * 20 + key)).is_continue())
+                        .count()
+                })
+            })
+            .collect();
+
+        let admitted: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+        // Only 10 new keys fit, and the rest share the overflow's budget of 10
+        assert_eq!(admitted, 20);
+        assert_eq!(keyed.len(), 10);
+    }
+
+    #[test]
+    fn it_restores_snapshots_of_active_keys() {
+        let factory = || Gcra::new(NonZeroUsize::new(5).unwrap(), Duration::from_secs(3_600));
+        let keyed: Keyed<String, Gcra> = Keyed::new(
+            NonZeroUsize::new(2).unwrap(),
+            Duration::from_secs(60),
+            factory,
+        );
+
+        assert!(
+            keyed
+                .process_n("alice", NonZeroUsize::new(5).unwrap())
+                .is_continue()
+        );
+        assert!(keyed.process("bob").is_continue());
+        let snapshots = keyed.snapshot();
+        assert_eq!(snapshots.len(), 2);
+
+        // After a restart, alice has not been handed a fresh quota
+        let restarted: Keyed<String, Gcra> = Keyed::new(
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(60),
+            factory,
+        );
+        let mut snapshots = snapshots;
+        snapshots.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            restarted.restore(snapshots).unwrap(),
+            1,
+            "Only one key fits"
+        );
+        assert!(restarted.process("alice").is_break());
+        assert_eq!(restarted.len(), 1);
+    }
+}
@@ -0,0 +1,415 @@
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+#[cfg(feature = "std")]
+use quanta::Clock;
+
+use crate::Backoff;
+use crate::DefaultClock;
+use crate::Quota;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::TimeSource;
+use crate::gcra::Limits;
+use crate::sync::AtomicU64;
+
+/// A Leaky Bucket (as a queue) traffic shaper.
+///
+/// Requests drain from a virtual queue at a constant rate of one every
+/// `period / rate`. Rather than admitting a burst all at once, each admitted request
+/// is told how long to wait for its turn, so bursts are smoothed into evenly spaced
+/// output. When the queue already holds `queue_depth` requests, further requests are
+/// rejected.
+///
+/// Use [`Strategy::reserve_n`] to be told the delay. [`Strategy::process_n`] only
+/// admits requests which can go immediately, without joining the queue.
+///
+/// The rate and queue depth can be changed at runtime with [`LeakyBucket::set_rate`]
+/// and [`LeakyBucket::set_queue_depth`].
+///
+/// [`Strategy::refund_n`] does nothing: a refunded request's slot stays in the queue,
+/// so that the requests around it stay evenly spaced.
+#[derive(Debug)]
+pub struct LeakyBucket<C: TimeSource = DefaultClock> {
+    /// The time (in nanoseconds) at which the queue will next be empty.
+    next_free: AtomicU64,
+    interval_ns: AtomicU64,
+    queue_depth: AtomicUsize,
+    clock: C,
+    anchor: C::Instant,
+    backoff: Backoff,
+}
+
+#[cfg(feature = "std")]
+impl LeakyBucket {
+    /// Creates a new `LeakyBucket`.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate` - The number of requests leaked per `period`.
+    /// * `period` - The duration over which `rate` requests are spread.
+    /// * `queue_depth` - The maximum number of requests waiting in the queue.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, too long, or too short for the rate. Use
+    /// [`LeakyBucket::from_quota`] to handle invalid arguments instead.
+    pub fn new(rate: NonZeroUsize, period: Duration, queue_depth: NonZeroUsize) -> Self {
+        Self::with_clock(rate, period, queue_depth, Clock::new())
+    }
+
+    /// Creates a new `LeakyBucket` which leaks `quota.limit()` requests every
+    /// `quota.period()`, and queues up to `quota.burst()` of them.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource> LeakyBucket<C> {
+    const POLICY: &'static str = "leaky_bucket";
+
+    /// Creates a new `LeakyBucket` which reads time from the supplied [`TimeSource`].
+    ///
+    /// Use `Clock::mock()` to drive the bucket deterministically in tests.
+    ///
+    /// # Panics
+    ///
+    /// See [`LeakyBucket::new`].
+    pub fn with_clock(
+        rate: NonZeroUsize,
+        period: Duration,
+        queue_depth: NonZeroUsize,
+        clock: C,
+    ) -> Self {
+        Self::from_quota_with_clock(Quota::expect_valid(rate, period, queue_depth), clock)
+    }
+
+    /// Creates a new `LeakyBucket` from a [`Quota`], which reads time from the supplied
+    /// [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
+        let anchor = clock.now();
+
+        Self {
+            next_free: AtomicU64::new(0),
+            interval_ns: AtomicU64::new(Self::interval_ns(quota)),
+            queue_depth: AtomicUsize::new(quota.burst().get()),
+            clock,
+            anchor,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Sets how the strategy waits when it keeps losing races with other threads.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Changes the rate at which requests leak out to `quota.limit()` every
+    /// `quota.period()`.
+    ///
+    /// Requests already queued are re-spaced at the new rate, so tightening the rate
+    /// delays the queue's tail and loosening it lets the queue drain sooner. The quota's
+    /// burst is ignored; change the queue depth with [`LeakyBucket::set_queue_depth`].
+    pub fn set_rate(&self, quota: Quota) {
+        let interval_ns = Self::interval_ns(quota);
+        let old_interval_ns = self.interval_ns.swap(interval_ns, Ordering::AcqRel);
+
+        // The queue is a GCRA backlog, so it is re-spaced the same way
+        let limits = Limits {
+            emission_interval_ns: interval_ns,
+            delay_tolerance_ns: 0,
+        };
+        limits.rescale(
+            &self.next_free,
+            self.clock.elapsed_ns(self.anchor),
+            old_interval_ns,
+        );
+    }
+
+    /// Changes the maximum number of requests waiting in the queue.
+    ///
+    /// Requests already queued keep their place, even if the queue is now over its
+    /// depth. New requests are rejected until it drains.
+    pub fn set_queue_depth(&self, queue_depth: NonZeroUsize) {
+        self.queue_depth.store(queue_depth.get(), Ordering::Release);
+    }
+
+    fn interval_ns(quota: Quota) -> u64 {
+        // A valid quota spreads its limit at least a nanosecond apart
+        quota.period().as_nanos() as u64 / quota.limit().get() as u64
+    }
+
+    /// The interval between requests, and the longest backlog the queue may hold, in
+    /// nanoseconds.
+    #[inline]
+    fn limits(&self) -> (u64, u64) {
+        let interval_ns = self.interval_ns.load(Ordering::Acquire);
+        let queue_depth = self.queue_depth.load(Ordering::Acquire);
+        (interval_ns, interval_ns.saturating_mul(queue_depth as u64))
+    }
+
+    /// The state of the queue at `now` when it will next be empty at `next_free`.
+    fn state_at(&self, now: u64, next_free: u64) -> State {
+        let (interval_ns, capacity_ns) = self.limits();
+        let backlog = next_free.saturating_sub(now);
+        let slack = capacity_ns.saturating_sub(backlog);
+        let remaining = slack / interval_ns;
+
+        let retry_after_ns = if remaining > 0 {
+            0
+        } else {
+            (backlog + interval_ns).saturating_sub(capacity_ns)
+        };
+
+        State {
+            limit: self.queue_depth.load(Ordering::Acquire),
+            remaining: remaining as usize,
+            reset_after: Duration::from_nanos(backlog),
+            retry_after: Duration::from_nanos(retry_after_ns),
+        }
+    }
+}
+
+impl<C: TimeSource> Strategy for LeakyBucket<C> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        match self.reserve_n(cost, Duration::ZERO) {
+            ControlFlow::Continue(_) => ControlFlow::Continue(()),
+            ControlFlow::Break(reason) => ControlFlow::Break(reason),
+        }
+    }
+
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        let (interval_ns, capacity_ns) = self.limits();
+        let increment_ns = interval_ns.saturating_mul(cost.get() as u64);
+
+        // A cost which would overflow even an empty queue can never be admitted.
+        if increment_ns > capacity_ns {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: cost.get(),
+                capacity: (capacity_ns / interval_ns) as usize,
+                policy: Self::POLICY,
+            });
+        }
+
+        let max_delay_ns = u64::try_from(max_delay.as_nanos()).unwrap_or(u64::MAX);
+        let now = self.clock.elapsed_ns(self.anchor);
+        let mut spins = 0;
+
+        loop {
+            self.backoff.snooze(spins);
+
+            let next_free = self.next_free.load(Ordering::Acquire);
+
+            // The request leaks out once everything ahead of it has
+            let start = next_free.max(now);
+            let delay = start - now;
+            let backlog = delay + increment_ns;
+
+            if backlog > capacity_ns || delay > max_delay_ns {
+                // Wait for the queue to drain enough to take the request, and for
+                // the delay to fit within what the caller will accept.
+                let wait_ns = backlog
+                    .saturating_sub(capacity_ns)
+                    .max(delay.saturating_sub(max_delay_ns));
+                return ControlFlow::Break(
+                    self.state_at(now, next_free)
+                        .overloaded(Duration::from_nanos(wait_ns), Self::POLICY),
+                );
+            }
+
+            if self
+                .next_free
+                .compare_exchange_weak(
+                    next_free,
+                    start + increment_ns,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return ControlFlow::Continue(Duration::from_nanos(delay));
+            }
+            // If CAS fails, another thread joined the queue; loop and recalculate.
+            spins += 1;
+        }
+    }
+
+    // Refunds are ignored. The bucket can't tell which slot the refunded request held,
+    // and pulling the end of the queue back would schedule new requests on top of ones
+    // already spaced out. An abandoned slot just leaves a gap in the output.
+
+    fn state(&self) -> State {
+        let now = self.clock.elapsed_ns(self.anchor);
+        let next_free = self.next_free.load(Ordering::Acquire);
+
+        self.state_at(now, next_free)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn bucket(clock: Clock) -> LeakyBucket {
+        // One request every 100ms, at most 3 queued
+        LeakyBucket::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(1),
+            NonZeroUsize::new(3).unwrap(),
+            clock,
+        )
+    }
+
+    #[test]
+    fn it_spaces_a_burst_evenly() {
+        let (clock, mock) = Clock::mock();
+        let rl = bucket(clock);
+        let max_delay = Duration::from_secs(10);
+
+        // The burst is admitted, but each request must wait for the one before it
+        for expected in [0, 100, 200] {
+            assert_eq!(
+                rl.reserve_n(NonZeroUsize::MIN, max_delay),
+                ControlFlow::Continue(Duration::from_millis(expected))
+            );
+        }
+
+        // The queue is full, and one slot drains after 100ms
+        match rl.reserve_n(NonZeroUsize::MIN, max_delay) {
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after,
+                limit,
+                remaining,
+                reset_after,
+                policy,
+            }) => {
+                assert_eq!(retry_after, Duration::from_millis(100));
+                assert_eq!(limit, 3);
+                assert_eq!(remaining, 0);
+                assert_eq!(reset_after, Duration::from_millis(300));
+                assert_eq!(policy, "leaky_bucket");
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+
+        mock.increment(Duration::from_millis(100));
+        assert_eq!(
+            rl.reserve_n(NonZeroUsize::MIN, max_delay),
+            ControlFlow::Continue(Duration::from_millis(200))
+        );
+    }
+
+    #[test]
+    fn it_only_processes_requests_which_need_no_delay() {
+        let (clock, mock) = Clock::mock();
+        let rl = bucket(clock);
+
+        assert!(rl.process().is_continue());
+        // Would have to queue for 100ms
+        assert_eq!(
+            rl.process(),
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::from_millis(100),
+                limit: 3,
+                remaining: 2,
+                reset_after: Duration::from_millis(100),
+                policy: "leaky_bucket",
+            })
+        );
+
+        mock.increment(Duration::from_millis(100));
+        assert!(rl.process().is_continue());
+    }
+
+    #[test]
+    fn it_respects_the_callers_max_delay() {
+        let (clock, _mock) = Clock::mock();
+        let rl = bucket(clock);
+
+        assert!(rl.process().is_continue());
+        assert!(
+            rl.reserve_n(NonZeroUsize::MIN, Duration::from_millis(150))
+                .is_continue()
+        );
+
+        // Third in line would wait 200ms, which is 50ms too long
+        match rl.reserve_n(NonZeroUsize::MIN, Duration::from_millis(150)) {
+            ControlFlow::Break(Reason::Overloaded { retry_after, .. }) => {
+                assert_eq!(retry_after, Duration::from_millis(50));
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+        // The rejection must not have joined the queue
+        assert_eq!(rl.state().reset_after, Duration::from_millis(200));
+    }
+
+    #[test]
+    fn it_keeps_the_spacing_when_a_request_is_refunded() {
+        let (clock, _mock) = Clock::mock();
+        let rl = bucket(clock);
+        let max_delay = Duration::from_secs(10);
+
+        for expected in [0, 100] {
+            assert_eq!(
+                rl.reserve_n(NonZeroUsize::MIN, max_delay),
+                ControlFlow::Continue(Duration::from_millis(expected))
+            );
+        }
+
+        // Whichever request gave up its slot, the next one must not share a slot
+        // with the one still queued
+        rl.refund_n(NonZeroUsize::MIN);
+        assert_eq!(
+            rl.reserve_n(NonZeroUsize::MIN, max_delay),
+            ControlFlow::Continue(Duration::from_millis(200))
+        );
+    }
+
+    #[test]
+    fn it_rejects_costs_above_queue_depth() {
+        let (clock, _mock) = Clock::mock();
+        let rl = bucket(clock);
+
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(4).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: 4,
+                capacity: 3,
+                policy: "leaky_bucket",
+            })
+        );
+        // A cost of 3 fills the queue, delaying the next request by 300ms
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        assert_eq!(rl.state().remaining, 0);
+    }
+
+    #[test]
+    fn it_reconfigures_without_losing_state() {
+        let (clock, _mock) = Clock::mock();
+        let rl = bucket(clock);
+        let max_delay = Duration::from_secs(10);
+
+        assert!(rl.process_n(NonZeroUsize::new(2).unwrap()).is_continue());
+
+        // Halving the rate re-spaces the 2 queued requests 200ms apart
+        rl.set_rate(Quota::per_second(5).unwrap());
+        assert_eq!(rl.state().reset_after, Duration::from_millis(400));
+        assert_eq!(
+            rl.reserve_n(NonZeroUsize::MIN, max_delay),
+            ControlFlow::Continue(Duration::from_millis(400))
+        );
+
+        // A shallower queue keeps what is queued but admits nothing more
+        rl.set_queue_depth(NonZeroUsize::new(2).unwrap());
+        let state = rl.state();
+        assert_eq!(state.limit, 2);
+        assert_eq!(state.remaining, 0);
+        assert!(rl.reserve_n(NonZeroUsize::MIN, max_delay).is_break());
+    }
+}
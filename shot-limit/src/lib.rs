@@ -11,9 +11,31 @@
 //! ## Key Concepts
 //!
 //! * **Lock-Free**: No `Mutex` or `RwLock` in the hot path.
+//! * **Linearizable**: [`TokenBucket`], [`Gcra`] and [`SlidingWindow`] decide each request
+//!   with a single atomic state transition, so racing requests are admitted exactly as
+//!   if they had arrived one at a time, and never see each other's half-made updates.
+//!   These claims are model-checked with `loom`.
 //! * **Lazy Evaluation**: Tokens and windows are recalculated at the moment of the request,
 //!   eliminating the need for background worker threads or timers.
-//! * **Strategy Trait**: A unified interface for different limiting algorithms.
+//! * **Strategy Trait**: A unified interface for different limiting algorithms, covering
+//!   both request rates and (with [`ConcurrencyLimit`] and [`Permit`]) work in flight.
+//! * **Pluggable Time**: Strategies read time through a [`TimeSource`]. The default is a
+//!   TSC-based `quanta::Clock`; every strategy also has a `with_clock` constructor.
+//!
+//! ## Feature Flags
+//!
+//! - `std` (default): Enables the `quanta::Clock` default, [`Keyed`], [`Hierarchy`],
+//!   [`ShardedTokenBucket`], [`CalendarQuota`] and snapshots.
+//!   Without it the crate is `no_std`, needing only `alloc` and 64-bit atomics, and
+//!   strategies are built with `with_clock` from a [`TimeSource`] such as
+//!   [`ManualClock`].
+//! - `tokio`: Enables [`TokioClock`], which follows tokio's (possibly paused) virtual time,
+//!   and `AsyncAcquire`, which waits for capacity from any strategy.
+//! - `serde`: Enables `StrategyConfig`, which builds strategies from configuration files.
+//! - `shared`: Enables `SharedGcra`, a limit shared between processes through a
+//!   memory-mapped file.
+//! - `tz`: Enables `CalendarQuota::with_time_zone`, which aligns boundaries to a
+//!   [`jiff`](https://docs.rs/jiff) time zone through its daylight saving time.
 //!
 //! ## Example
 //!
@@ -32,24 +54,209 @@
 //! }
 //! ```
 
-use std::fmt::Debug;
-use std::ops::ControlFlow;
-use std::time::Duration;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use core::fmt::Debug;
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::time::Duration;
 
+#[cfg(feature = "tokio")]
+mod acquire;
+mod aimd;
+mod backoff;
+#[cfg(feature = "std")]
+mod calendar;
+mod clock;
+mod combinators;
+mod concurrency;
+#[cfg(feature = "serde")]
+mod config;
+mod feedback;
 mod fixed_window;
+mod float;
 mod gcra;
+#[cfg(feature = "std")]
+mod hierarchy;
+#[cfg(feature = "std")]
+mod keyed;
+mod leaky_bucket;
+mod permit;
+mod quota;
+#[cfg(feature = "std")]
+mod sharded;
+#[cfg(feature = "shared")]
+mod shared;
+mod sliding_log;
 mod sliding_window;
+#[cfg(feature = "std")]
+mod snapshot;
+mod sync;
 mod token_bucket;
+mod vegas;
 
+#[cfg(feature = "tokio")]
+pub use acquire::Acquire;
+#[cfg(feature = "tokio")]
+pub use acquire::AsyncAcquire;
+pub use aimd::AdjustableRate;
+pub use aimd::Aimd;
+pub use backoff::Backoff;
+#[cfg(feature = "std")]
+pub use calendar::Calendar;
+#[cfg(feature = "std")]
+pub use calendar::CalendarQuota;
+pub use clock::DefaultClock;
+pub use clock::ManualClock;
+pub use clock::TimeSource;
+#[cfg(feature = "tokio")]
+pub use clock::TokioClock;
+pub use combinators::AllOf;
+pub use combinators::AnyOf;
+pub use combinators::FirstOf;
+pub use combinators::Named;
+pub use concurrency::ConcurrencyLimit;
+#[cfg(feature = "serde")]
+pub use config::StrategyConfig;
+pub use feedback::Feedback;
+pub use feedback::Outcome;
 pub use fixed_window::FixedWindow;
 pub use gcra::Gcra;
+#[cfg(feature = "std")]
+pub use hierarchy::Hierarchy;
+#[cfg(feature = "std")]
+pub use keyed::Keyed;
+pub use leaky_bucket::LeakyBucket;
+pub use permit::Permit;
+pub use quota::Quota;
+pub use quota::QuotaError;
+#[cfg(feature = "std")]
+pub use sharded::ShardedTokenBucket;
+#[cfg(feature = "shared")]
+pub use shared::SharedGcra;
+pub use sliding_log::SlidingLog;
 pub use sliding_window::SlidingWindow;
+#[cfg(feature = "std")]
+pub use snapshot::Persist;
+#[cfg(feature = "std")]
+pub use snapshot::Snapshot;
+#[cfg(feature = "std")]
+pub use snapshot::SnapshotError;
 pub use token_bucket::TokenBucket;
+pub use vegas::Vegas;
 
 /// Reasons why a request might be rejected by a strategy.
-#[derive(Debug, PartialEq)]
+///
+/// Every variant carries a `policy` identifying the strategy (or named policy) which
+/// made the decision. New variants may be added in future releases.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Reason {
-    Overloaded { retry_after: Duration },
+    /// The rate limit has been reached. Enough capacity for the request will be
+    /// available again after `retry_after`.
+    Overloaded {
+        /// Time until the rejected request would be admitted.
+        retry_after: Duration,
+        /// The maximum number of permits that can be available at once.
+        limit: usize,
+        /// The number of permits available when the request was rejected.
+        remaining: usize,
+        /// Time until the strategy has fully recovered to `limit` permits.
+        reset_after: Duration,
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+    /// The requested cost can never be satisfied because it is larger than
+    /// the strategy's capacity. Retrying will not help.
+    CostExceedsCapacity {
+        /// The cost of the rejected request.
+        cost: usize,
+        /// The largest cost the strategy could ever admit.
+        capacity: usize,
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+    /// A quota has been used up and will not be replenished until a calendar
+    /// boundary (e.g. midnight or the first of the month).
+    QuotaExhausted {
+        /// Time until the quota resets at the next boundary.
+        retry_after: Duration,
+        /// The size of the quota.
+        limit: usize,
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+    /// Requests have been administratively blocked. Retrying will not help until
+    /// the block is lifted.
+    Blocked {
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+}
+
+impl Reason {
+    /// Time until a retry could succeed, or `None` if retrying will not help.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Overloaded { retry_after, .. } | Self::QuotaExhausted { retry_after, .. } => {
+                Some(*retry_after)
+            }
+            Self::CostExceedsCapacity { .. } | Self::Blocked { .. } => None,
+        }
+    }
+
+    /// Returns the same rejection, attributed to `policy` instead.
+    pub(crate) fn with_policy(mut self, policy: &'static str) -> Self {
+        match &mut self {
+            Self::Overloaded { policy: p, .. }
+            | Self::CostExceedsCapacity { policy: p, .. }
+            | Self::QuotaExhausted { policy: p, .. }
+            | Self::Blocked { policy: p } => *p = policy,
+        }
+        self
+    }
+
+    /// Identifies the policy which rejected the request.
+    pub fn policy(&self) -> &'static str {
+        match self {
+            Self::Overloaded { policy, .. }
+            | Self::CostExceedsCapacity { policy, .. }
+            | Self::QuotaExhausted { policy, .. }
+            | Self::Blocked { policy } => policy,
+        }
+    }
+}
+
+/// A point-in-time view of a strategy's capacity, obtained without consuming anything.
+///
+/// This is intended for dashboards, `RateLimit-*` style response headers and upstream
+/// admission decisions. Because other threads may be processing requests concurrently,
+/// the values are advisory and may be stale by the time they are read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct State {
+    /// The maximum number of permits that can be available at once.
+    pub limit: usize,
+    /// The number of permits that could be consumed right now.
+    pub remaining: usize,
+    /// Time until the strategy has fully recovered to `limit` permits.
+    pub reset_after: Duration,
+    /// Time until the next permit becomes available. Zero if `remaining > 0`.
+    pub retry_after: Duration,
+}
+
+impl State {
+    /// Builds a `Reason::Overloaded` for a request rejected while in this state.
+    pub(crate) fn overloaded(self, retry_after: Duration, policy: &'static str) -> Reason {
+        Reason::Overloaded {
+            retry_after,
+            limit: self.limit,
+            remaining: self.remaining,
+            reset_after: self.reset_after,
+            policy,
+        }
+    }
 }
 
 /// The core trait for all rate-limiting algorithms.
@@ -65,5 +272,64 @@ pub trait Strategy: Debug + Send + Sync {
     /// # Errors
     ///
     /// Returns `Reason` if the rate limit has been reached.
-    fn process(&self) -> ControlFlow<Reason>;
+    fn process(&self) -> ControlFlow<Reason> {
+        self.process_n(NonZeroUsize::MIN)
+    }
+
+    /// Attempts to process a request which consumes `cost` units of capacity.
+    ///
+    /// This is useful for charging expensive requests (e.g. batch endpoints or
+    /// uploads) more than cheap ones. The whole cost is consumed, or nothing is.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Reason::Overloaded` if `cost` units are not yet available, with a
+    /// `retry_after` indicating when they will be. Returns
+    /// `Reason::CostExceedsCapacity` if `cost` is larger than the strategy could
+    /// ever allow.
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason>;
+
+    /// Reserves capacity for a request which consumes `cost` units, returning how long
+    /// the caller must delay the request before sending it.
+    ///
+    /// Shaping strategies such as [`LeakyBucket`] admit bursts by queueing them, and
+    /// will not hand out a delay longer than `max_delay`. Other strategies never delay
+    /// admitted requests, so by default this is [`Strategy::process_n`] with a zero delay.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Strategy::process_n`]. A `retry_after` accounts for `max_delay`.
+    fn reserve_n(&self, cost: NonZeroUsize, max_delay: Duration) -> ControlFlow<Reason, Duration> {
+        let _ = max_delay;
+        match self.process_n(cost) {
+            ControlFlow::Continue(()) => ControlFlow::Continue(Duration::ZERO),
+            ControlFlow::Break(reason) => ControlFlow::Break(reason),
+        }
+    }
+
+    /// Returns `cost` units of capacity once the work admitted by
+    /// [`Strategy::process_n`] has finished.
+    ///
+    /// Concurrency limits such as [`ConcurrencyLimit`] count work in flight, so need to
+    /// know when it ends. Rate-based strategies don't, so by default this does nothing.
+    /// Prefer holding a [`Permit`], which calls this when dropped.
+    fn release_n(&self, cost: NonZeroUsize) {
+        let _ = cost;
+    }
+
+    /// Gives back `cost` units consumed by a request which was admitted but never
+    /// performed, e.g. because a later check rejected it or it was cancelled.
+    ///
+    /// Refunds are best effort: capacity never exceeds the limit, and a refund which
+    /// races with a window rotation may land in the new window. Strategies which cannot
+    /// forget an admission keep the default, which does nothing.
+    fn refund_n(&self, cost: NonZeroUsize) {
+        let _ = cost;
+    }
+
+    /// Inspects the current limit, remaining permits and recovery times.
+    ///
+    /// Unlike [`Strategy::process`], this never consumes a permit or otherwise
+    /// changes the strategy's state.
+    fn state(&self) -> State;
 }
@@ -0,0 +1,284 @@
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::ops::Deref;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+use crate::Reason;
+use crate::Strategy;
+
+/// Capacity admitted by a strategy, which is released back to it when dropped.
+///
+/// Rate-based strategies ignore the release, but concurrency limits such as
+/// [`ConcurrencyLimit`](crate::ConcurrencyLimit) use it to count the work in flight,
+/// so hold the permit for as long as the work runs. If the work is abandoned before it
+/// starts, call [`Permit::refund`] instead, so rate-based strategies get their capacity
+/// back too.
+///
+/// `P` is any pointer to a strategy, e.g. `&S` or `Arc<S>`.
+#[derive(Debug)]
+#[must_use = "the permit is released as soon as it is dropped"]
+pub struct Permit<P>
+where
+    P: Deref<Target: Strategy>,
+{
+    strategy: P,
+    cost: NonZeroUsize,
+    /// Whether to refund rather than release when dropped.
+    refund: bool,
+}
+
+impl<P> Permit<P>
+where
+    P: Deref<Target: Strategy>,
+{
+    /// Attempts to acquire a permit for a single request.
+    ///
+    /// # Errors
+    ///
+    /// See [`Strategy::process`].
+    pub fn acquire(strategy: P) -> ControlFlow<Reason, Self> {
+        Self::acquire_n(strategy, NonZeroUsize::MIN)
+    }
+
+    /// Attempts to acquire a permit for a request which consumes `cost` units.
+    ///
+    /// # Errors
+    ///
+    /// See [`Strategy::process_n`].
+    pub fn acquire_n(strategy: P, cost: NonZeroUsize) -> ControlFlow<Reason, Self> {
+        strategy.process_n(cost)?;
+        ControlFlow::Continue(Self::new(strategy, cost))
+    }
+
+    /// Attempts to reserve a permit for a request which consumes `cost` units,
+    /// together with how long to delay the request before sending it.
+    ///
+    /// # Errors
+    ///
+    /// See [`Strategy::reserve_n`].
+    pub fn reserve_n(
+        strategy: P,
+        cost: NonZeroUsize,
+        max_delay: Duration,
+    ) -> ControlFlow<Reason, (Self, Duration)> {
+        let delay = strategy.reserve_n(cost, max_delay)?;
+        ControlFlow::Continue((Self::new(strategy, cost), delay))
+    }
+
+    /// Blocks the thread until the strategy admits a request which consumes `cost`
+    /// units, or until `timeout` has passed.
+    ///
+    /// The thread is parked for each rejection's `retry_after`, and for the delay
+    /// handed out by shaping strategies such as [`LeakyBucket`](crate::LeakyBucket).
+    /// Waking early, whether spuriously or through [`Thread::unpark`](thread::Thread::unpark),
+    /// just parks the thread again.
+    ///
+    /// # Errors
+    ///
+    /// Returns the rejection straight away if waiting will never help, including when
+    /// its `retry_after` is too long to represent, or if the request would not be
+    /// admitted within `timeout`.
+    #[cfg(feature = "std")]
+    pub fn acquire_blocking(
+        strategy: P,
+        cost: NonZeroUsize,
+        timeout: Option<Duration>,
+    ) -> ControlFlow<Reason, Self> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+        loop {
+            let now = Instant::now();
+            let max_delay = deadline.map_or(Duration::MAX, |deadline| {
+                deadline.saturating_duration_since(now)
+            });
+
+            match strategy.reserve_n(cost, max_delay) {
+                ControlFlow::Continue(delay) => {
+                    let permit = Self::new(strategy, cost);
+                    park_for(delay);
+                    return ControlFlow::Continue(permit);
+                }
+                ControlFlow::Break(reason) => {
+                    // Give up if waiting will never help, or will take too long
+                    let Some(retry_after) = reason.retry_after() else {
+                        return ControlFlow::Break(reason);
+                    };
+                    // A retry too far off to represent will never come
+                    let Some(retry_at) = now.checked_add(retry_after) else {
+                        return ControlFlow::Break(reason);
+                    };
+                    if deadline.is_some_and(|deadline| retry_at > deadline) {
+                        return ControlFlow::Break(reason);
+                    }
+                    park_for(retry_after);
+                }
+            }
+        }
+    }
+
+    /// Gives the capacity back as if the request had never been admitted.
+    ///
+    /// Use this when the request is abandoned before it is performed, e.g. because it
+    /// was cancelled while waiting. See [`Strategy::refund_n`].
+    pub fn refund(mut self) {
+        self.refund = true;
+    }
+
+    fn new(strategy: P, cost: NonZeroUsize) -> Self {
+        Self {
+            strategy,
+            cost,
+            refund: false,
+        }
+    }
+
+    /// The units of capacity held by this permit.
+    pub fn cost(&self) -> NonZeroUsize {
+        self.cost
+    }
+}
+
+impl<P> Drop for Permit<P>
+where
+    P: Deref<Target: Strategy>,
+{
+    fn drop(&mut self) {
+        if self.refund {
+            self.strategy.refund_n(self.cost);
+        } else {
+            self.strategy.release_n(self.cost);
+        }
+    }
+}
+
+/// Parks the thread for `duration`, parking it again whenever it wakes early.
+#[cfg(feature = "std")]
+fn park_for(duration: Duration) {
+    let start = Instant::now();
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            return;
+        }
+        thread::park_timeout(duration - elapsed);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use quanta::Clock;
+
+    use super::*;
+    use crate::ConcurrencyLimit;
+    use crate::Quota;
+    use crate::State;
+    use crate::TokenBucket;
+
+    #[test]
+    fn it_refunds_abandoned_requests() {
+        let (clock, _mock) = Clock::mock();
+        let rl = TokenBucket::with_clock(
+            NonZeroUsize::new(2).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+
+        // A finished request keeps its token...
+        drop(Permit::acquire(&rl).continue_value().unwrap());
+        assert_eq!(rl.state().remaining, 1);
+
+        // ...but an abandoned one gives it back
+        Permit::acquire(&rl).continue_value().unwrap().refund();
+        assert_eq!(rl.state().remaining, 1);
+    }
+
+    #[test]
+    fn it_releases_concurrency_either_way() {
+        let rl = ConcurrencyLimit::new(NonZeroUsize::new(1).unwrap());
+
+        drop(Permit::acquire(&rl).continue_value().unwrap());
+        assert_eq!(rl.in_flight(), 0);
+
+        Permit::acquire(&rl).continue_value().unwrap().refund();
+        assert_eq!(rl.in_flight(), 0);
+    }
+
+    #[test]
+    fn it_blocks_until_admitted() {
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicBool;
+        use std::sync::atomic::Ordering;
+        use std::thread;
+        use std::time::Instant;
+
+        let rl = TokenBucket::from_quota(Quota::new(1, Duration::from_millis(100)).unwrap());
+        drop(Permit::acquire(&rl).continue_value().unwrap());
+
+        // Waiting for the next token takes longer than the timeout, so fails at once
+        let start = Instant::now();
+        let timeout = Some(Duration::from_millis(20));
+        assert!(Permit::acquire_blocking(&rl, NonZeroUsize::MIN, timeout).is_break());
+        assert!(start.elapsed() < Duration::from_millis(20));
+
+        // Keep waking the waiter early, which must not let it through too soon
+        let waiter = thread::current();
+        let done = Arc::new(AtomicBool::new(false));
+        let waker = thread::spawn({
+            let done = Arc::clone(&done);
+            move || {
+                while !done.load(Ordering::Acquire) {
+                    waiter.unpark();
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+        });
+
+        let timeout = Some(Duration::from_secs(1));
+        let permit = Permit::acquire_blocking(&rl, NonZeroUsize::MIN, timeout);
+        done.store(true, Ordering::Release);
+        waker.join().unwrap();
+
+        assert!(permit.is_continue());
+        assert!(start.elapsed() >= Duration::from_millis(90));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    /// Rejects everything, with a `retry_after` too long to wait for.
+    #[derive(Debug)]
+    struct Stalled;
+
+    impl Strategy for Stalled {
+        fn process_n(&self, _cost: NonZeroUsize) -> ControlFlow<Reason> {
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::MAX,
+                limit: 1,
+                remaining: 0,
+                reset_after: Duration::MAX,
+                policy: "stalled",
+            })
+        }
+
+        fn state(&self) -> State {
+            State {
+                limit: 1,
+                remaining: 0,
+                reset_after: Duration::MAX,
+                retry_after: Duration::MAX,
+            }
+        }
+    }
+
+    #[test]
+    fn it_gives_up_on_a_retry_too_far_off_to_represent() {
+        // Without a timeout, this would otherwise park the thread for good
+        let reason = Permit::acquire_blocking(&Stalled, NonZeroUsize::MIN, None)
+            .break_value()
+            .unwrap();
+        assert_eq!(reason.policy(), "stalled");
+    }
+}
@@ -0,0 +1,239 @@
+use core::fmt;
+use core::num::NonZeroUsize;
+use core::time::Duration;
+
+/// A validated rate: `limit` requests every `period`, with bursts of up to `burst`.
+///
+/// Every rate-based strategy can be built from a `Quota` with its `from_quota`
+/// constructor. Unlike the raw constructors, which panic on nonsensical arguments,
+/// building a `Quota` reports them as a [`QuotaError`]:
+///
+/// ```rust
+/// use shot_limit::Gcra;
+/// use shot_limit::Quota;
+///
+/// let quota = Quota::per_second(100)?.with_burst(10)?;
+/// let gcra = Gcra::from_quota(quota);
+/// # Ok::<(), shot_limit::QuotaError>(())
+/// ```
+///
+/// The burst defaults to the limit. Window-based strategies always allow their whole
+/// limit at once, so they ignore it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Quota {
+    limit: NonZeroUsize,
+    period: Duration,
+    burst: NonZeroUsize,
+}
+
+/// Reasons why a [`Quota`] is invalid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum QuotaError {
+    /// A count which must be positive is zero.
+    Zero {
+        /// The name of the argument, or of the configuration key.
+        field: &'static str,
+    },
+    /// The period is zero, or too long to represent in nanoseconds.
+    InvalidPeriod {
+        /// The requested period.
+        period: Duration,
+    },
+    /// The period is too short to spread `limit` requests across, since each must be at
+    /// least a nanosecond apart.
+    PeriodTooShort {
+        /// The requested limit.
+        limit: usize,
+        /// The requested period.
+        period: Duration,
+    },
+    /// The lowest limit of a range is above the highest.
+    InvertedBounds {
+        /// The requested lowest limit.
+        min: usize,
+        /// The requested highest limit.
+        max: usize,
+    },
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Zero { field } => write!(f, "{field} must be greater than zero"),
+            Self::InvalidPeriod { period } => write!(f, "invalid period {period:?}"),
+            Self::PeriodTooShort { limit, period } => {
+                write!(f, "period {period:?} is too short for a limit of {limit}")
+            }
+            Self::InvertedBounds { min, max } => write!(f, "min {min} exceeds max {max}"),
+        }
+    }
+}
+
+impl core::error::Error for QuotaError {}
+
+impl Quota {
+    /// A quota of `limit` requests every `period`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`QuotaError`] if `limit` is zero, or `period` is zero, longer than
+    /// `u64::MAX` nanoseconds, or shorter than `limit` nanoseconds.
+    pub fn new(limit: usize, period: Duration) -> Result<Self, QuotaError> {
+        let limit = non_zero("limit", limit)?;
+        Self::check(limit, period)?;
+
+        Ok(Self {
+            limit,
+            period,
+            burst: limit,
+        })
+    }
+
+    /// A quota of `limit` requests every second.
+    ///
+    /// # Errors
+    ///
+    /// See [`Quota::new`].
+    pub fn per_second(limit: usize) -> Result<Self, QuotaError> {
+        Self::new(limit, Duration::from_secs(1))
+    }
+
+    /// A quota of `limit` requests every minute.
+    ///
+    /// # Errors
+    ///
+    /// See [`Quota::new`].
+    pub fn per_minute(limit: usize) -> Result<Self, QuotaError> {
+        Self::new(limit, Duration::from_secs(60))
+    }
+
+    /// Spreads the same limit over `period` instead.
+    ///
+    /// # Errors
+    ///
+    /// See [`Quota::new`].
+    pub fn with_period(mut self, period: Duration) -> Result<Self, QuotaError> {
+        Self::check(self.limit, period)?;
+        self.period = period;
+        Ok(self)
+    }
+
+    /// Sets how many requests may be admitted at once, after a quiet spell.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`QuotaError`] if `burst` is zero.
+    pub fn with_burst(mut self, burst: usize) -> Result<Self, QuotaError> {
+        self.burst = non_zero("burst", burst)?;
+        Ok(self)
+    }
+
+    /// The number of requests allowed per period.
+    pub fn limit(&self) -> NonZeroUsize {
+        self.limit
+    }
+
+    /// The period over which the limit applies.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The number of requests which may be admitted at once.
+    pub fn burst(&self) -> NonZeroUsize {
+        self.burst
+    }
+
+    /// Builds the quota for a raw constructor, which panics on invalid arguments.
+    pub(crate) fn expect_valid(limit: NonZeroUsize, period: Duration, burst: NonZeroUsize) -> Self {
+        if let Err(e) = Self::check(limit, period) {
+            panic!("{e}");
+        }
+
+        Self {
+            limit,
+            period,
+            burst,
+        }
+    }
+
+    fn check(limit: NonZeroUsize, period: Duration) -> Result<(), QuotaError> {
+        let period_ns = u64::try_from(period.as_nanos())
+            .ok()
+            .filter(|ns| *ns > 0)
+            .ok_or(QuotaError::InvalidPeriod { period })?;
+
+        if period_ns < limit.get() as u64 {
+            return Err(QuotaError::PeriodTooShort {
+                limit: limit.get(),
+                period,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn non_zero(field: &'static str, value: usize) -> Result<NonZeroUsize, QuotaError> {
+    NonZeroUsize::new(value).ok_or(QuotaError::Zero { field })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn it_defaults_the_burst_to_the_limit() {
+        let quota = Quota::per_minute(30).unwrap();
+        assert_eq!(quota.limit().get(), 30);
+        assert_eq!(quota.period(), Duration::from_secs(60));
+        assert_eq!(quota.burst().get(), 30);
+
+        let quota = quota
+            .with_period(Duration::from_secs(10))
+            .unwrap()
+            .with_burst(5)
+            .unwrap();
+        assert_eq!(quota.limit().get(), 30);
+        assert_eq!(quota.period(), Duration::from_secs(10));
+        assert_eq!(quota.burst().get(), 5);
+    }
+
+    #[test]
+    fn it_rejects_invalid_quotas() {
+        assert_eq!(
+            Quota::per_second(0).unwrap_err(),
+            QuotaError::Zero { field: "limit" }
+        );
+        assert_eq!(
+            Quota::per_second(1).unwrap().with_burst(0).unwrap_err(),
+            QuotaError::Zero { field: "burst" }
+        );
+        assert_eq!(
+            Quota::new(1, Duration::ZERO).unwrap_err(),
+            QuotaError::InvalidPeriod {
+                period: Duration::ZERO
+            }
+        );
+        assert_eq!(
+            Quota::new(1, Duration::MAX).unwrap_err(),
+            QuotaError::InvalidPeriod {
+                period: Duration::MAX
+            }
+        );
+
+        // More than one request per nanosecond cannot be spread out
+        let err = Quota::per_second(1_000_000_001).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "period 1s is too short for a limit of 1000000001"
+        );
+        assert!(
+            Quota::per_second(1_000_000_000)
+                .unwrap()
+                .with_period(Duration::from_nanos(999_999_999))
+                .is_err()
+        );
+    }
+}
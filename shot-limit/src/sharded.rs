@@ -0,0 +1,305 @@
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+
+use quanta::Clock;
+
+use crate::DefaultClock;
+use crate::Quota;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::TimeSource;
+use crate::TokenBucket;
+
+/// A [`TokenBucket`] split into independent shards, so that threads on many cores do
+/// not all contend on the same cache line.
+///
+/// Each thread is given a home shard, and shards share out the capacity and the
+/// refill rate evenly, so the shards together admit exactly the bucket's limit. When
+/// its home shard is empty, a request steals from up to
+/// [`ShardedTokenBucket::with_steal`] other shards before it is rejected.
+///
+/// This trades accuracy for scaling:
+///
+/// * A request is rejected if the shards it tries are empty, even while other
+///   shards still hold tokens. Stealing from every shard, the default, only rejects
+///   requests when the whole bucket is empty, at the cost of touching more cache
+///   lines when it is.
+/// * A single request can cost no more than one shard holds, and refunds go back to
+///   the home shard.
+#[derive(Debug)]
+pub struct ShardedTokenBucket<C: TimeSource + Clone = DefaultClock> {
+    shards: Box<[Shard<C>]>,
+    steal: usize,
+    quota: Quota,
+    clock: C,
+}
+
+/// A shard, padded to its own cache lines (two, for CPUs which prefetch in pairs).
+#[derive(Debug)]
+#[repr(align(128))]
+struct Shard<C: TimeSource>(TokenBucket<C>);
+
+impl ShardedTokenBucket {
+    /// Creates a new `ShardedTokenBucket` with one shard per available CPU.
+    ///
+    /// The arguments are as for [`TokenBucket::new`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, too long, or too short for the limit. Use
+    /// [`ShardedTokenBucket::from_quota`] to handle invalid arguments instead.
+    pub fn new(capacity: NonZeroUsize, increment: NonZeroUsize, period: Duration) -> Self {
+        Self::with_clock(capacity, increment, period, Clock::new())
+    }
+
+    /// Creates a new `ShardedTokenBucket` with one shard per available CPU, which adds
+    /// `quota.limit()` tokens every `quota.period()` and holds up to `quota.burst()`.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource + Clone> ShardedTokenBucket<C> {
+    const POLICY: &'static str = "sharded_token_bucket";
+
+    /// Creates a new `ShardedTokenBucket` which reads time from the supplied
+    /// [`TimeSource`].
+    ///
+    /// # Panics
+    ///
+    /// See [`ShardedTokenBucket::new`].
+    pub fn with_clock(
+        capacity: NonZeroUsize,
+        increment: NonZeroUsize,
+        period: Duration,
+        clock: C,
+    ) -> Self {
+        let quota = Quota::expect_valid(increment, period, capacity);
+        Self::from_quota_with_clock(quota, clock)
+    }
+
+    /// Creates a new `ShardedTokenBucket` from a [`Quota`], which reads time from the
+    /// supplied [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
+        let shards = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+
+        let mut bucket = Self {
+            shards: Box::new([]),
+            steal: usize::MAX,
+            quota,
+            clock,
+        };
+        bucket.split(shards);
+        bucket
+    }
+
+    /// Splits the bucket into `shards` shards, or one per token if it holds fewer.
+    ///
+    /// This refills the bucket, so set it up before use.
+    pub fn with_shards(mut self, shards: NonZeroUsize) -> Self {
+        self.split(shards);
+        self
+    }
+
+    /// Sets how many shards besides its home shard a request tries before it is
+    /// rejected.
+    ///
+    /// Zero keeps every thread to its home shard, which scales best but rejects
+    /// requests while other shards hold tokens. The default tries every shard.
+    pub fn with_steal(mut self, shards: usize) -> Self {
+        self.steal = shards;
+        self
+    }
+
+    /// The number of shards the bucket is split into.
+    pub fn shards(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.shards.len()).expect("a bucket has at least one shard")
+    }
+
+    fn split(&mut self, shards: NonZeroUsize) {
+        let burst = self.quota.burst().get();
+        let shards = shards.get().min(burst);
+
+        // Each shard refills `shards` times slower, so together they refill at the
+        // full rate.
+        let period = self
+            .quota
+            .period()
+            .saturating_mul(u32::try_from(shards).unwrap_or(u32::MAX));
+
+        self.shards = (0..shards)
+            .map(|i| {
+                let capacity = burst / shards + usize::from(i < burst % shards);
+                let quota = Quota::expect_valid(
+                    self.quota.limit(),
+                    period,
+                    NonZeroUsize::new(capacity).expect("every shard holds a token"),
+                );
+                Shard(TokenBucket::from_quota_with_clock(
+                    quota,
+                    self.clock.clone(),
+                ))
+            })
+            .collect();
+    }
+
+    /// The shard the current thread uses first.
+    #[inline]
+    fn home(&self) -> usize {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        thread_local! {
+            static HOME: usize = NEXT.fetch_add(1, Ordering::Relaxed);
+        }
+
+        HOME.with(|home| *home) % self.shards.len()
+    }
+}
+
+impl<C: TimeSource + Clone> Strategy for ShardedTokenBucket<C> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let home = self.home();
+        let tries = self.steal.min(self.shards.len() - 1) + 1;
+        let mut state = EMPTY;
+        let mut retry_after = Duration::MAX;
+
+        for i in 0..self.shards.len() {
+            let shard = &self.shards[(home + i) % self.shards.len()].0;
+            if i >= tries {
+                // Untried shards only count towards the rejection's totals
+                add(&mut state, shard.state());
+                continue;
+            }
+
+            match shard.process_n(cost) {
+                ControlFlow::Continue(()) => return ControlFlow::Continue(()),
+                ControlFlow::Break(Reason::Overloaded {
+                    retry_after: after,
+                    limit,
+                    remaining,
+                    reset_after,
+                    ..
+                }) => {
+                    retry_after = retry_after.min(after);
+                    add(
+                        &mut state,
+                        State {
+                            limit,
+                            remaining,
+                            reset_after,
+                            retry_after: after,
+                        },
+                    );
+                }
+                ControlFlow::Break(reason) => {
+                    return ControlFlow::Break(reason.with_policy(Self::POLICY));
+                }
+            }
+        }
+
+        ControlFlow::Break(state.overloaded(retry_after, Self::POLICY))
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.shards[self.home()].0.refund_n(cost);
+    }
+
+    fn state(&self) -> State {
+        let mut state = EMPTY;
+        for shard in &self.shards {
+            add(&mut state, shard.0.state());
+        }
+        state
+    }
+}
+
+/// The state of a bucket without any shards, to [`add`] shards to.
+const EMPTY: State = State {
+    limit: 0,
+    remaining: 0,
+    reset_after: Duration::ZERO,
+    retry_after: Duration::MAX,
+};
+
+/// Adds a shard's state to the state of the whole bucket.
+fn add(total: &mut State, shard: State) {
+    total.limit += shard.limit;
+    total.remaining += shard.remaining;
+    total.reset_after = total.reset_after.max(shard.reset_after);
+    total.retry_after = total.retry_after.min(shard.retry_after);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(clock: Clock, capacity: usize, shards: usize) -> ShardedTokenBucket {
+        ShardedTokenBucket::with_clock(
+            NonZeroUsize::new(capacity).unwrap(),
+            NonZeroUsize::new(capacity).unwrap(),
+            Duration::from_secs(1),
+            clock,
+        )
+        .with_shards(NonZeroUsize::new(shards).unwrap())
+    }
+
+    #[test]
+    fn it_steals_from_other_shards() {
+        let (clock, mock) = Clock::mock();
+        let rl = bucket(clock, 10, 4);
+        assert_eq!(rl.shards().get(), 4);
+
+        for _ in 0..10 {
+            assert!(rl.process().is_continue());
+        }
+        match rl.process() {
+            ControlFlow::Break(Reason::Overloaded {
+                limit,
+                remaining,
+                policy,
+                ..
+            }) => {
+                assert_eq!(limit, 10);
+                assert_eq!(remaining, 0);
+                assert_eq!(policy, "sharded_token_bucket");
+            }
+            other => panic!("expected Overloaded, got {other:?}"),
+        }
+
+        // Together the shards refill at the full rate
+        mock.increment(Duration::from_millis(800));
+        assert_eq!(rl.state().remaining, 8);
+    }
+
+    #[test]
+    fn it_keeps_to_the_home_shard_without_stealing() {
+        let (clock, _mock) = Clock::mock();
+        let rl = bucket(clock, 10, 4).with_steal(0);
+
+        // The home shard holds 2 or 3 of the 10 tokens
+        let mut admitted = 0;
+        while rl.process().is_continue() {
+            admitted += 1;
+        }
+        assert!((2..=3).contains(&admitted));
+        assert_eq!(rl.state().remaining, 10 - admitted);
+
+        // No shard could ever hold 4 tokens
+        let reason = rl.process_n(NonZeroUsize::new(4).unwrap());
+        assert!(matches!(
+            reason,
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                capacity: 2..=3,
+                ..
+            })
+        ));
+
+        // Never more shards than tokens
+        assert_eq!(bucket(Clock::mock().0, 3, 8).shards().get(), 3);
+    }
+}
@@ -0,0 +1,330 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use memmap2::MmapOptions;
+use memmap2::MmapRaw;
+
+use crate::Backoff;
+use crate::Quota;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::gcra::Limits;
+
+/// The layout of a [`SharedGcra`] file.
+///
+/// All zeroes is a valid, unclaimed region, so a freshly extended file needs no
+/// further initialisation.
+#[repr(C)]
+struct Region {
+    /// Zero until claimed, then [`SharedGcra::CLAIMING`] while the quota is written,
+    /// then [`SharedGcra::MAGIC`] once it can be read.
+    magic: AtomicU64,
+    emission_interval_ns: AtomicU64,
+    delay_tolerance_ns: AtomicU64,
+    /// Theoretical Arrival Time (TAT) in nanoseconds since the Unix epoch.
+    tat: AtomicU64,
+}
+
+/// A [`Gcra`](crate::Gcra) whose state lives in a memory-mapped file, so that every
+/// process which opens the same file shares one limit.
+///
+/// Use it where several worker processes on one host must enforce a combined rate,
+/// without a coordinator. Point it at a file on a RAM-backed filesystem such as
+/// `/dev/shm` for POSIX shared memory, or at a file on disk to also keep the limit
+/// across restarts.
+///
+/// GCRA keeps its whole state in a single word, which is updated with one
+/// compare-and-swap, so a process dying at any point cannot leave the state
+/// half-written. Time is read from the wall clock, which all processes agree on. If
+/// the wall clock steps backwards the limit is briefly stricter, and if it steps
+/// forwards, briefly looser.
+///
+/// The file must not be truncated or modified by anything else while mapped.
+pub struct SharedGcra {
+    map: MmapRaw,
+    limits: Limits,
+    backoff: Backoff,
+}
+
+impl fmt::Debug for SharedGcra {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedGcra")
+            .field("limits", &self.limits)
+            .field("tat", &self.region().tat)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SharedGcra {
+    /// The policy reported when the limit rejects a request.
+    pub const POLICY: &'static str = "shared_gcra";
+    /// Identifies a region claimed by a `SharedGcra`, whose quota has been written.
+    const MAGIC: u64 = u64::from_be_bytes(*b"shotgcr1");
+    /// Marks a region whose quota is being written by the process which claimed it.
+    const CLAIMING: u64 = u64::from_be_bytes(*b"shotgcr0");
+    /// How long to wait for another process to finish writing the quota.
+    #[cfg(not(test))]
+    const CLAIM_TIMEOUT: Duration = Duration::from_secs(1);
+    #[cfg(test)]
+    const CLAIM_TIMEOUT: Duration = Duration::from_millis(50);
+
+    /// Opens the limit stored in the file at `path`, creating the file if needed.
+    ///
+    /// Every process sharing the file must open it with the same `quota`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or mapped, if it holds something
+    /// other than a `SharedGcra` (`InvalidData`), if it is already in use with a
+    /// different quota (`InvalidInput`), or if another process claimed it but did not
+    /// finish writing its quota within a second (`TimedOut`). The last only happens if
+    /// that process died while opening the file, which then needs to be removed.
+    pub fn open(path: impl AsRef<Path>, quota: Quota) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // Extending fills with zeroes, which is an unclaimed region
+        let len = size_of::<Region>();
+        if file.metadata()?.len() < len as u64 {
+            file.set_len(len as u64)?;
+        }
+
+        let limiter = Self {
+            map: MmapOptions::new().len(len).map_raw(&file)?,
+            limits: Limits::from_quota(quota),
+            backoff: Backoff::default(),
+        };
+        limiter.claim()?;
+        Ok(limiter)
+    }
+
+    /// Sets how the limit waits when it keeps losing races with other threads and
+    /// processes.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Marks the region as ours and records the quota, or checks that it matches.
+    ///
+    /// Only the process which claims the region writes the quota, and nobody reads it
+    /// until it is complete, so racing openers all agree on one quota.
+    fn claim(&self) -> io::Result<()> {
+        let region = self.region();
+        let deadline = Instant::now() + Self::CLAIM_TIMEOUT;
+
+        loop {
+            match region.magic.compare_exchange(
+                0,
+                Self::CLAIMING,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    region
+                        .emission_interval_ns
+                        .store(self.limits.emission_interval_ns, Ordering::Relaxed);
+                    region
+                        .delay_tolerance_ns
+                        .store(self.limits.delay_tolerance_ns, Ordering::Relaxed);
+                    region.magic.store(Self::MAGIC, Ordering::Release);
+                    return Ok(());
+                }
+                Err(Self::MAGIC) => break,
+                Err(Self::CLAIMING) if Instant::now() < deadline => std::thread::yield_now(),
+                Err(Self::CLAIMING) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "SharedGcra was claimed but its quota was never written",
+                    ));
+                }
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "file does not hold a SharedGcra",
+                    ));
+                }
+            }
+        }
+
+        let claimed = Limits {
+            emission_interval_ns: region.emission_interval_ns.load(Ordering::Relaxed),
+            delay_tolerance_ns: region.delay_tolerance_ns.load(Ordering::Relaxed),
+        };
+        if claimed != self.limits {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SharedGcra is already in use with a different quota",
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn region(&self) -> &Region {
+        // SAFETY: The mapping is page aligned and at least `size_of::<Region>()` long,
+        // and it lives as long as `self`. Other processes only ever access it through
+        // the same atomics, and any bit pattern is a valid `AtomicU64`.
+        unsafe { &*(self.map.as_ptr() as *const Region) }
+    }
+
+    /// Nanoseconds since the Unix epoch.
+    #[inline]
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64)
+    }
+}
+
+impl Strategy for SharedGcra {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        self.limits.process_n(
+            &self.region().tat,
+            Self::now(),
+            cost,
+            self.backoff,
+            Self::POLICY,
+        )
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.limits.refund_n(&self.region().tat, cost);
+    }
+
+    fn state(&self) -> State {
+        let tat = self.region().tat.load(Ordering::Acquire);
+        self.limits.state_at(Self::now(), tat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A path in the temporary directory, removed again on drop.
+    struct TempPath(PathBuf);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "shot-limit-{}-{}-{name}",
+                std::process::id(),
+                SharedGcra::now()
+            )))
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn it_shares_one_limit_between_openers() {
+        let path = TempPath::new("shares");
+        let quota = Quota::per_minute(4).unwrap();
+
+        let first = SharedGcra::open(&path.0, quota).unwrap();
+        assert!(first.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+
+        // A second mapping (as another process would have) sees the same usage
+        let second = SharedGcra::open(&path.0, quota).unwrap();
+        assert_eq!(second.state().remaining, 1);
+        assert!(second.process().is_continue());
+        assert!(first.process().is_break());
+
+        first.refund_n(NonZeroUsize::new(1).unwrap());
+        assert_eq!(second.state().remaining, 1);
+    }
+
+    #[test]
+    fn it_rejects_a_different_quota() {
+        let path = TempPath::new("quota");
+        SharedGcra::open(&path.0, Quota::per_minute(4).unwrap()).unwrap();
+
+        let err = SharedGcra::open(
+            &path.0,
+            Quota::per_minute(4)
+                .unwrap()
+                .with_period(Duration::from_secs(1))
+                .unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        std::fs::write(&path.0, [0xff; 32]).unwrap();
+        let err = SharedGcra::open(&path.0, Quota::per_minute(4).unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn it_agrees_on_one_quota_when_opened_concurrently() {
+        use std::sync::Barrier;
+        use std::thread;
+
+        let path = TempPath::new("race");
+        let quotas = [
+            Quota::per_minute(4).unwrap(),
+            Quota::per_second(4).unwrap(),
+            Quota::per_minute(4).unwrap().with_burst(1).unwrap(),
+        ];
+        let barrier = Barrier::new(quotas.len());
+
+        let opened = thread::scope(|scope| {
+            let handles: Vec<_> = quotas
+                .iter()
+                .map(|quota| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        SharedGcra::open(&path.0, *quota).ok()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        // Exactly one quota wins, and it is written whole
+        let winners: Vec<_> = opened.iter().flatten().collect();
+        assert_eq!(winners.len(), 1);
+        let reopened = quotas
+            .iter()
+            .filter(|quota| SharedGcra::open(&path.0, **quota).is_ok())
+            .count();
+        assert_eq!(reopened, 1);
+    }
+
+    #[test]
+    fn it_times_out_on_an_unfinished_claim() {
+        let path = TempPath::new("claiming");
+        let mut bytes = [0; 32];
+        bytes[..8].copy_from_slice(&SharedGcra::CLAIMING.to_ne_bytes());
+        std::fs::write(&path.0, bytes).unwrap();
+
+        let err = SharedGcra::open(&path.0, Quota::per_minute(4).unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
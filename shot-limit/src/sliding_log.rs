@@ -0,0 +1,535 @@
+use alloc::boxed::Box;
+use core::fmt;
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+#[cfg(feature = "std")]
+use quanta::Clock;
+
+use crate::Backoff;
+use crate::DefaultClock;
+use crate::Quota;
+use crate::QuotaError;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::TimeSource;
+use crate::sync::AtomicU128;
+
+/// An exact Sliding Log implementation.
+///
+/// It remembers when each of the last `limit` permits was admitted, in a fixed-size
+/// ring. A request is admitted only if the permits it would replace are older than
+/// the window, so no more than `limit` permits are ever admitted within any
+/// `window`, and `retry_after` is exactly when the oldest of them expires.
+///
+/// Memory is 16 bytes per permit, so this suits low-rate, high-value limits (e.g. 5
+/// password resets per hour). Prefer [`SlidingWindow`](crate::SlidingWindow) for
+/// large limits.
+///
+/// An admission claims its entries and publishes its time in a single update of a
+/// 128-bit atomic, then records the time in each entry. A thread which finds an entry
+/// claimed but not yet recorded records it itself, rather than wait for the thread
+/// which claimed it, so this is lock-free on targets with 128-bit atomics.
+///
+/// An admission cannot be taken back without losing exactness, so
+/// [`Strategy::refund_n`] does nothing. When combining strategies with
+/// [`AllOf`](crate::AllOf), put a `SlidingLog` last so it is never rolled back.
+///
+/// The window can be changed at runtime with [`SlidingLog::set_window`]. The limit is
+/// the size of the ring, so it is fixed.
+pub struct SlidingLog<C: TimeSource = DefaultClock> {
+    /// One [`Entry`] per permit, packed. `position % log.len()` holds the permit
+    /// admitted at `position`.
+    log: Box<[AtomicU128]>,
+    /// The latest [`Claim`], packed.
+    head: AtomicU128,
+    window_ns: AtomicU64,
+    clock: C,
+    anchor: C::Instant,
+    backoff: Backoff,
+}
+
+/// The permits admitted so far, claimed by a single atomic update.
+#[derive(Clone, Copy)]
+struct Claim {
+    /// The number of permits ever admitted, and so the position of the next one.
+    head: u64,
+    /// `admitted_at_ns + 1` of the latest admission, or of an earlier one if that was
+    /// later, so that it never goes backwards.
+    stamp: u64,
+}
+
+impl Claim {
+    #[inline]
+    fn pack(self) -> u128 {
+        ((self.head as u128) << 64) | self.stamp as u128
+    }
+
+    #[inline]
+    fn unpack(word: u128) -> Self {
+        Self {
+            head: (word >> 64) as u64,
+            stamp: word as u64,
+        }
+    }
+}
+
+/// A permit recorded in the ring.
+#[derive(Clone, Copy)]
+struct Entry {
+    /// `position + log.len()` of the permit recorded, so that the entry for `position`
+    /// is still waiting for the previous lap's permit while this is below `position`.
+    /// Tags only grow, so a stale update can never overwrite a later lap.
+    tag: u64,
+    /// `admitted_at_ns + 1`, or zero for an entry which was never used.
+    stamp: u64,
+}
+
+impl Entry {
+    #[inline]
+    fn pack(self) -> u128 {
+        ((self.tag as u128) << 64) | self.stamp as u128
+    }
+
+    #[inline]
+    fn unpack(word: u128) -> Self {
+        Self {
+            tag: (word >> 64) as u64,
+            stamp: word as u64,
+        }
+    }
+}
+
+impl<C: TimeSource> fmt::Debug for SlidingLog<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlidingLog")
+            .field("limit", &self.log.len())
+            .field("window_ns", &self.window_ns)
+            .field(
+                "head",
+                &Claim::unpack(self.head.load(Ordering::Relaxed)).head,
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "std")]
+impl SlidingLog {
+    /// Creates a new `SlidingLog`.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The number of requests allowed in any `window`.
+    /// * `window` - The duration over which `limit` applies.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero, too long, or too short for the limit. Use
+    /// [`SlidingLog::from_quota`] to handle invalid arguments instead.
+    pub fn new(limit: NonZeroUsize, window: Duration) -> Self {
+        Self::with_clock(limit, window, Clock::new())
+    }
+
+    /// Creates a new `SlidingLog` which allows `quota.limit()` requests in any window of
+    /// `quota.period()`.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource> SlidingLog<C> {
+    const POLICY: &'static str = "sliding_log";
+
+    /// Creates a new `SlidingLog` which reads time from the supplied [`TimeSource`].
+    ///
+    /// Use `Clock::mock()` to drive the log deterministically in tests.
+    ///
+    /// # Panics
+    ///
+    /// See [`SlidingLog::new`].
+    pub fn with_clock(limit: NonZeroUsize, window: Duration, clock: C) -> Self {
+        Self::from_quota_with_clock(Quota::expect_valid(limit, window, limit), clock)
+    }
+
+    /// Creates a new `SlidingLog` from a [`Quota`], which reads time from the supplied
+    /// [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
+        let anchor = clock.now();
+
+        Self {
+            // Every entry starts out unused, as if recorded in the lap before the first
+            log: (0..quota.limit().get() as u64)
+                .map(|tag| AtomicU128::new(Entry { tag, stamp: 0 }.pack()))
+                .collect(),
+            head: AtomicU128::new(Claim { head: 0, stamp: 0 }.pack()),
+            window_ns: AtomicU64::new(quota.period().as_nanos() as u64),
+            clock,
+            anchor,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Sets how the strategy waits when it keeps losing races with other threads.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Changes the duration over which the limit applies.
+    ///
+    /// Admissions already logged are kept, and expire `window` after they happened.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`QuotaError`] if the limit and `window` don't make a valid [`Quota`],
+    /// in which case the window is unchanged.
+    pub fn set_window(&self, window: Duration) -> Result<(), QuotaError> {
+        let quota = Quota::new(self.log.len(), window)?;
+        self.window_ns
+            .store(quota.period().as_nanos() as u64, Ordering::Release);
+        Ok(())
+    }
+
+    #[inline]
+    fn entry(&self, position: u64) -> &AtomicU128 {
+        &self.log[(position % self.log.len() as u64) as usize]
+    }
+
+    /// Reads the entry which the permit at `position` will replace, or `None` if the
+    /// permit it holds has been claimed but not yet recorded.
+    #[inline]
+    fn replaced(&self, position: u64) -> Option<Entry> {
+        let entry = Entry::unpack(self.entry(position).load(Ordering::Acquire));
+        (entry.tag >= position).then_some(entry)
+    }
+
+    /// Records the permit at `position` as admitted at `stamp`, unless it already is.
+    #[inline]
+    fn record(&self, position: u64, stamp: u64) {
+        let tag = position + self.log.len() as u64;
+        let slot = self.entry(position);
+        let mut current = slot.load(Ordering::Acquire);
+        // Stops once this or any other thread has recorded it
+        while Entry::unpack(current).tag < tag {
+            match slot.compare_exchange_weak(
+                current,
+                Entry { tag, stamp }.pack(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// When the permit recorded in `entry` drops out of the window, or `None` if the
+    /// entry was never used.
+    #[inline]
+    fn expiry(&self, entry: Entry) -> Option<u64> {
+        match entry.stamp {
+            0 => None,
+            stamp => Some(stamp - 1 + self.window_ns.load(Ordering::Acquire)),
+        }
+    }
+
+    /// The state of the log at `now`.
+    fn state_at(&self, now: u64) -> State {
+        let limit = self.log.len();
+        let head = Claim::unpack(self.head.load(Ordering::Acquire)).head;
+        let window_ns = self.window_ns.load(Ordering::Acquire);
+
+        let mut in_window = 0;
+        let mut first_expiry = u64::MAX;
+        let mut last_expiry = now;
+        for position in head..head + limit as u64 {
+            let expiry = match self.replaced(position) {
+                Some(entry) => self.expiry(entry),
+                // Being admitted right now
+                None => Some(now + window_ns),
+            };
+
+            if let Some(expiry) = expiry.filter(|expiry| *expiry > now) {
+                in_window += 1;
+                first_expiry = first_expiry.min(expiry);
+                last_expiry = last_expiry.max(expiry);
+            }
+        }
+
+        let remaining = limit - in_window;
+        let retry_after_ns = if remaining > 0 { 0 } else { first_expiry - now };
+
+        State {
+            limit,
+            remaining,
+            reset_after: Duration::from_nanos(last_expiry - now),
+            retry_after: Duration::from_nanos(retry_after_ns),
+        }
+    }
+}
+
+impl<C: TimeSource> Strategy for SlidingLog<C> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let limit = self.log.len() as u64;
+        if cost.get() as u64 > limit {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: cost.get(),
+                capacity: limit as usize,
+                policy: Self::POLICY,
+            });
+        }
+
+        let cost = cost.get() as u64;
+        let now = self.clock.elapsed_ns(self.anchor);
+        let mut spins = 0;
+
+        'retry: loop {
+            self.backoff.snooze(spins);
+
+            // The request takes over the `cost` oldest entries, so all of them must
+            // have left the window.
+            let word = self.head.load(Ordering::Acquire);
+            let claim = Claim::unpack(word);
+            let mut latest_expiry = None;
+            for position in claim.head..claim.head + cost {
+                let Some(entry) = self.replaced(position) else {
+                    // The thread which claimed it may have been preempted, so record
+                    // it on its behalf. The latest stamp is no earlier than its own,
+                    // so this can only keep the permit in the window for longer.
+                    self.record(position - limit, claim.stamp);
+                    spins += 1;
+                    continue 'retry;
+                };
+                latest_expiry = latest_expiry.max(self.expiry(entry));
+            }
+
+            if let Some(expiry) = latest_expiry.filter(|expiry| *expiry > now) {
+                return ControlFlow::Break(
+                    self.state_at(now)
+                        .overloaded(Duration::from_nanos(expiry - now), Self::POLICY),
+                );
+            }
+
+            // Claim the entries and publish our admission time together, then record
+            // the time in each of them.
+            let stamp = claim.stamp.max(now + 1);
+            let next = Claim {
+                head: claim.head + cost,
+                stamp,
+            };
+            if self
+                .head
+                .compare_exchange_weak(word, next.pack(), Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                for position in claim.head..next.head {
+                    self.record(position, stamp);
+                }
+                return ControlFlow::Continue(());
+            }
+            // If CAS fails, another thread admitted a request; loop and recalculate.
+            spins += 1;
+        }
+    }
+
+    fn state(&self) -> State {
+        self.state_at(self.clock.elapsed_ns(self.anchor))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn it_enforces_exact_limits() {
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingLog::with_clock(
+            NonZeroUsize::new(3).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+
+        for _ in 0..3 {
+            assert!(rl.process().is_continue());
+            mock.increment(Duration::from_secs(10));
+        }
+
+        // At 59s all three admissions are still within the window
+        mock.increment(Duration::from_secs(29));
+        assert_eq!(
+            rl.process(),
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::from_secs(1),
+                limit: 3,
+                remaining: 0,
+                reset_after: Duration::from_secs(21),
+                policy: "sliding_log",
+            })
+        );
+
+        // The first admission expires at exactly 60s
+        mock.increment(Duration::from_secs(1));
+        assert!(rl.process().is_continue());
+
+        // The next to expire is the one admitted at 10s
+        match rl.process() {
+            ControlFlow::Break(Reason::Overloaded { retry_after, .. }) => {
+                assert_eq!(retry_after, Duration::from_secs(10));
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_charges_weighted_costs() {
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingLog::with_clock(
+            NonZeroUsize::new(5).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+
+        assert!(rl.process_n(NonZeroUsize::new(2).unwrap()).is_continue());
+        mock.increment(Duration::from_secs(30));
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        assert_eq!(rl.state().remaining, 0);
+
+        // Both of the 2 oldest permits expire at 60s, but 3 also needs one from 90s
+        mock.increment(Duration::from_secs(30));
+        assert_eq!(rl.state().remaining, 2);
+        match rl.process_n(NonZeroUsize::new(3).unwrap()) {
+            ControlFlow::Break(Reason::Overloaded { retry_after, .. }) => {
+                assert_eq!(retry_after, Duration::from_secs(30));
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+        assert!(rl.process_n(NonZeroUsize::new(2).unwrap()).is_continue());
+
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(6).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: 6,
+                capacity: 5,
+                policy: "sliding_log",
+            })
+        );
+    }
+
+    #[test]
+    fn it_reports_state_without_consuming() {
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingLog::with_clock(
+            NonZeroUsize::new(2).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+
+        let state = rl.state();
+        assert_eq!(state.limit, 2);
+        assert_eq!(state.remaining, 2);
+        assert_eq!(state.reset_after, Duration::ZERO);
+        assert_eq!(state.retry_after, Duration::ZERO);
+
+        assert!(rl.process().is_continue());
+        mock.increment(Duration::from_secs(15));
+        assert!(rl.process().is_continue());
+
+        let state = rl.state();
+        assert_eq!(state.remaining, 0);
+        assert_eq!(state.retry_after, Duration::from_secs(45));
+        assert_eq!(state.reset_after, Duration::from_secs(60));
+        assert_eq!(rl.state(), state);
+    }
+
+    #[test]
+    fn it_changes_the_window_of_logged_admissions() {
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingLog::with_clock(
+            NonZeroUsize::new(2).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+
+        assert!(rl.process().is_continue());
+        mock.increment(Duration::from_secs(10));
+        assert!(rl.process().is_continue());
+
+        // Lengthening the window keeps both admissions for longer
+        rl.set_window(Duration::from_secs(120)).unwrap();
+        assert_eq!(rl.state().retry_after, Duration::from_secs(110));
+
+        // Shortening it lets the first one expire straight away
+        rl.set_window(Duration::from_secs(5)).unwrap();
+        assert_eq!(rl.state().remaining, 1);
+        assert!(rl.process().is_continue());
+
+        // A window too short for the limit is refused, and the old one kept
+        assert_eq!(
+            rl.set_window(Duration::ZERO),
+            Err(QuotaError::InvalidPeriod {
+                period: Duration::ZERO
+            })
+        );
+        assert_eq!(rl.state().remaining, 0);
+    }
+
+    #[test]
+    fn it_never_over_admits_under_contention() {
+        use std::thread;
+
+        let rl = Arc::new(SlidingLog::new(
+            NonZeroUsize::new(4).unwrap(),
+            Duration::from_secs(60),
+        ));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || (0..100).filter(|_| rl.process().is_continue()).count())
+            })
+            .collect();
+
+        let allowed: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(allowed, 4);
+        assert_eq!(rl.state().remaining, 0);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+    use crate::ManualClock;
+
+    #[test]
+    fn it_admits_once_into_a_slot_being_recorded() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let limit = NonZeroUsize::new(1).unwrap();
+            let rl = Arc::new(
+                SlidingLog::with_clock(limit, Duration::from_secs(1), &CLOCK)
+                    .with_backoff(Backoff::new(0, thread::yield_now)),
+            );
+            let racer = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.process().is_continue())
+            };
+
+            // Whichever loses must see the winner's admission, recorded or not
+            let admitted = [rl.process().is_continue(), racer.join().unwrap()];
+            assert_eq!(admitted.iter().filter(|admitted| **admitted).count(), 1);
+            assert_eq!(rl.state().remaining, 0);
+        });
+    }
+}
@@ -1,113 +1,383 @@
-use std::num::NonZeroUsize;
-use std::ops::ControlFlow;
-use std::sync::atomic::AtomicU64;
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
-use std::time::Duration;
-
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+#[cfg(feature = "std")]
 use quanta::Clock;
-use quanta::Instant;
 
+use super::Backoff;
+use super::DefaultClock;
+#[cfg(feature = "std")]
+use super::Persist;
+use super::Quota;
 use super::Reason;
+#[cfg(feature = "std")]
+use super::Snapshot;
+#[cfg(feature = "std")]
+use super::SnapshotError;
+use super::State;
 use super::Strategy;
+use super::TimeSource;
+use crate::float;
+#[cfg(feature = "std")]
+use crate::snapshot::mismatch;
+#[cfg(feature = "std")]
+use crate::snapshot::ns_until;
+#[cfg(feature = "std")]
+use crate::snapshot::wall_after;
+use crate::sync::AtomicU128;
 
 /// A Sliding Window Counter implementation.
 ///
 /// It maintains a count for the current fixed window and the previous one.
 /// The effective count is: (previous_count * %_of_window_left) + current_count.
+///
+/// Both counts and the end of the current window are packed into a single 128-bit
+/// word, so each request, including one which slides the window, is decided by a
+/// single atomic update. This is lock-free on targets with 128-bit atomics, such as
+/// x86_64 and AArch64, and falls back to a lock elsewhere. Each window counts up to
+/// `u32::MAX` requests, which caps the capacity.
+///
+/// The limit can be changed at runtime with [`SlidingWindow::set_capacity`] and
+/// [`SlidingWindow::set_rate`].
 #[derive(Debug)]
-pub struct SlidingWindow {
-    capacity: usize,
-    period_ns: u64,
-    /// Current window's request count
-    current_count: AtomicUsize,
+pub struct SlidingWindow<C: TimeSource = DefaultClock> {
+    capacity: AtomicUsize,
+    period_ns: AtomicU64,
+    /// The current [`Window`], packed.
+    window: AtomicU128,
+    clock: C,
+    anchor: C::Instant,
+    backoff: Backoff,
+}
+
+/// The counts of the current and previous windows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Window {
+    /// Timestamp (nanos from anchor) for the end of the current window
+    end: u64,
     /// Previous window's request count
-    previous_count: AtomicUsize,
-    /// Timestamp (nanos from anchor) for the start of the current window
-    current_window_start: AtomicU64,
-    clock: Clock,
-    anchor: Instant,
+    previous: u32,
+    /// Current window's request count
+    current: u32,
 }
 
+impl Window {
+    #[inline]
+    fn pack(self) -> u128 {
+        ((self.end as u128) << 64) | ((self.previous as u128) << 32) | self.current as u128
+    }
+
+    #[inline]
+    fn unpack(word: u128) -> Self {
+        Self {
+            end: (word >> 64) as u64,
+            previous: (word >> 32) as u32,
+            current: word as u32,
+        }
+    }
+
+    /// The window at `now`, which slides forward once this one has ended.
+    #[inline]
+    fn at(self, now: u64, period_ns: u64) -> Self {
+        if now < self.end {
+            return self;
+        }
+
+        Self {
+            end: (now / period_ns + 1) * period_ns,
+            // If we moved at least two windows forward, the previous count is 0
+            previous: if now < self.end + period_ns {
+                self.current
+            } else {
+                0
+            },
+            current: 0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl SlidingWindow {
+    /// Creates a new `SlidingWindow`.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of requests allowed within any window.
+    /// * `period` - The duration of the window.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, too long, or too short for the limit. Use
+    /// [`SlidingWindow::from_quota`] to handle invalid arguments instead.
     pub fn new(capacity: NonZeroUsize, period: Duration) -> Self {
-        let clock = Clock::new();
+        Self::with_clock(capacity, period, Clock::new())
+    }
+
+    /// Creates a new `SlidingWindow` which allows `quota.limit()` requests in any window
+    /// of `quota.period()`.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource> SlidingWindow<C> {
+    /// Creates a new `SlidingWindow` which reads time from the supplied [`TimeSource`].
+    ///
+    /// Use `Clock::mock()` to drive the window deterministically in tests.
+    ///
+    /// # Panics
+    ///
+    /// See [`SlidingWindow::new`].
+    pub fn with_clock(capacity: NonZeroUsize, period: Duration, clock: C) -> Self {
+        Self::from_quota_with_clock(Quota::expect_valid(capacity, period, capacity), clock)
+    }
+
+    /// Creates a new `SlidingWindow` from a [`Quota`], which reads time from the
+    /// supplied [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
         let anchor = clock.now();
+        let window = Window {
+            end: quota.period().as_nanos() as u64,
+            previous: 0,
+            current: 0,
+        };
+
         Self {
-            capacity: capacity.get(),
-            period_ns: period.as_nanos() as u64,
-            current_count: AtomicUsize::new(0),
-            previous_count: AtomicUsize::new(0),
-            current_window_start: AtomicU64::new(0),
+            capacity: AtomicUsize::new(quota.limit().get()),
+            period_ns: AtomicU64::new(quota.period().as_nanos() as u64),
+            window: AtomicU128::new(window.pack()),
             clock,
             anchor,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Sets how the strategy waits when it keeps losing races with other threads.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+impl<C: TimeSource> SlidingWindow<C> {
+    const POLICY: &'static str = "sliding_window";
+
+    /// Changes the number of requests allowed per window.
+    ///
+    /// Requests already counted in the current and previous windows keep counting
+    /// against the new capacity, so tightening the limit takes effect immediately.
+    pub fn set_capacity(&self, capacity: NonZeroUsize) {
+        self.capacity.store(capacity.get(), Ordering::Release);
+    }
+
+    /// Changes the limit to `quota.limit()` requests per window of `quota.period()`.
+    ///
+    /// The capacity changes as with [`SlidingWindow::set_capacity`]. The current window
+    /// keeps its start time and its count, and ends a new period after it started.
+    /// Later windows are aligned to multiples of the new period.
+    pub fn set_rate(&self, quota: Quota) {
+        self.set_capacity(quota.limit());
+
+        let period_ns = quota.period().as_nanos() as u64;
+        let old_period_ns = self.period_ns.swap(period_ns, Ordering::AcqRel);
+        let _ = self
+            .window
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |window| {
+                let mut window = Window::unpack(window);
+                window.end = window
+                    .end
+                    .saturating_sub(old_period_ns)
+                    .saturating_add(period_ns);
+                Some(window.pack())
+            });
+    }
+
+    #[inline]
+    fn capacity(&self) -> usize {
+        // Counts must fit in their half of the packed window
+        self.capacity.load(Ordering::Acquire).min(u32::MAX as usize)
+    }
+
+    #[inline]
+    fn period_ns(&self) -> u64 {
+        self.period_ns.load(Ordering::Acquire)
+    }
+
+    /// The weighted count: (previous_count * %_of_window_left) + current_count.
+    #[inline]
+    fn estimate(&self, now: u64, window: Window) -> usize {
+        let period_ns = self.period_ns();
+        let weight = window.end.saturating_sub(now).min(period_ns) as f64 / period_ns as f64;
+        float::floor(window.previous as f64 * weight) as usize + window.current as usize
+    }
+
+    /// Estimate wait time based on when the weighted count would drop far enough
+    /// below capacity to fit the cost.
+    fn retry_after(&self, estimated_count: usize, cost: usize) -> Duration {
+        let capacity = self.capacity();
+        let missing = (estimated_count + cost).saturating_sub(capacity);
+        let retry_after_ns =
+            float::ceil((missing as f64 / capacity as f64) * self.period_ns() as f64) as u64;
+        Duration::from_nanos(retry_after_ns)
+    }
+
+    /// The window at `now`, as it would be after a slide, without performing it.
+    fn window_at(&self, now: u64) -> Window {
+        Window::unpack(self.window.load(Ordering::Acquire)).at(now, self.period_ns())
+    }
+
+    /// The state of the window at `now`, given the counts of `window`.
+    fn state_at(&self, now: u64, window: Window) -> State {
+        let capacity = self.capacity();
+        let period_ns = self.period_ns();
+        let estimated_count = self.estimate(now, window);
+        let remaining = capacity.saturating_sub(estimated_count);
+
+        let retry_after = if remaining > 0 {
+            Duration::ZERO
+        } else {
+            self.retry_after(estimated_count, 1)
+        };
+
+        // Requests in the current window stop counting once they have slid out of the
+        // following window; requests in the previous window once this one ends.
+        let reset_after_ns = if window.current > 0 {
+            (window.end + period_ns).saturating_sub(now)
+        } else if window.previous > 0 {
+            window.end.saturating_sub(now)
+        } else {
+            0
+        };
+
+        State {
+            limit: capacity,
+            remaining,
+            reset_after: Duration::from_nanos(reset_after_ns),
+            retry_after,
         }
     }
 }
 
-impl Strategy for SlidingWindow {
+impl<C: TimeSource> Strategy for SlidingWindow<C> {
     #[inline]
-    fn process(&self) -> ControlFlow<Reason> {
-        let now = self.clock.now().duration_since(self.anchor).as_nanos() as u64;
-        let mut window_start = self.current_window_start.load(Ordering::Acquire);
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let cost = cost.get();
+        let capacity = self.capacity();
+        let period_ns = self.period_ns();
+
+        // Even an empty window can never admit more than capacity
+        if cost > capacity {
+            return ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost,
+                capacity,
+                policy: Self::POLICY,
+            });
+        }
+
+        let now = self.clock.elapsed_ns(self.anchor);
+        let mut spins = 0;
 
-        // 1. Check if we need to slide the window
-        if now >= window_start + self.period_ns {
-            let new_window_start = (now / self.period_ns) * self.period_ns;
+        loop {
+            self.backoff.snooze(spins);
 
+            // 1. Slide the window, if it has ended, and calculate the weighted count
+            let current = self.window.load(Ordering::Acquire);
+            let window = Window::unpack(current).at(now, period_ns);
+            let estimated_count = self.estimate(now, window);
+
+            if estimated_count + cost > capacity {
+                let retry_after = self.retry_after(estimated_count, cost);
+                return ControlFlow::Break(
+                    self.state_at(now, window)
+                        .overloaded(retry_after, Self::POLICY),
+                );
+            }
+
+            // 2. Count the request and any slide in one update
+            let next = Window {
+                current: window.current + cost as u32,
+                ..window
+            };
             if self
-                .current_window_start
-                .compare_exchange(
-                    window_start,
-                    new_window_start,
-                    Ordering::SeqCst,
-                    Ordering::Relaxed,
-                )
+                .window
+                .compare_exchange_weak(current, next.pack(), Ordering::AcqRel, Ordering::Relaxed)
                 .is_ok()
             {
-                // If we moved at least two windows forward, previous_count is 0
-                let prev_val = if now >= window_start + (2 * self.period_ns) {
-                    0
-                } else {
-                    self.current_count.load(Ordering::Acquire)
-                };
-
-                self.previous_count.store(prev_val, Ordering::Release);
-                self.current_count.store(0, Ordering::Release);
-                window_start = new_window_start;
-            } else {
-                // May have been set by a different thread
-                window_start = self.current_window_start.load(Ordering::Acquire);
+                return ControlFlow::Continue(());
             }
+            // If CAS fails, another thread updated the window; loop and recalculate.
+            spins += 1;
         }
+    }
 
-        // 2. Calculate the weighted count
-        let prev_count = self.previous_count.load(Ordering::Acquire) as f64;
-        let curr_count = self.current_count.load(Ordering::Acquire);
+    fn refund_n(&self, cost: NonZeroUsize) {
+        let cost = u32::try_from(cost.get()).unwrap_or(u32::MAX);
+        let _ = self
+            .window
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |window| {
+                let mut window = Window::unpack(window);
+                window.current = window.current.saturating_sub(cost);
+                Some(window.pack())
+            });
+    }
 
-        let elapsed_in_window = now - window_start;
-        let weight = (self.period_ns - elapsed_in_window) as f64 / self.period_ns as f64;
-        let estimated_count = (prev_count * weight).floor() as usize + curr_count;
+    fn state(&self) -> State {
+        let now = self.clock.elapsed_ns(self.anchor);
+        self.state_at(now, self.window_at(now))
+    }
+}
 
-        if estimated_count < self.capacity {
-            self.current_count.fetch_add(1, Ordering::SeqCst);
-            ControlFlow::Continue(())
-        } else {
-            // Estimate wait time based on when the weighted count would drop below capacity
-            let missing = estimated_count - self.capacity;
-            let retry_after_ns = if missing > 0 {
-                ((missing as f64 / self.capacity as f64) * self.period_ns as f64).ceil() as u64
-            } else {
-                0
-            };
-            ControlFlow::Break(Reason::Overloaded {
-                retry_after: Duration::from_nanos(retry_after_ns),
-            })
+#[cfg(feature = "std")]
+impl<C: TimeSource> Persist for SlidingWindow<C> {
+    fn snapshot_at(&self, now: SystemTime) -> Snapshot {
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let window = self.window_at(elapsed);
+
+        Snapshot::SlidingWindow {
+            previous: window.previous as usize,
+            current: window.current as usize,
+            ends_at: wall_after(now, window.end - elapsed),
+        }
+    }
+
+    fn restore_at(&self, snapshot: &Snapshot, now: SystemTime) -> Result<(), SnapshotError> {
+        let Snapshot::SlidingWindow {
+            mut previous,
+            mut current,
+            mut ends_at,
+        } = *snapshot
+        else {
+            return Err(mismatch(Self::POLICY, snapshot));
+        };
+
+        // Slide the snapshot's window forward to now, as `process` would have
+        let period = Duration::from_nanos(self.period_ns());
+        if ends_at <= now {
+            (previous, current) = (current, 0);
+            ends_at += period;
+        }
+        if ends_at <= now {
+            return Ok(());
         }
+
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let window = Window {
+            end: elapsed.saturating_add(ns_until(now, ends_at)),
+            previous: u32::try_from(previous).unwrap_or(u32::MAX),
+            current: u32::try_from(current).unwrap_or(u32::MAX),
+        };
+        self.window.store(window.pack(), Ordering::Release);
+        Ok(())
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -153,6 +423,12 @@ mod tests {
         );
     }
 
+    #[test]
+    #[should_panic(expected = "invalid period 0ns")]
+    fn test_sliding_window_rejects_zero_period() {
+        SlidingWindow::new(NonZeroUsize::new(10).unwrap(), Duration::ZERO);
+    }
+
     #[test]
     fn test_sliding_window_concurrency() {
         use std::sync::Arc;
@@ -194,9 +470,10 @@ mod tests {
         assert!(rl.process().is_continue());
 
         // Check internal state instead of a single 'expires' field
-        let now = rl.clock.now().duration_since(rl.anchor).as_nanos() as u64;
-        let window_start = rl.current_window_start.load(Ordering::Acquire);
-        let prev_count = rl.previous_count.load(Ordering::Acquire);
+        let now = rl.clock.elapsed_ns(rl.anchor);
+        let window = Window::unpack(rl.window.load(Ordering::Acquire));
+        let window_start = window.end - rl.period_ns();
+        let prev_count = window.previous;
 
         // 1. The window start should be the most recent boundary (now - (now % period))
         assert!(
@@ -204,7 +481,7 @@ mod tests {
             "Window start should not be in the future"
         );
         assert!(
-            now < window_start + rl.period_ns,
+            now < window_start + rl.period_ns(),
             "Now must fall within the current window"
         );
 
@@ -297,4 +574,193 @@ mod tests {
             "Should not allow a full second burst immediately"
         );
     }
+
+    #[test]
+    fn test_sliding_window_weighted_costs() {
+        let rl = SlidingWindow::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60));
+
+        assert!(rl.process_n(NonZeroUsize::new(7).unwrap()).is_continue());
+        // 3 left, so a cost of 4 is rejected without consuming anything
+        assert!(matches!(
+            rl.process_n(NonZeroUsize::new(4).unwrap()),
+            ControlFlow::Break(Reason::Overloaded { .. })
+        ));
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+    }
+
+    #[test]
+    fn test_sliding_window_rejects_costs_above_capacity() {
+        let rl = SlidingWindow::new(NonZeroUsize::new(10).unwrap(), Duration::from_secs(60));
+
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(11).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: 11,
+                capacity: 10,
+                policy: "sliding_window",
+            })
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_state_without_consuming() {
+        let period = Duration::from_secs(60);
+        let rl = SlidingWindow::new(NonZeroUsize::new(10).unwrap(), period);
+
+        let state = rl.state();
+        assert_eq!(state.limit, 10);
+        assert_eq!(state.remaining, 10);
+        assert_eq!(state.reset_after, Duration::ZERO);
+        assert_eq!(state.retry_after, Duration::ZERO);
+
+        assert!(rl.process_n(NonZeroUsize::new(10).unwrap()).is_continue());
+        let state = rl.state();
+        assert_eq!(state.remaining, 0);
+        assert!(state.retry_after > Duration::ZERO);
+        // The current window's requests only fully expire after the next window
+        assert!(state.reset_after > period);
+        assert!(state.reset_after <= period * 2);
+
+        // Inspecting did not consume anything
+        assert_eq!(
+            Window::unpack(rl.window.load(Ordering::Acquire)).current,
+            10
+        );
+    }
+
+    #[test]
+    fn test_sliding_window_deterministic_with_mock_clock() {
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingWindow::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_millis(100),
+            clock,
+        );
+
+        // Fill the first window
+        assert!(rl.process_n(NonZeroUsize::new(10).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+
+        // 30ms into the second window the previous count weighs 70%, so
+        // floor(10 * 0.7) = 7 are still counted and 3 are free
+        mock.increment(Duration::from_millis(130));
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+
+        // Two whole windows later everything has slid out
+        mock.increment(Duration::from_millis(200));
+        assert!(rl.process_n(NonZeroUsize::new(10).unwrap()).is_continue());
+    }
+
+    #[test]
+    fn it_reconfigures_without_losing_state() {
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingWindow::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            Duration::from_secs(60),
+            clock,
+        );
+        assert!(rl.process_n(NonZeroUsize::new(6).unwrap()).is_continue());
+
+        // The 6 requests already admitted still count
+        rl.set_capacity(NonZeroUsize::new(8).unwrap());
+        assert_eq!(rl.state().limit, 8);
+        assert_eq!(rl.state().remaining, 2);
+        rl.set_capacity(NonZeroUsize::new(5).unwrap());
+        assert!(rl.process().is_break());
+
+        // With a 20s period, at 30s the window has slid and half of it remains
+        rl.set_rate(Quota::new(5, Duration::from_secs(20)).unwrap());
+        mock.increment(Duration::from_secs(30));
+        assert_eq!(rl.state().remaining, 2);
+        assert!(rl.process_n(NonZeroUsize::new(2).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+    }
+
+    #[test]
+    fn test_sliding_window_restores_snapshots() {
+        use quanta::Clock;
+
+        let capacity = NonZeroUsize::new(10).unwrap();
+        let period = Duration::from_secs(10);
+        let (clock, mock) = Clock::mock();
+        let rl = SlidingWindow::with_clock(capacity, period, clock);
+        mock.increment(Duration::from_secs(2));
+        assert!(rl.process_n(NonZeroUsize::new(6).unwrap()).is_continue());
+
+        let taken_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let snapshot = rl.snapshot_at(taken_at);
+        assert_eq!(
+            snapshot,
+            Snapshot::SlidingWindow {
+                previous: 0,
+                current: 6,
+                ends_at: taken_at + Duration::from_secs(8),
+            }
+        );
+
+        // Halfway through the next window, half of the 6 requests still count
+        let (clock, _mock) = Clock::mock();
+        let restored = SlidingWindow::with_clock(capacity, period, clock);
+        restored
+            .restore_at(&snapshot, taken_at + Duration::from_secs(13))
+            .unwrap();
+        let state = restored.state();
+        assert_eq!(state.remaining, 7);
+        assert_eq!(state.reset_after, Duration::from_secs(5));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+    use crate::ManualClock;
+
+    fn window(clock: &'static ManualClock, capacity: usize) -> SlidingWindow<&'static ManualClock> {
+        let capacity = NonZeroUsize::new(capacity).unwrap();
+        SlidingWindow::with_clock(capacity, Duration::from_secs(1), clock)
+            .with_backoff(Backoff::new(0, thread::yield_now))
+    }
+
+    #[test]
+    fn it_admits_each_request_once() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let rl = Arc::new(window(&CLOCK, 2));
+            let racer = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.process().is_continue())
+            };
+
+            let admitted = [rl.process().is_continue(), racer.join().unwrap()];
+            assert_eq!(admitted, [true, true]);
+            assert!(rl.process().is_break());
+        });
+    }
+
+    #[test]
+    fn it_slides_the_window_once() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let rl = Arc::new(window(&CLOCK, 2));
+            assert!(rl.process_n(NonZeroUsize::new(2).unwrap()).is_continue());
+
+            // Half of the previous window's requests still count
+            CLOCK.advance(Duration::from_millis(1500));
+            let racer = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.process().is_continue())
+            };
+
+            let admitted = rl.process().is_continue();
+            assert_ne!(admitted, racer.join().unwrap());
+            assert_eq!(rl.state().remaining, 0);
+        });
+    }
 }
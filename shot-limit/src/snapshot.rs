@@ -0,0 +1,174 @@
+use std::fmt;
+use std::time::Duration;
+use std::time::SystemTime;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::Strategy;
+
+/// The usage of a strategy at a point in time, which can be restored into another
+/// instance of the same strategy (e.g. after a restart).
+///
+/// Strategies measure time from a monotonic anchor which does not survive the
+/// process, so snapshots hold wall-clock times instead. Time which passes between
+/// taking and restoring a snapshot counts as usual, so a limit which would have
+/// recovered in the meantime is restored recovered. Limits themselves are not part
+/// of the snapshot: usage is restored against the limits of the restoring strategy.
+///
+/// With the `serde` feature, snapshots can be serialized, with times as RFC 3339
+/// timestamps.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum Snapshot {
+    /// A [`TokenBucket`](crate::TokenBucket) holding `tokens` at `taken_at`.
+    TokenBucket {
+        tokens: f64,
+        #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+        taken_at: SystemTime,
+    },
+    /// A [`Gcra`](crate::Gcra) whose theoretical arrival time is `tat`.
+    Gcra {
+        #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+        tat: SystemTime,
+    },
+    /// A [`FixedWindow`](crate::FixedWindow) which has admitted `used` requests in the
+    /// window ending at `expires_at`.
+    FixedWindow {
+        used: usize,
+        #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+        expires_at: SystemTime,
+    },
+    /// A [`SlidingWindow`](crate::SlidingWindow) which has counted `current` requests
+    /// in the window ending at `ends_at`, and `previous` in the window before.
+    SlidingWindow {
+        previous: usize,
+        current: usize,
+        #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+        ends_at: SystemTime,
+    },
+    /// A [`CalendarQuota`](crate::CalendarQuota) which has admitted `used` requests in
+    /// the period ending at `resets_at`.
+    CalendarQuota {
+        used: usize,
+        #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+        resets_at: SystemTime,
+    },
+}
+
+impl Snapshot {
+    /// The policy of the strategy the snapshot was taken from.
+    pub fn policy(&self) -> &'static str {
+        match self {
+            Self::TokenBucket { .. } => "token_bucket",
+            Self::Gcra { .. } => "gcra",
+            Self::FixedWindow { .. } => "fixed_window",
+            Self::SlidingWindow { .. } => "sliding_window",
+            Self::CalendarQuota { .. } => "calendar_quota",
+        }
+    }
+}
+
+/// Reasons why a [`Snapshot`] cannot be restored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SnapshotError {
+    /// The snapshot was taken from a different kind of strategy.
+    Mismatch {
+        /// The policy of the restoring strategy.
+        expected: &'static str,
+        /// The policy of the snapshot.
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch { expected, found } => {
+                write!(f, "cannot restore a {found} snapshot into {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// A strategy whose usage can be saved as a [`Snapshot`] and restored later.
+pub trait Persist: Strategy {
+    /// Takes a snapshot, pairing the strategy's current time with the wall-clock `now`.
+    fn snapshot_at(&self, now: SystemTime) -> Snapshot;
+
+    /// Replaces the strategy's usage with `snapshot`, as of the wall-clock `now`.
+    ///
+    /// Restore before the strategy starts serving requests. Requests racing with the
+    /// restore may be judged against either usage.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SnapshotError::Mismatch`] if the snapshot was taken from a different
+    /// kind of strategy.
+    fn restore_at(&self, snapshot: &Snapshot, now: SystemTime) -> Result<(), SnapshotError>;
+
+    /// Takes a snapshot as of the current wall-clock time.
+    fn snapshot(&self) -> Snapshot {
+        self.snapshot_at(SystemTime::now())
+    }
+
+    /// Restores `snapshot` as of the current wall-clock time.
+    ///
+    /// # Errors
+    ///
+    /// See [`Persist::restore_at`].
+    fn restore(&self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        self.restore_at(snapshot, SystemTime::now())
+    }
+}
+
+/// Wall-clock time `ns` nanoseconds after `now`.
+pub(crate) fn wall_after(now: SystemTime, ns: u64) -> SystemTime {
+    now + Duration::from_nanos(ns)
+}
+
+/// Nanoseconds from `now` until `then`, or zero if `then` has passed.
+pub(crate) fn ns_until(now: SystemTime, then: SystemTime) -> u64 {
+    then.duration_since(now)
+        .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+}
+
+/// Nanoseconds from `then` until `now`, or zero if `then` is in the future.
+pub(crate) fn ns_since(now: SystemTime, then: SystemTime) -> u64 {
+    ns_until(then, now)
+}
+
+/// The error for restoring `snapshot` into a strategy with `policy`.
+pub(crate) fn mismatch(policy: &'static str, snapshot: &Snapshot) -> SnapshotError {
+    SnapshotError::Mismatch {
+        expected: policy,
+        found: snapshot.policy(),
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_serializes_times_as_timestamps() {
+        let snapshot = Snapshot::FixedWindow {
+            used: 3,
+            expires_at: SystemTime::UNIX_EPOCH + Duration::from_millis(1_500),
+        };
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"fixed_window","used":3,"expires_at":"1970-01-01T00:00:01.500000000Z"}"#
+        );
+        assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+    }
+}
@@ -0,0 +1,68 @@
+//! The atomics behind strategies whose decisions are model-checked with loom.
+//!
+//! Under `cfg(loom)` these are loom's, so that loom can explore every interleaving of
+//! the threads racing on them. loom has no 128-bit atomics, so it models one as a
+//! locked cell, which is enough to check the transitions made with it.
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::AtomicBool;
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::AtomicU64;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicBool;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicU64;
+#[cfg(not(loom))]
+pub(crate) use portable_atomic::AtomicU128;
+
+#[cfg(loom)]
+#[derive(Debug)]
+pub(crate) struct AtomicU128(loom::sync::Mutex<u128>);
+
+#[cfg(loom)]
+impl AtomicU128 {
+    pub(crate) fn new(value: u128) -> Self {
+        Self(loom::sync::Mutex::new(value))
+    }
+
+    pub(crate) fn load(&self, _: core::sync::atomic::Ordering) -> u128 {
+        *self.0.lock().unwrap()
+    }
+
+    pub(crate) fn store(&self, value: u128, _: core::sync::atomic::Ordering) {
+        *self.0.lock().unwrap() = value;
+    }
+
+    pub(crate) fn compare_exchange_weak(
+        &self,
+        current: u128,
+        new: u128,
+        _: core::sync::atomic::Ordering,
+        _: core::sync::atomic::Ordering,
+    ) -> Result<u128, u128> {
+        let mut value = self.0.lock().unwrap();
+        if *value == current {
+            *value = new;
+            Ok(current)
+        } else {
+            Err(*value)
+        }
+    }
+
+    pub(crate) fn fetch_update(
+        &self,
+        _: core::sync::atomic::Ordering,
+        _: core::sync::atomic::Ordering,
+        mut f: impl FnMut(u128) -> Option<u128>,
+    ) -> Result<u128, u128> {
+        let mut value = self.0.lock().unwrap();
+        let current = *value;
+        match f(current) {
+            Some(new) => {
+                *value = new;
+                Ok(current)
+            }
+            None => Err(current),
+        }
+    }
+}
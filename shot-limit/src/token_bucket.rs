@@ -1,116 +1,298 @@
-use std::num::NonZeroUsize;
-use std::ops::ControlFlow;
-use std::sync::atomic::AtomicU64;
-use std::sync::atomic::Ordering;
-use std::time::Duration;
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
+#[cfg(feature = "std")]
 use quanta::Clock;
-use quanta::Instant;
 
+use super::Backoff;
+use super::DefaultClock;
+#[cfg(feature = "std")]
+use super::Persist;
+use super::Quota;
 use super::Reason;
+#[cfg(feature = "std")]
+use super::Snapshot;
+#[cfg(feature = "std")]
+use super::SnapshotError;
+use super::State;
 use super::Strategy;
+use super::TimeSource;
+use crate::gcra::Limits;
+#[cfg(feature = "std")]
+use crate::snapshot::mismatch;
+#[cfg(feature = "std")]
+use crate::snapshot::ns_since;
+use crate::sync::AtomicBool;
+use crate::sync::AtomicU64;
 
 /// A classic Token Bucket algorithm.
 ///
 /// It maintains a "bucket" of tokens that is replenished over time.
 /// This allows for bursts of traffic up to the bucket's capacity while
 /// maintaining a steady average rate.
+///
+/// The bucket is stored as the time at which it will next be full, so each request is
+/// decided by a single atomic update, and racing requests never see a half-updated
+/// bucket. Tokens are refilled one every `period / increment`.
+///
+/// The capacity and refill rate can be changed at runtime with
+/// [`TokenBucket::set_capacity`] and [`TokenBucket::set_rate`], without refilling the
+/// bucket. Changes are made one at a time, so racing setters never adjust the bucket
+/// for a capacity or rate which another has already replaced.
 #[derive(Debug)]
-pub struct TokenBucket {
-    capacity_units: u64,
-    /// Number of units (tokens * 10^9) added per nanosecond.
-    refill_rate_units_per_ns: f64,
-    /// Current units in bucket (scaled by 10^9).
-    units: AtomicU64,
-    last_update_ns: AtomicU64,
-    clock: Clock,
-    anchor: Instant,
+pub struct TokenBucket<C: TimeSource = DefaultClock> {
+    /// Max tokens the bucket can hold.
+    capacity: AtomicU64,
+    /// Nanoseconds taken to refill a single token.
+    refill_interval_ns: AtomicU64,
+    /// Timestamp (nanos from anchor) at which the bucket will be full. Until then it
+    /// is missing one token for every refill interval left.
+    full_at: AtomicU64,
+    /// Held by the setter which is changing the capacity or rate.
+    reconfiguring: AtomicBool,
+    clock: C,
+    anchor: C::Instant,
+    backoff: Backoff,
 }
 
+#[cfg(feature = "std")]
 impl TokenBucket {
-    const UNITS_SCALE: u64 = 1_000_000_000; // Cost of 1 token
-
     /// Creates a new `TokenBucket`.
     ///
     /// # Arguments
     /// * `capacity` - Max tokens the bucket can hold (burst size).
     /// * `increment` - Tokens added during the period (steady-state rate).
     /// * `period` - The duration over which `increment` is added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero, too long, or too short for the limit. Use
+    /// [`TokenBucket::from_quota`] to handle invalid arguments instead.
     pub fn new(capacity: NonZeroUsize, increment: NonZeroUsize, period: Duration) -> Self {
-        let clock = Clock::new();
+        Self::with_clock(capacity, increment, period, Clock::new())
+    }
+
+    /// Creates a new `TokenBucket` which adds `quota.limit()` tokens every
+    /// `quota.period()`, and holds up to `quota.burst()` tokens.
+    pub fn from_quota(quota: Quota) -> Self {
+        Self::from_quota_with_clock(quota, Clock::new())
+    }
+}
+
+impl<C: TimeSource> TokenBucket<C> {
+    /// Creates a new `TokenBucket` which reads time from the supplied [`TimeSource`].
+    ///
+    /// Use `Clock::mock()` to drive the bucket deterministically in tests.
+    ///
+    /// # Panics
+    ///
+    /// See [`TokenBucket::new`].
+    pub fn with_clock(
+        capacity: NonZeroUsize,
+        increment: NonZeroUsize,
+        period: Duration,
+        clock: C,
+    ) -> Self {
+        let quota = Quota::expect_valid(increment, period, capacity);
+        Self::from_quota_with_clock(quota, clock)
+    }
+
+    /// Creates a new `TokenBucket` from a [`Quota`], which reads time from the supplied
+    /// [`TimeSource`].
+    pub fn from_quota_with_clock(quota: Quota, clock: C) -> Self {
         let anchor = clock.now();
 
-        let refill_rate_units_per_ns = if period.as_nanos() > 0 {
-            (increment.get() as u64 * Self::UNITS_SCALE) as f64 / period.as_nanos() as f64
-        } else {
-            0f64
-        };
         Self {
-            capacity_units: capacity.get() as u64 * Self::UNITS_SCALE,
-            refill_rate_units_per_ns,
+            capacity: AtomicU64::new(quota.burst().get() as u64),
+            refill_interval_ns: AtomicU64::new(Self::refill_interval_ns(quota)),
             // Start with a full bucket
-            units: AtomicU64::new(capacity.get() as u64 * Self::UNITS_SCALE),
-            last_update_ns: AtomicU64::new(0),
+            full_at: AtomicU64::new(0),
+            reconfiguring: AtomicBool::new(false),
             clock,
             anchor,
+            backoff: Backoff::default(),
         }
     }
+
+    /// Sets how the strategy waits when it keeps losing races with other threads.
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
 }
 
-impl Strategy for TokenBucket {
-    #[inline]
-    fn process(&self) -> ControlFlow<Reason> {
-        let now = self.clock.now().duration_since(self.anchor).as_nanos() as u64;
-        let mut spins = 0;
+impl<C: TimeSource> TokenBucket<C> {
+    const POLICY: &'static str = "token_bucket";
 
-        loop {
-            if spins > 10 {
-                std::thread::yield_now();
-            }
+    /// Changes the maximum number of tokens the bucket can hold.
+    ///
+    /// Tokens already in the bucket are kept, up to the new capacity, so raising the
+    /// capacity does not let a burst through and lowering it takes effect immediately.
+    pub fn set_capacity(&self, capacity: NonZeroUsize) {
+        self.reconfigure(|| self.store_capacity(capacity.get() as u64));
+    }
+
+    fn store_capacity(&self, capacity: u64) {
+        let refill_interval_ns = self.refill_interval_ns.load(Ordering::Acquire);
+        let old_capacity = self.capacity.load(Ordering::Acquire);
+        let now = self.clock.elapsed_ns(self.anchor);
 
-            let last_update = self.last_update_ns.load(Ordering::Acquire);
-            let current_units = self.units.load(Ordering::Acquire);
-
-            // 1. Calculate how many units have accumulated since the last call
-            let elapsed = now.saturating_sub(last_update);
-
-            let refill = (elapsed as f64 * self.refill_rate_units_per_ns).floor() as u64;
-            let new_units = std::cmp::min(self.capacity_units, current_units + refill);
-
-            // 2. Check if we have at least 1 full token (10^9 units)
-            if new_units < Self::UNITS_SCALE {
-                let missing_units = Self::UNITS_SCALE - new_units;
-                // Time until 1 token is available
-                let wait_ns = if self.refill_rate_units_per_ns > 0f64 {
-                    (missing_units as f64 / self.refill_rate_units_per_ns).ceil() as u64
-                } else {
-                    // You will never get any more tokens, if there are no refills.
-                    // There's no "good" answer here, so let's make it a second
-                    Self::UNITS_SCALE
-                };
-
-                return ControlFlow::Break(Reason::Overloaded {
-                    retry_after: Duration::from_nanos(wait_ns),
+        // Change the capacity and the backlog in the order which never lets a racing
+        // request see extra tokens.
+        if capacity > old_capacity {
+            // The extra room starts out empty
+            let extra_ns = (capacity - old_capacity).saturating_mul(refill_interval_ns);
+            let _ = self
+                .full_at
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |full_at| {
+                    Some(full_at.max(now).saturating_add(extra_ns))
                 });
-            }
+            self.capacity.store(capacity, Ordering::Release);
+        } else {
+            // Tokens above the new capacity are dropped
+            self.capacity.store(capacity, Ordering::Release);
+            let excess_ns = (old_capacity - capacity).saturating_mul(refill_interval_ns);
+            let _ = self
+                .full_at
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |full_at| {
+                    Some(full_at.saturating_sub(excess_ns))
+                });
+        }
+    }
 
-            // 3. Atomically update state
-            if self
-                .last_update_ns
-                .compare_exchange_weak(last_update, now, Ordering::SeqCst, Ordering::Relaxed)
-                .is_ok()
-            {
-                self.units
-                    .store(new_units - Self::UNITS_SCALE, Ordering::Release);
-                return ControlFlow::Continue(());
-            }
-            // If CAS fails, another thread updated time; loop and recalculate.
-            spins += 1;
+    /// Changes the refill rate to `quota.limit()` tokens every `quota.period()`.
+    ///
+    /// Tokens refilled so far are credited at the old rate, and only time from now on
+    /// refills at the new one. Requests racing with the change may be judged by either
+    /// rate. The quota's burst is ignored; change the capacity with
+    /// [`TokenBucket::set_capacity`].
+    pub fn set_rate(&self, quota: Quota) {
+        self.reconfigure(|| self.store_rate(quota));
+    }
+
+    fn store_rate(&self, quota: Quota) {
+        let refill_interval_ns = Self::refill_interval_ns(quota);
+        let old_interval_ns = self
+            .refill_interval_ns
+            .swap(refill_interval_ns, Ordering::AcqRel);
+
+        // The tokens missing now are refilled at the new rate
+        let limits = Limits {
+            emission_interval_ns: refill_interval_ns,
+            delay_tolerance_ns: 0,
+        };
+        limits.rescale(
+            &self.full_at,
+            self.clock.elapsed_ns(self.anchor),
+            old_interval_ns,
+        );
+    }
+
+    /// Runs `change` once no other setter is running, so that each one reads the
+    /// capacity and rate the previous one left.
+    fn reconfigure(&self, change: impl FnOnce()) {
+        let mut failures = 0;
+        while self
+            .reconfiguring
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            failures += 1;
+            self.backoff.snooze(failures);
+        }
+        change();
+        self.reconfiguring.store(false, Ordering::Release);
+    }
+
+    fn refill_interval_ns(quota: Quota) -> u64 {
+        // A valid quota spreads its limit at least a nanosecond apart
+        quota.period().as_nanos() as u64 / quota.limit().get() as u64
+    }
+
+    /// The bucket's arithmetic, which is that of a GCRA which tolerates a burst of the
+    /// bucket's capacity.
+    #[inline]
+    fn limits(&self) -> Limits {
+        let emission_interval_ns = self.refill_interval_ns.load(Ordering::Acquire);
+        Limits {
+            emission_interval_ns,
+            delay_tolerance_ns: self
+                .capacity
+                .load(Ordering::Acquire)
+                .saturating_mul(emission_interval_ns),
         }
     }
 }
 
-#[cfg(test)]
+impl<C: TimeSource> Strategy for TokenBucket<C> {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        let now = self.clock.elapsed_ns(self.anchor);
+        self.limits()
+            .process_n(&self.full_at, now, cost, self.backoff, Self::POLICY)
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.limits().refund_n(&self.full_at, cost);
+    }
+
+    fn state(&self) -> State {
+        let now = self.clock.elapsed_ns(self.anchor);
+        let full_at = self.full_at.load(Ordering::Acquire);
+
+        self.limits().state_at(now, full_at)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: TimeSource> Persist for TokenBucket<C> {
+    fn snapshot_at(&self, now: SystemTime) -> Snapshot {
+        let elapsed = self.clock.elapsed_ns(self.anchor);
+        let Limits {
+            emission_interval_ns,
+            delay_tolerance_ns,
+        } = self.limits();
+        let missing_ns = self
+            .full_at
+            .load(Ordering::Acquire)
+            .saturating_sub(elapsed)
+            .min(delay_tolerance_ns);
+
+        Snapshot::TokenBucket {
+            tokens: (delay_tolerance_ns - missing_ns) as f64 / emission_interval_ns as f64,
+            taken_at: now,
+        }
+    }
+
+    fn restore_at(&self, snapshot: &Snapshot, now: SystemTime) -> Result<(), SnapshotError> {
+        let Snapshot::TokenBucket { tokens, taken_at } = *snapshot else {
+            return Err(mismatch(Self::POLICY, snapshot));
+        };
+
+        let Limits {
+            emission_interval_ns,
+            delay_tolerance_ns,
+        } = self.limits();
+        let held_ns = ((tokens * emission_interval_ns as f64) as u64).min(delay_tolerance_ns);
+
+        // The bucket kept refilling while the snapshot was on the shelf
+        let missing_ns = (delay_tolerance_ns - held_ns).saturating_sub(ns_since(now, taken_at));
+
+        self.full_at.store(
+            self.clock
+                .elapsed_ns(self.anchor)
+                .saturating_add(missing_ns),
+            Ordering::Release,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -164,30 +346,267 @@ mod tests {
         );
     }
 
-    #[tokio::test]
-    async fn test_token_accumulation_deterministic() {
-        // This freezes time!
-        tokio::time::pause();
+    #[test]
+    fn test_token_accumulation_deterministic() {
+        let (clock, mock) = Clock::mock();
 
-        let rl = TokenBucket::new(
+        let rl = TokenBucket::with_clock(
             NonZeroUsize::new(10).unwrap(),
             NonZeroUsize::new(1).unwrap(),
             Duration::from_millis(100),
+            clock,
         );
 
+        // Drain the bucket so only refilled tokens can be used
+        assert!(rl.process_n(NonZeroUsize::new(10).unwrap()).is_continue());
+
         // Advance time manually without relying on the OS clock
         for _ in 0..3 {
-            tokio::time::advance(Duration::from_millis(30)).await;
-            let _ = rl.process();
+            mock.increment(Duration::from_millis(30));
+            assert!(rl.process().is_break());
         }
 
-        // Total 90ms. Advance 11ms to hit 101ms.
-        tokio::time::advance(Duration::from_millis(11)).await;
+        // Total 90ms. Advance 10ms to hit exactly 100ms.
+        mock.increment(Duration::from_millis(10));
 
         assert_eq!(
             rl.process(),
             ControlFlow::Continue(()),
-            "Token should have accumulated at 101ms"
+            "Token should have accumulated at 100ms"
+        );
+        assert_eq!(
+            rl.process(),
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after: Duration::from_millis(100),
+                limit: 10,
+                remaining: 0,
+                reset_after: Duration::from_secs(1),
+                policy: "token_bucket",
+            })
+        );
+    }
+
+    #[test]
+    fn it_charges_weighted_costs() {
+        let rl = TokenBucket::new(
+            NonZeroUsize::new(10).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(1),
+        );
+
+        // Take 7 of the 10 tokens in one go
+        assert!(rl.process_n(NonZeroUsize::new(7).unwrap()).is_continue());
+
+        // 3 left, so a cost of 4 must wait for one more token (~1s)
+        match rl.process_n(NonZeroUsize::new(4).unwrap()) {
+            ControlFlow::Break(Reason::Overloaded {
+                retry_after,
+                remaining,
+                ..
+            }) => {
+                assert_eq!(remaining, 3);
+                assert!(retry_after > Duration::from_millis(900));
+                assert!(retry_after <= Duration::from_secs(1));
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+
+        // The failed attempt must not have consumed anything
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        assert!(rl.process().is_break());
+    }
+
+    #[test]
+    fn it_rejects_costs_above_capacity() {
+        let rl = TokenBucket::new(
+            NonZeroUsize::new(5).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(
+            rl.process_n(NonZeroUsize::new(6).unwrap()),
+            ControlFlow::Break(Reason::CostExceedsCapacity {
+                cost: 6,
+                capacity: 5,
+                policy: "token_bucket",
+            })
+        );
+        // Exactly capacity is fine
+        assert!(rl.process_n(NonZeroUsize::new(5).unwrap()).is_continue());
+    }
+
+    #[test]
+    fn it_reports_state_without_consuming() {
+        let rl = TokenBucket::new(
+            NonZeroUsize::new(3).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_secs(1),
+        );
+
+        let state = rl.state();
+        assert_eq!(state.limit, 3);
+        assert_eq!(state.remaining, 3);
+        assert_eq!(state.reset_after, Duration::ZERO);
+        assert_eq!(state.retry_after, Duration::ZERO);
+
+        // Inspecting twice changes nothing
+        assert_eq!(rl.state().remaining, 3);
+
+        assert!(rl.process_n(NonZeroUsize::new(3).unwrap()).is_continue());
+        let state = rl.state();
+        assert_eq!(state.remaining, 0);
+        assert!(state.retry_after > Duration::from_millis(900));
+        assert!(state.reset_after > Duration::from_millis(2900));
+        assert!(state.reset_after <= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn it_reconfigures_without_losing_state() {
+        let (clock, mock) = Clock::mock();
+        let rl = TokenBucket::with_clock(
+            NonZeroUsize::new(10).unwrap(),
+            NonZeroUsize::new(1).unwrap(),
+            Duration::from_millis(100),
+            clock,
         );
+        assert!(rl.process_n(NonZeroUsize::new(6).unwrap()).is_continue());
+
+        // Raising the capacity doesn't refill the bucket
+        rl.set_capacity(NonZeroUsize::new(20).unwrap());
+        assert_eq!(rl.state().limit, 20);
+        assert_eq!(rl.state().remaining, 4);
+
+        // Lowering it below the tokens held takes effect immediately
+        rl.set_capacity(NonZeroUsize::new(2).unwrap());
+        assert_eq!(rl.state().remaining, 2);
+        assert!(rl.process_n(NonZeroUsize::new(2).unwrap()).is_continue());
+
+        // 50ms at the old rate earns half a token, then the new rate earns the rest
+        mock.increment(Duration::from_millis(50));
+        rl.set_rate(Quota::per_second(1).unwrap());
+        mock.increment(Duration::from_millis(499));
+        assert!(rl.process().is_break());
+        mock.increment(Duration::from_millis(1));
+        assert!(rl.process().is_continue());
+        assert_eq!(rl.state().retry_after, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn it_restores_snapshots_with_refill_in_between() {
+        let ten = NonZeroUsize::new(10).unwrap();
+        let period = Duration::from_secs(1);
+        let (clock, _mock) = Clock::mock();
+        let rl = TokenBucket::with_clock(ten, ten, period, clock);
+        assert!(rl.process_n(ten).is_continue());
+
+        let taken_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let snapshot = rl.snapshot_at(taken_at);
+
+        // Half a period later, in a new process
+        let (clock, _mock) = Clock::mock();
+        let restored = TokenBucket::with_clock(ten, ten, period, clock);
+        restored
+            .restore_at(&snapshot, taken_at + Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(restored.state().remaining, 5);
+
+        let err = restored
+            .restore_at(
+                &Snapshot::Gcra { tat: taken_at },
+                taken_at + Duration::from_millis(500),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot restore a gcra snapshot into token_bucket"
+        );
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+    use crate::ManualClock;
+
+    fn bucket(clock: &'static ManualClock, capacity: usize) -> TokenBucket<&'static ManualClock> {
+        let capacity = NonZeroUsize::new(capacity).unwrap();
+        TokenBucket::with_clock(capacity, NonZeroUsize::MIN, Duration::from_secs(1), clock)
+            .with_backoff(Backoff::new(0, thread::yield_now))
+    }
+
+    #[test]
+    fn it_admits_each_token_once() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let rl = Arc::new(bucket(&CLOCK, 2));
+            let racer = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.process().is_continue())
+            };
+
+            let admitted = [rl.process().is_continue(), racer.join().unwrap()];
+            assert_eq!(admitted, [true, true]);
+            assert!(rl.process().is_break());
+            assert_eq!(rl.state().remaining, 0);
+        });
+    }
+
+    #[test]
+    fn it_consumes_a_cost_whole_or_not_at_all() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let rl = Arc::new(bucket(&CLOCK, 3));
+            let two = NonZeroUsize::new(2).unwrap();
+            let racer = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.process_n(two).is_continue())
+            };
+
+            let admitted = rl.process_n(two).is_continue();
+            assert_ne!(admitted, racer.join().unwrap());
+            assert_eq!(rl.state().remaining, 1);
+        });
+    }
+
+    #[test]
+    fn it_refunds_to_racing_requests() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let rl = Arc::new(bucket(&CLOCK, 1));
+            assert!(rl.process().is_continue());
+            let refund = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.refund_n(NonZeroUsize::MIN))
+            };
+
+            let admitted = rl.process().is_continue();
+            refund.join().unwrap();
+            assert_eq!(rl.state().remaining, usize::from(!admitted));
+        });
+    }
+
+    #[test]
+    fn it_resizes_for_the_capacity_the_last_setter_left() {
+        static CLOCK: ManualClock = ManualClock::new();
+
+        loom::model(|| {
+            let rl = Arc::new(bucket(&CLOCK, 2));
+            let raise = {
+                let rl = Arc::clone(&rl);
+                thread::spawn(move || rl.set_capacity(NonZeroUsize::new(3).unwrap()))
+            };
+
+            rl.set_capacity(NonZeroUsize::MIN);
+            raise.join().unwrap();
+            // Either order leaves the one token which fits in both capacities
+            assert_eq!(rl.state().remaining, 1);
+        });
     }
 }
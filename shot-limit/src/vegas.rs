@@ -0,0 +1,232 @@
+use core::num::NonZeroUsize;
+use core::ops::ControlFlow;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+use crate::ConcurrencyLimit;
+use crate::Feedback;
+use crate::Outcome;
+use crate::Reason;
+use crate::State;
+use crate::Strategy;
+use crate::float;
+
+/// An adaptive concurrency limit, in the style of TCP Vegas.
+///
+/// Use it where the capacity of a service is unknown or varies with load. It keeps
+/// the shortest latency seen as an estimate of the latency without queueing. Each
+/// recorded latency then estimates how many requests are queued:
+/// `limit * (1 - shortest / latency)`.
+///
+/// The limit grows while the queue is short, and shrinks when the queue grows long or
+/// a request fails. Failed requests, including ones which timed out or were
+/// cancelled, are not latency samples. Each step is `log10(limit)`. Successes
+/// recorded while less than half the limit is in use are ignored, since they say
+/// nothing about the limit. Every 1,000 samples the shortest latency is reset, so the
+/// baseline can rise if the service slows down for good.
+#[derive(Debug)]
+pub struct Vegas {
+    limit: ConcurrencyLimit,
+    min: usize,
+    max: usize,
+    /// The shortest latency seen since the last reset, in nanoseconds.
+    rtt_noload_ns: AtomicU64,
+    samples: AtomicU64,
+}
+
+impl Vegas {
+    /// The policy reported when the limit rejects a request.
+    pub const POLICY: &'static str = "vegas";
+    const PROBE_INTERVAL: u64 = 1_000;
+
+    /// Creates a new `Vegas` limit, starting at `initial` requests in flight.
+    ///
+    /// The limit adapts between 1 and 1,000 unless changed with [`Vegas::with_bounds`].
+    pub fn new(initial: NonZeroUsize) -> Self {
+        Self {
+            limit: ConcurrencyLimit::new(initial),
+            min: 1,
+            max: 1_000,
+            rtt_noload_ns: AtomicU64::new(u64::MAX),
+            samples: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the lowest and highest limits to adapt between.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is greater than `max`.
+    pub fn with_bounds(mut self, min: NonZeroUsize, max: NonZeroUsize) -> Self {
+        assert!(min <= max, "min must not exceed max");
+        self.min = min.get();
+        self.max = max.get();
+        self.set_limit(self.limit());
+        self
+    }
+
+    /// The current limit on requests in flight.
+    pub fn limit(&self) -> usize {
+        self.limit.state().limit
+    }
+
+    /// The number of requests currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.limit.in_flight()
+    }
+
+    /// Records how an admitted request went, which took `latency` to complete.
+    ///
+    /// Call this before the request's permit is released, so that it still counts as in
+    /// flight.
+    pub fn record(&self, outcome: Outcome, latency: Duration) {
+        let limit = self.limit();
+        let step = (limit.checked_ilog10().unwrap_or(0) as usize).max(1);
+
+        if outcome == Outcome::Failure {
+            // A request which failed or was cut short says nothing about the latency
+            // without queueing, so it doesn't count as a sample
+            self.set_limit(limit.saturating_sub(step));
+            return;
+        }
+
+        let rtt_ns = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX).max(1);
+
+        let samples = self.samples.fetch_add(1, Ordering::AcqRel) + 1;
+        if samples.is_multiple_of(Self::PROBE_INTERVAL) {
+            self.rtt_noload_ns.store(rtt_ns, Ordering::Release);
+        }
+        let rtt_noload_ns = self
+            .rtt_noload_ns
+            .fetch_min(rtt_ns, Ordering::AcqRel)
+            .min(rtt_ns);
+
+        if self.in_flight() * 2 < limit {
+            return;
+        }
+
+        let queue = float::ceil(limit as f64 * (1.0 - rtt_noload_ns as f64 / rtt_ns as f64));
+        let queue = queue as usize;
+        let next = if queue <= step {
+            // Nothing is queueing, so there is plenty of room to grow
+            limit + 6 * step
+        } else if queue < 3 * step {
+            limit + step
+        } else if queue > 6 * step {
+            limit - step
+        } else {
+            return;
+        };
+
+        self.set_limit(next);
+    }
+
+    fn set_limit(&self, limit: usize) {
+        if let Some(limit) = NonZeroUsize::new(limit.clamp(self.min, self.max)) {
+            self.limit.set_max(limit);
+        }
+    }
+}
+
+impl Feedback for Vegas {
+    fn record(&self, outcome: Outcome, latency: Duration) {
+        Vegas::record(self, outcome, latency);
+    }
+}
+
+impl Strategy for Vegas {
+    #[inline]
+    fn process_n(&self, cost: NonZeroUsize) -> ControlFlow<Reason> {
+        self.limit
+            .process_n(cost)
+            .map_break(|reason| reason.with_policy(Self::POLICY))
+    }
+
+    fn release_n(&self, cost: NonZeroUsize) {
+        self.limit.release_n(cost);
+    }
+
+    fn refund_n(&self, cost: NonZeroUsize) {
+        self.limit.refund_n(cost);
+    }
+
+    fn state(&self) -> State {
+        self.limit.state()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::Permit;
+
+    const BASELINE: Duration = Duration::from_millis(10);
+
+    fn vegas(limit: usize) -> Vegas {
+        Vegas::new(NonZeroUsize::new(limit).unwrap())
+    }
+
+    #[test]
+    fn it_grows_while_nothing_queues() {
+        let rl = vegas(10);
+        let _permits: Vec<_> = (0..10)
+            .map(|_| Permit::acquire(&rl).continue_value().unwrap())
+            .collect();
+
+        rl.record(Outcome::Success, BASELINE);
+        assert_eq!(rl.limit(), 16);
+
+        // A slightly slower response means a short queue, so growth slows down
+        rl.record(Outcome::Success, BASELINE + BASELINE / 10);
+        assert_eq!(rl.limit(), 17);
+    }
+
+    #[test]
+    fn it_shrinks_when_latency_grows() {
+        let rl = vegas(20);
+        let _permits: Vec<_> = (0..14)
+            .map(|_| Permit::acquire(&rl).continue_value().unwrap())
+            .collect();
+        rl.record(Outcome::Success, BASELINE);
+        assert_eq!(rl.limit(), 26);
+
+        // 10x the baseline means most requests in flight are queueing
+        rl.record(Outcome::Success, BASELINE * 10);
+        assert_eq!(rl.limit(), 25);
+        rl.record(Outcome::Failure, BASELINE);
+        assert_eq!(rl.limit(), 24);
+    }
+
+    #[test]
+    fn it_ignores_successes_when_underused() {
+        let rl = vegas(10).with_bounds(
+            NonZeroUsize::new(9).unwrap(),
+            NonZeroUsize::new(12).unwrap(),
+        );
+        let permit = Permit::acquire(&rl).continue_value().unwrap();
+
+        rl.record(Outcome::Success, BASELINE);
+        assert_eq!(rl.limit(), 10);
+
+        // Failures always count, but never beyond the bounds
+        rl.record(Outcome::Failure, BASELINE);
+        rl.record(Outcome::Failure, BASELINE);
+        assert_eq!(rl.limit(), 9);
+        drop(permit);
+    }
+
+    #[test]
+    fn it_rejects_beyond_the_limit() {
+        let rl = vegas(1);
+        let _permit = Permit::acquire(&rl).continue_value().unwrap();
+
+        match rl.process() {
+            ControlFlow::Break(Reason::Overloaded { limit, policy, .. }) => {
+                assert_eq!(limit, 1);
+                assert_eq!(policy, "vegas");
+            }
+            other => panic!("Expected Overloaded, got {:?}", other),
+        }
+    }
+}
@@ -31,6 +31,15 @@
 //!
 //! You should see something like 50% 200 and 50% 503 (unavailable) or 408 (timeout).
 //!
+//! Instead of the strategy flags, the strategy can be loaded from a TOML file with
+//! `--config limits.toml`:
+//!
+//! ```toml
+//! type = "gcra"
+//! limit = 10
+//! period = "1s"
+//! ```
+//!
 //! Notes:
 //! - The timeout is set as a failsafe to enforce SLAs in case an
 //!   inner service is taking too long to complete.
@@ -38,6 +47,7 @@
 //!   small variation around 50%.
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -48,11 +58,7 @@ use axum::response::IntoResponse;
 use axum::routing::get;
 use clap::Parser;
 use clap::ValueEnum;
-use shot_limit::FixedWindow;
-use shot_limit::Gcra;
-use shot_limit::SlidingWindow;
-use shot_limit::Strategy;
-use shot_limit::TokenBucket;
+use shot_limit::StrategyConfig;
 use tower::BoxError;
 use tower::ServiceBuilder;
 use tower_governor::GovernorLayer;
@@ -108,28 +114,55 @@ struct Args {
     /// The address to listen on
     #[arg(short, long, default_value = "127.0.0.1:3000")]
     addr: SocketAddr,
+
+    /// A TOML file describing the strategy, which overrides the strategy flags
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+impl Args {
+    /// Describes the strategy chosen by the flags, or `None` for Governor.
+    fn strategy_config(&self) -> Option<StrategyConfig> {
+        let (capacity, period) = (self.capacity, self.period);
+        match self.strategy {
+            StrategyType::Fixed => Some(StrategyConfig::FixedWindow { capacity, period }),
+            StrategyType::Gcra => Some(StrategyConfig::Gcra {
+                limit: capacity,
+                period,
+            }),
+            StrategyType::Sliding => Some(StrategyConfig::SlidingWindow { capacity, period }),
+            StrategyType::Bucket => Some(StrategyConfig::TokenBucket {
+                capacity,
+                increment: self.increment,
+                period,
+            }),
+            StrategyType::Governor => None,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), BoxError> {
     let args = Args::parse();
 
-    let capacity = args.capacity.try_into()?;
-    let increment = args.increment.try_into()?;
+    let capacity: u32 = args.capacity.try_into()?;
 
-    // Ensure the Arc is created as the trait object type immediately
-    let strategy: Option<Arc<dyn Strategy>> = match args.strategy {
-        StrategyType::Fixed => Some(Arc::new(FixedWindow::new(capacity, args.period))),
-        StrategyType::Gcra => Some(Arc::new(Gcra::new(capacity, args.period))),
-        StrategyType::Sliding => Some(Arc::new(SlidingWindow::new(capacity, args.period))),
-        StrategyType::Bucket => Some(Arc::new(TokenBucket::new(capacity, increment, args.period))),
-        StrategyType::Governor => None,
+    let config = match &args.config {
+        Some(path) => Some(toml::from_str(&std::fs::read_to_string(path)?)?),
+        None => args.strategy_config(),
     };
+    let strategy = config.as_ref().map(StrategyConfig::build).transpose()?;
 
-    println!(
-        "📡 Strategy: {:?} | Limiter: {:?} | Capacity: {} per {:?} | Timeout: {:?}",
-        args.strategy, args.limiter, args.capacity, args.period, args.timeout
-    );
+    match &config {
+        Some(config) => println!(
+            "📡 Strategy: {:?} | Limiter: {:?} | Timeout: {:?}",
+            config, args.limiter, args.timeout
+        ),
+        None => println!(
+            "📡 Strategy: {:?} | Limiter: {:?} | Capacity: {} per {:?} | Timeout: {:?}",
+            args.strategy, args.limiter, args.capacity, args.period, args.timeout
+        ),
+    }
 
     // Setup OTLP metrics
     let meter_provider = init_metrics("axum_managed").await?;
@@ -173,7 +206,7 @@ async fn main() -> Result<(), BoxError> {
             let config = Arc::new(
                 GovernorConfigBuilder::default()
                     .period(args.period)
-                    .burst_size(capacity.get() as u32)
+                    .burst_size(capacity)
                     .key_extractor(GlobalKeyExtractor)
                     .finish()
                     .ok_or(BoxError::from("Could not build governor"))?,
@@ -207,10 +240,7 @@ async fn main() -> Result<(), BoxError> {
 
     let listener = tokio::net::TcpListener::bind(args.addr).await?;
 
-    println!(
-        "📡 Listening on http://{}", 
-        listener.local_addr()?.to_string()
-    );
+    println!("📡 Listening on http://{}", listener.local_addr()?);
 
     axum::serve(listener, app).await?;
 
@@ -32,12 +32,7 @@ async fn main() {
     println!("Budget: 500ms total timeout\n");
 
     println!("---\n--- Testing Managed Throughput (Timeout Service) ---");
-    run_stress(make_timeout_svc(
-        bucket.clone(),
-        total_timeout,
-        service.clone(),
-    ))
-    .await;
+    run_stress(make_timeout_svc(bucket.clone(), total_timeout, service)).await;
 
     println!("\n--- Testing Managed Latency (Latency Service) ---");
     run_stress(make_latency_svc(bucket, total_timeout, service)).await;
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use shot_limit::AdjustableRate;
+use shot_limit::Aimd;
+use tower::Layer;
+
+use crate::feedback::FeedbackService;
+use crate::layer::RateLimitLayer;
+use crate::service::RateLimitService;
+
+/// Rate limits requests with an [`Aimd`] strategy, and adapts its rate to how the
+/// inner service copes.
+///
+/// Each response is reported to the strategy: errors, timeouts, and responses slower
+/// than its latency threshold, back the rate off; successes grow it again.
+#[derive(Debug)]
+pub struct AimdLayer<L> {
+    limiter: Arc<Aimd<L>>,
+    rate_limit: RateLimitLayer<Aimd<L>>,
+}
+
+impl<L> Clone for AimdLayer<L> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: Arc::clone(&self.limiter),
+            rate_limit: self.rate_limit.clone(),
+        }
+    }
+}
+
+impl<L> AimdLayer<L>
+where
+    L: AdjustableRate,
+{
+    /// Create an AimdLayer
+    pub fn new(limiter: Arc<Aimd<L>>) -> Self {
+        Self {
+            rate_limit: RateLimitLayer::new(Arc::clone(&limiter)),
+            limiter,
+        }
+    }
+
+    /// Set whether the service should fail immediately when overloaded.
+    ///
+    /// See [`RateLimitLayer::with_fail_fast`].
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.rate_limit = self.rate_limit.with_fail_fast(fail_fast);
+        self
+    }
+
+    /// Set a unified timeout for both waiting for a permit and request execution.
+    ///
+    /// See [`RateLimitLayer::with_timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.rate_limit = self.rate_limit.with_timeout(timeout);
+        self
+    }
+}
+
+impl<L, S> Layer<S> for AimdLayer<L>
+where
+    L: AdjustableRate,
+{
+    type Service = RateLimitService<Aimd<L>, FeedbackService<Aimd<L>, S>>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        self.rate_limit
+            .layer(FeedbackService::new(service, Arc::clone(&self.limiter)))
+    }
+}
@@ -101,6 +101,9 @@ where
                         ShotError::Timeout => rejections.timeouts += 1,
                         ShotError::Overloaded => rejections.sheds += 1,
                         ShotError::RateLimited { .. } => rejections.sheds += 1,
+                        ShotError::CostExceedsCapacity { .. } => rejections.sheds += 1,
+                        ShotError::QuotaExhausted { .. } => rejections.sheds += 1,
+                        ShotError::Blocked { .. } => rejections.sheds += 1,
                         ShotError::Inner(_) => rejections.inner += 1,
                     }
                 } else if e.downcast_ref::<tower::timeout::error::Elapsed>().is_some() {
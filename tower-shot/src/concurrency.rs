@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use shot_limit::Vegas;
+use tower::BoxError;
+use tower::Layer;
+use tower::util::MapErr;
+
+use crate::error::ShotError;
+use crate::feedback::FeedbackService;
+use crate::layer::RateLimitLayer;
+use crate::service::RateLimitService;
+
+/// Limits requests in flight with a [`Vegas`] limit, which adapts to the latency of
+/// the inner service. Errors, timeouts and cancelled requests shrink the limit.
+///
+/// Requests over the limit are shed immediately with [`ShotError::Overloaded`], as
+/// waiting for a slot would only add to the queue the limit is trying to avoid.
+#[derive(Clone, Debug)]
+pub struct AdaptiveConcurrencyLayer {
+    limiter: Arc<Vegas>,
+    rate_limit: RateLimitLayer<Vegas>,
+}
+
+impl AdaptiveConcurrencyLayer {
+    /// Create an AdaptiveConcurrencyLayer
+    pub fn new(limiter: Arc<Vegas>) -> Self {
+        Self {
+            rate_limit: RateLimitLayer::new(Arc::clone(&limiter)).with_fail_fast(true),
+            limiter,
+        }
+    }
+
+    /// Set a timeout for request execution.
+    ///
+    /// If a request takes longer, the service will return `ShotError::Timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.rate_limit = self.rate_limit.with_timeout(timeout);
+        self
+    }
+}
+
+impl<S> Layer<S> for AdaptiveConcurrencyLayer {
+    type Service =
+        MapErr<RateLimitService<Vegas, FeedbackService<Vegas, S>>, fn(BoxError) -> BoxError>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        let service = self
+            .rate_limit
+            .layer(FeedbackService::new(service, Arc::clone(&self.limiter)));
+        MapErr::new(service, shed as fn(BoxError) -> BoxError)
+    }
+}
+
+/// Reports rejections by the limit as load shedding, rather than rate limiting.
+fn shed(e: BoxError) -> BoxError {
+    match e.downcast_ref::<ShotError>() {
+        Some(ShotError::RateLimited { policy, .. }) if *policy == Vegas::POLICY => {
+            Box::new(ShotError::Overloaded)
+        }
+        _ => e,
+    }
+}
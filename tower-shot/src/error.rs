@@ -1,3 +1,7 @@
+use std::time::Duration;
+
+use shot_limit::Reason;
+
 /// Errors produced by the Tower Shot middleware stack.
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum ShotError {
@@ -18,11 +22,57 @@ pub enum ShotError {
     ///
     /// The duration indicates when the client should retry.
     /// When the `axum` feature is enabled, this converts to `429 Too Many Requests`
-    /// with a `Retry-After` header.
-    #[error("Rate limit exceeded; retry after {retry_after:?}")]
+    /// with `Retry-After` and `RateLimit-Limit`/`RateLimit-Remaining`/`RateLimit-Reset`
+    /// headers.
+    #[error("Rate limit exceeded ({policy}); retry after {retry_after:?}")]
     RateLimited {
         /// The duration to wait before retrying.
-        retry_after: std::time::Duration,
+        retry_after: Duration,
+        /// The maximum number of permits that can be available at once.
+        limit: usize,
+        /// The number of permits available when the request was rejected.
+        remaining: usize,
+        /// Time until the rate limit has fully recovered.
+        reset_after: Duration,
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+
+    /// The request costs more than the rate limit could ever allow.
+    ///
+    /// Retrying will not help. When the `axum` feature is enabled, this converts to
+    /// `413 Payload Too Large`.
+    #[error("Request cost {cost} exceeds rate limit capacity {capacity} ({policy})")]
+    CostExceedsCapacity {
+        /// The cost of the rejected request.
+        cost: usize,
+        /// The maximum cost the rate limit allows.
+        capacity: usize,
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+
+    /// A quota has been used up until a calendar boundary.
+    ///
+    /// When the `axum` feature is enabled, this converts to `429 Too Many Requests`
+    /// with `Retry-After` pointing at the boundary.
+    #[error("Quota exhausted ({policy}); resets in {retry_after:?}")]
+    QuotaExhausted {
+        /// The duration until the quota resets.
+        retry_after: Duration,
+        /// The size of the quota.
+        limit: usize,
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
+    },
+
+    /// Requests have been administratively blocked.
+    ///
+    /// When the `axum` feature is enabled, this converts to `403 Forbidden`.
+    #[error("Request blocked ({policy})")]
+    Blocked {
+        /// Identifies the policy which rejected the request.
+        policy: &'static str,
     },
 
     /// An unexpected error occurred in the inner service.
@@ -33,28 +83,103 @@ pub enum ShotError {
     Inner(String),
 }
 
+impl From<Reason> for ShotError {
+    fn from(reason: Reason) -> Self {
+        match reason {
+            Reason::Overloaded {
+                retry_after,
+                limit,
+                remaining,
+                reset_after,
+                policy,
+            } => Self::RateLimited {
+                retry_after,
+                limit,
+                remaining,
+                reset_after,
+                policy,
+            },
+            Reason::CostExceedsCapacity {
+                cost,
+                capacity,
+                policy,
+            } => Self::CostExceedsCapacity {
+                cost,
+                capacity,
+                policy,
+            },
+            Reason::QuotaExhausted {
+                retry_after,
+                limit,
+                policy,
+            } => Self::QuotaExhausted {
+                retry_after,
+                limit,
+                policy,
+            },
+            Reason::Blocked { policy } => Self::Blocked { policy },
+            // A rejection we don't know the details of
+            _ => Self::Overloaded,
+        }
+    }
+}
+
 #[cfg(feature = "axum")]
 impl axum::response::IntoResponse for ShotError {
     fn into_response(self) -> axum::response::Response {
+        use axum::http::HeaderName;
+        use axum::http::HeaderValue;
         use axum::http::StatusCode;
+        use axum::http::header::RETRY_AFTER;
+
+        const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+        const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+        const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+        // Clients should never be told to retry sooner than they can, so round up
+        fn secs(duration: Duration) -> HeaderValue {
+            HeaderValue::from(duration.as_secs_f64().ceil().max(1.0) as u64)
+        }
 
-        let (status, msg, headers) = match self {
-            Self::Overloaded => (StatusCode::SERVICE_UNAVAILABLE, self.to_string(), None),
-            Self::Timeout => (StatusCode::REQUEST_TIMEOUT, self.to_string(), None),
-            Self::RateLimited { retry_after } => {
-                let secs = retry_after.as_secs().max(1);
-                let val = axum::http::HeaderValue::from(secs);
-                (
-                    StatusCode::TOO_MANY_REQUESTS,
-                    self.to_string(),
-                    Some((axum::http::header::RETRY_AFTER, val)),
-                )
-            }
-            Self::Inner(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string(), None),
+        let (status, headers) = match self {
+            Self::Overloaded => (StatusCode::SERVICE_UNAVAILABLE, vec![]),
+            Self::Timeout => (StatusCode::REQUEST_TIMEOUT, vec![]),
+            Self::RateLimited {
+                retry_after,
+                limit,
+                remaining,
+                reset_after,
+                ..
+            } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                vec![
+                    (RETRY_AFTER, secs(retry_after)),
+                    (RATELIMIT_LIMIT, HeaderValue::from(limit)),
+                    (RATELIMIT_REMAINING, HeaderValue::from(remaining)),
+                    (RATELIMIT_RESET, secs(reset_after)),
+                ],
+            ),
+            Self::CostExceedsCapacity { capacity, .. } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                vec![(RATELIMIT_LIMIT, HeaderValue::from(capacity))],
+            ),
+            Self::QuotaExhausted {
+                retry_after, limit, ..
+            } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                vec![
+                    (RETRY_AFTER, secs(retry_after)),
+                    (RATELIMIT_LIMIT, HeaderValue::from(limit)),
+                    (RATELIMIT_REMAINING, HeaderValue::from(0)),
+                    (RATELIMIT_RESET, secs(retry_after)),
+                ],
+            ),
+            Self::Blocked { .. } => (StatusCode::FORBIDDEN, vec![]),
+            Self::Inner(_) => (StatusCode::INTERNAL_SERVER_ERROR, vec![]),
         };
 
-        let mut response = (status, msg).into_response();
-        if let Some((name, value)) = headers {
+        let mut response = (status, self.to_string()).into_response();
+        for (name, value) in headers {
             response.headers_mut().insert(name, value);
         }
         response
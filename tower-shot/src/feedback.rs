@@ -0,0 +1,125 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use pin_project_lite::pin_project;
+use shot_limit::Feedback;
+use shot_limit::Outcome;
+use tokio::time::Instant;
+use tower::Service;
+
+/// Reports the outcome and latency of every response to an adaptive strategy.
+///
+/// Errors are reported as [`Outcome::Failure`], and so are requests dropped before
+/// they complete, e.g. because they timed out or the caller went away. It must sit
+/// inside the [`RateLimitService`](crate::RateLimitService) which admits requests, so
+/// that each request is still in flight when it is reported.
+#[derive(Debug)]
+pub struct FeedbackService<L, S>
+where
+    L: ?Sized,
+{
+    inner: S,
+    limiter: Arc<L>,
+}
+
+impl<L, S> FeedbackService<L, S>
+where
+    L: Feedback + ?Sized,
+{
+    pub fn new(inner: S, limiter: Arc<L>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<L, S> Clone for FeedbackService<L, S>
+where
+    L: ?Sized,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limiter: Arc::clone(&self.limiter),
+        }
+    }
+}
+
+impl<L, S, Req> Service<Req> for FeedbackService<L, S>
+where
+    L: Feedback + ?Sized,
+    S: Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = FeedbackFuture<S::Future, L>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        FeedbackFuture {
+            inner: self.inner.call(req),
+            limiter: Arc::clone(&self.limiter),
+            start: Instant::now(),
+            done: false,
+        }
+    }
+}
+
+pin_project! {
+    /// A future that reports how the inner service future went once it completes, or
+    /// reports a failure if it is dropped first.
+    pub struct FeedbackFuture<F, L>
+    where
+        L: Feedback,
+        L: ?Sized,
+    {
+        #[pin]
+        inner: F,
+        limiter: Arc<L>,
+        start: Instant,
+        done: bool,
+    }
+
+    impl<F, L> PinnedDrop for FeedbackFuture<F, L>
+    where
+        L: Feedback,
+        L: ?Sized,
+    {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if !*this.done {
+                // Timeouts and cancellations are the clearest sign of overload
+                this.limiter.record(Outcome::Failure, this.start.elapsed());
+            }
+        }
+    }
+}
+
+impl<F, L, T, E> Future for FeedbackFuture<F, L>
+where
+    F: Future<Output = Result<T, E>>,
+    L: Feedback + ?Sized,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = match this.inner.poll(cx) {
+            Poll::Ready(output) => output,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let outcome = match output {
+            Ok(_) => Outcome::Success,
+            Err(_) => Outcome::Failure,
+        };
+        this.limiter.record(outcome, this.start.elapsed());
+        *this.done = true;
+        Poll::Ready(output)
+    }
+}
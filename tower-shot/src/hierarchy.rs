@@ -0,0 +1,179 @@
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::ops::ControlFlow;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+use pin_project_lite::pin_project;
+use shot_limit::Hierarchy;
+use shot_limit::Reason;
+use tower::BoxError;
+use tower::Layer;
+use tower::Service;
+
+use crate::error::ShotError;
+
+/// Extracts a request's path through a hierarchy, and processes it.
+type AdmitFn<Req, K> = Arc<dyn Fn(&Hierarchy<K>, &Req) -> ControlFlow<Reason> + Send + Sync>;
+
+/// Applies a [`Hierarchy`] of limits to requests, e.g. per tenant, per user and per
+/// endpoint.
+///
+/// Each request's path through the hierarchy is extracted from it by the function
+/// given to [`HierarchyLayer::new`], with one key per level. The keys are only known
+/// once the request is called, so requests over the limit are rejected immediately
+/// with a [`ShotError`] whose `policy` names the level which rejected them, rather
+/// than waiting in `poll_ready`.
+pub struct HierarchyLayer<Req, K> {
+    limiter: Arc<Hierarchy<K>>,
+    admit: AdmitFn<Req, K>,
+}
+
+impl<Req, K> Clone for HierarchyLayer<Req, K> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: Arc::clone(&self.limiter),
+            admit: Arc::clone(&self.admit),
+        }
+    }
+}
+
+impl<Req, K> fmt::Debug for HierarchyLayer<Req, K>
+where
+    K: Hash + Eq,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HierarchyLayer")
+            .field("limiter", &self.limiter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Req, K> HierarchyLayer<Req, K>
+where
+    K: Hash + Eq + Clone,
+{
+    /// Create a HierarchyLayer, which extracts each request's path through `limiter`
+    /// with `path`: an array of one key per level, from the root down.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of the array is not the number of levels in `limiter`.
+    pub fn new<F, const N: usize>(limiter: Arc<Hierarchy<K>>, path: F) -> Self
+    where
+        F: Fn(&Req) -> [K; N] + Send + Sync + 'static,
+    {
+        assert_eq!(N, limiter.depth(), "a HierarchyLayer needs a key per level");
+        Self {
+            limiter,
+            admit: Arc::new(move |limiter, req| limiter.process(&path(req))),
+        }
+    }
+}
+
+impl<Req, K, S> Layer<S> for HierarchyLayer<Req, K>
+where
+    K: Hash + Eq + Clone,
+{
+    type Service = HierarchyService<Req, K, S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        HierarchyService {
+            inner: service,
+            limiter: Arc::clone(&self.limiter),
+            admit: Arc::clone(&self.admit),
+        }
+    }
+}
+
+/// Rejects requests over any level of a [`Hierarchy`]. See [`HierarchyLayer`].
+pub struct HierarchyService<Req, K, S> {
+    inner: S,
+    limiter: Arc<Hierarchy<K>>,
+    admit: AdmitFn<Req, K>,
+}
+
+impl<Req, K, S> Clone for HierarchyService<Req, K, S>
+where
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limiter: Arc::clone(&self.limiter),
+            admit: Arc::clone(&self.admit),
+        }
+    }
+}
+
+impl<Req, K, S> fmt::Debug for HierarchyService<Req, K, S>
+where
+    K: Hash + Eq,
+    S: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HierarchyService")
+            .field("inner", &self.inner)
+            .field("limiter", &self.limiter)
+            .finish_non_exhaustive()
+    }
+}
+
+pin_project! {
+    /// The response of a [`HierarchyService`]: the inner service's, or a rejection.
+    #[project = ResponseFutureProj]
+    pub enum ResponseFuture<F> {
+        Admitted {
+            #[pin]
+            inner: F,
+        },
+        Rejected {
+            error: Option<ShotError>,
+        },
+    }
+}
+
+impl<F, T> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, BoxError>>,
+{
+    type Output = Result<T, BoxError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            ResponseFutureProj::Admitted { inner } => inner.poll(cx),
+            ResponseFutureProj::Rejected { error } => {
+                let error = error.take().expect("polled after completion");
+                Poll::Ready(Err(Box::new(error)))
+            }
+        }
+    }
+}
+
+impl<Req, K, S> Service<Req> for HierarchyService<Req, K, S>
+where
+    K: Hash + Eq + Clone,
+    S: Service<Req, Error = BoxError>,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        match (self.admit)(&self.limiter, &req) {
+            ControlFlow::Continue(()) => ResponseFuture::Admitted {
+                inner: self.inner.call(req),
+            },
+            ControlFlow::Break(reason) => ResponseFuture::Rejected {
+                error: Some(ShotError::from(reason)),
+            },
+        }
+    }
+}
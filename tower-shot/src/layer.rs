@@ -64,7 +64,7 @@ where
 
 impl<L, S> Layer<S> for RateLimitLayer<L>
 where
-    L: ?Sized,
+    L: Strategy + ?Sized,
 {
     type Service = RateLimitService<L, S>;
 
@@ -21,6 +21,13 @@
 //!    - **Best for:** Maximizing successful requests (Wait & Retry).
 //!    - **Behavior:** If the limit is reached, it waits (retries) for a permit until the specified `timeout`.
 //!
+//! [`AimdLayer`] rate limits with a [`shot_limit::Aimd`] strategy and adapts its rate
+//! to the inner service's errors and latency, instead of relying on a static limit.
+//! [`AdaptiveConcurrencyLayer`] does the same for the number of requests in flight,
+//! using a [`shot_limit::Vegas`] limit.
+//! [`HierarchyLayer`] applies nested limits, such as per tenant and per user, from a
+//! [`shot_limit::Hierarchy`], with a key extractor for each level.
+//!
 //! ## Feature Flags
 //!
 //! - `axum`: Enables `IntoResponse` for [`ShotError`], allowing automatic conversion
@@ -29,7 +36,11 @@
 //!   - `408 Request Timeout` (Timeout)
 //!   - `500 Internal Server Error` (Inner error)
 
+mod aimd;
+mod concurrency;
 mod error;
+mod feedback;
+mod hierarchy;
 mod layer;
 mod service;
 mod utils;
@@ -40,9 +51,14 @@ mod tests;
 #[cfg(doc)]
 use shot_limit::Strategy;
 
+pub use aimd::AimdLayer;
+pub use concurrency::AdaptiveConcurrencyLayer;
 pub use error::ShotError;
+pub use feedback::FeedbackService;
+pub use hierarchy::HierarchyLayer;
+pub use hierarchy::HierarchyService;
 pub use layer::RateLimitLayer;
 pub use service::RateLimitService;
 pub use utils::ServiceBuilderExt;
 pub use utils::make_latency_svc;
-pub use utils::make_timeout_svc;
\ No newline at end of file
+pub use utils::make_timeout_svc;
@@ -1,4 +1,5 @@
 use std::future::Future;
+use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -19,7 +20,7 @@ use tokio::time::timeout;
 use tower::BoxError;
 use tower::Service;
 
-use shot_limit::Reason;
+use shot_limit::Permit;
 use shot_limit::Strategy;
 
 use crate::error::ShotError;
@@ -32,12 +33,13 @@ struct RateLimitServiceMetrics {
 #[derive(Debug)]
 pub struct RateLimitService<L, S>
 where
-    L: ?Sized,
+    L: Strategy + ?Sized,
 {
     inner: S,
     limiter: Arc<L>,
     sleep: Option<Pin<Box<Sleep>>>,
-    permit_acquired: bool,
+    /// Acquired in `poll_ready` and handed to the response future by `call`.
+    permit: Option<Permit<Arc<L>>>,
     fail_fast: bool,
     timeout: Option<Duration>,
     wait_start: Option<Instant>,
@@ -47,33 +49,45 @@ where
 
 pin_project! {
     /// A future that wraps the inner service future with a timeout.
-    pub struct ResponseFuture<F> {
+    ///
+    /// It holds the request's permit until the response is ready, so concurrency
+    /// limits count the whole time the inner service spends on the request.
+    pub struct ResponseFuture<F, L>
+    where
+        L: Strategy,
+        L: ?Sized,
+    {
         #[pin]
         inner: Timeout<F>,
+        permit: Option<Permit<Arc<L>>>,
     }
 }
 
-impl<F, T, E> Future for ResponseFuture<F>
+impl<F, L, T, E> Future for ResponseFuture<F, L>
 where
     F: Future<Output = Result<T, E>>,
+    L: Strategy + ?Sized,
     E: From<BoxError>,
 {
     type Output = Result<T, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        match this.inner.poll(cx) {
-            Poll::Ready(Ok(res)) => Poll::Ready(res),
-            Poll::Ready(Err(_)) => Poll::Ready(Err(E::from(Box::new(ShotError::Timeout)))),
-            Poll::Pending => Poll::Pending,
-        }
+        let output = match this.inner.poll(cx) {
+            Poll::Ready(Ok(res)) => res,
+            Poll::Ready(Err(_)) => Err(E::from(Box::new(ShotError::Timeout))),
+            Poll::Pending => return Poll::Pending,
+        };
+        // The work is done, so release the permit without waiting to be dropped
+        this.permit.take();
+        Poll::Ready(output)
     }
 }
 
 // Manually implement Clone because Pin<Box<Sleep>> cannot be cloned
 impl<L, S> Clone for RateLimitService<L, S>
 where
-    L: ?Sized,
+    L: Strategy + ?Sized,
     S: Clone,
 {
     fn clone(&self) -> Self {
@@ -82,7 +96,7 @@ where
             limiter: Arc::clone(&self.limiter),
             // We start with a fresh sleep state for the new clone
             sleep: None,
-            permit_acquired: false,
+            permit: None,
             fail_fast: self.fail_fast,
             timeout: self.timeout,
             wait_start: None,
@@ -92,6 +106,19 @@ where
     }
 }
 
+// A permit acquired by `poll_ready` but never used by `call` is handed back, so
+// cancelled callers don't leak burst capacity.
+impl<L, S> Drop for RateLimitService<L, S>
+where
+    L: Strategy + ?Sized,
+{
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            permit.refund();
+        }
+    }
+}
+
 impl<L, S, Req> Service<Req> for RateLimitService<L, S>
 where
     L: Strategy + ?Sized,
@@ -100,7 +127,7 @@ where
 {
     type Response = S::Response;
     type Error = S::Error;
-    type Future = ResponseFuture<S::Future>;
+    type Future = ResponseFuture<S::Future, L>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         // 1. If we are currently sleeping, check if we're done
@@ -110,7 +137,9 @@ where
                     self.sleep = None;
                     // We woke up.
                     // If we have a timeout, check if we exceeded it during sleep.
-                    if let Some(timeout) = self.timeout
+                    // A delayed permit was reserved within the timeout, so honour it.
+                    if self.permit.is_none()
+                        && let Some(timeout) = self.timeout
                         && let Some(start) = self.wait_start
                         && start.elapsed() >= timeout
                     {
@@ -137,60 +166,79 @@ where
         }
 
         // 3. Check the strategy if we don't have a permit yet
-        if !self.permit_acquired {
-            // Check timeout before processing
-            if let Some(timeout) = self.timeout {
+        if self.permit.is_none() {
+            // Check timeout before processing. Shaping strategies may delay the
+            // request, but only for as long as we are prepared to wait.
+            let max_delay = if self.fail_fast {
+                Duration::ZERO
+            } else if let Some(timeout) = self.timeout {
                 let start = *self.wait_start.get_or_insert(Instant::now());
-                if start.elapsed() >= timeout {
+                let remaining = timeout.saturating_sub(start.elapsed());
+                if remaining.is_zero() {
                     self.wait_start = None;
                     return Poll::Ready(Err(Box::new(ShotError::Timeout)));
                 }
-            }
+                remaining
+            } else {
+                Duration::MAX
+            };
+
+            match Permit::reserve_n(Arc::clone(&self.limiter), NonZeroUsize::MIN, max_delay) {
+                ControlFlow::Continue((permit, delay)) => {
+                    self.permit = Some(permit);
 
-            match self.limiter.process() {
-                ControlFlow::Continue(_) => {
-                    self.permit_acquired = true;
+                    // Wait for our turn, so that calls reach the inner service evenly spaced
+                    if !delay.is_zero() {
+                        let mut sleep_fut = Box::pin(sleep(delay));
+                        if sleep_fut.as_mut().poll(cx).is_pending() {
+                            self.sleep = Some(sleep_fut);
+                            return Poll::Pending;
+                        }
+                    }
                 }
                 ControlFlow::Break(reason) => {
-                    let Reason::Overloaded { retry_after } = reason;
+                    // Fail if we were asked to, or if waiting will never help
+                    let retry_after = match reason.retry_after() {
+                        Some(retry_after) if !self.fail_fast => retry_after,
+                        _ => {
+                            self.wait_start = None;
+                            return Poll::Ready(Err(Box::new(ShotError::from(reason))));
+                        }
+                    };
+
+                    // If this is the first time we are blocking, record start time
+                    let start = *self.wait_start.get_or_insert(Instant::now());
 
-                    if self.fail_fast {
-                        return Poll::Ready(Err(Box::new(ShotError::RateLimited { retry_after })));
+                    let sleep_duration = if let Some(timeout) = self.timeout {
+                        let elapsed = start.elapsed();
+                        let remaining = timeout.saturating_sub(elapsed);
+                        if remaining.is_zero() {
+                            self.wait_start = None;
+                            return Poll::Ready(Err(Box::new(ShotError::Timeout)));
+                        }
+                        std::cmp::min(retry_after, remaining)
                     } else {
-                        // If this is the first time we are blocking, record start time
-                        let start = *self.wait_start.get_or_insert(Instant::now());
+                        retry_after
+                    };
 
-                        let sleep_duration = if let Some(timeout) = self.timeout {
-                            let elapsed = start.elapsed();
-                            let remaining = timeout.saturating_sub(elapsed);
-                            if remaining.is_zero() {
+                    let mut sleep_fut = Box::pin(sleep(sleep_duration));
+                    match sleep_fut.as_mut().poll(cx) {
+                        Poll::Pending => {
+                            self.sleep = Some(sleep_fut);
+                            return Poll::Pending;
+                        }
+                        Poll::Ready(_) => {
+                            // Immediate wakeup (sleep(0) or similar)
+                            // If due to timeout expiry
+                            if let Some(timeout) = self.timeout
+                                && start.elapsed() >= timeout
+                            {
                                 self.wait_start = None;
                                 return Poll::Ready(Err(Box::new(ShotError::Timeout)));
                             }
-                            std::cmp::min(retry_after, remaining)
-                        } else {
-                            retry_after
-                        };
-
-                        let mut sleep_fut = Box::pin(sleep(sleep_duration));
-                        match sleep_fut.as_mut().poll(cx) {
-                            Poll::Pending => {
-                                self.sleep = Some(sleep_fut);
-                                return Poll::Pending;
-                            }
-                            Poll::Ready(_) => {
-                                // Immediate wakeup (sleep(0) or similar)
-                                // If due to timeout expiry
-                                if let Some(timeout) = self.timeout
-                                    && start.elapsed() >= timeout
-                                {
-                                    self.wait_start = None;
-                                    return Poll::Ready(Err(Box::new(ShotError::Timeout)));
-                                }
-
-                                cx.waker().wake_by_ref();
-                                return Poll::Pending;
-                            }
+
+                            cx.waker().wake_by_ref();
+                            return Poll::Pending;
                         }
                     }
                 }
@@ -201,7 +249,7 @@ where
     }
 
     fn call(&mut self, req: Req) -> Self::Future {
-        self.permit_acquired = false;
+        let permit = self.permit.take();
         let start = self.wait_start.take();
         let timeout_duration = match (self.timeout, start) {
             (Some(t), Some(s)) => t.saturating_sub(s.elapsed()),
@@ -211,13 +259,14 @@ where
 
         ResponseFuture {
             inner: timeout(timeout_duration, self.inner.call(req)),
+            permit,
         }
     }
 }
 
 impl<L, S> RateLimitService<L, S>
 where
-    L: ?Sized,
+    L: Strategy + ?Sized,
 {
     pub fn new(inner: S, limiter: Arc<L>) -> Self {
         let meter = global::meter("rate_limit_service");
@@ -229,7 +278,7 @@ where
             inner,
             limiter,
             sleep: None,
-            permit_acquired: false,
+            permit: None,
             fail_fast: false,
             timeout: None,
             wait_start: None,
@@ -8,12 +8,19 @@ use std::task::Context;
 use std::task::Poll;
 use std::time::Duration;
 
+use shot_limit::Aimd;
+use shot_limit::ConcurrencyLimit;
 use shot_limit::FixedWindow;
 use shot_limit::Gcra;
+use shot_limit::LeakyBucket;
+use shot_limit::Quota;
 use shot_limit::Reason;
 use shot_limit::SlidingWindow;
+use shot_limit::State;
 use shot_limit::Strategy;
 use shot_limit::TokenBucket;
+use shot_limit::TokioClock;
+use shot_limit::Vegas;
 use tower::BoxError;
 use tower::Layer;
 use tower::Service;
@@ -52,16 +59,30 @@ struct InstantRecoveryStrategy {
 }
 
 impl Strategy for InstantRecoveryStrategy {
-    fn process(&self) -> ControlFlow<Reason> {
+    fn process_n(&self, _cost: NonZeroUsize) -> ControlFlow<Reason> {
         if self.already_blocked.swap(true, Ordering::SeqCst) {
             ControlFlow::Continue(())
         } else {
             // Hint an immediate recovery
             ControlFlow::Break(Reason::Overloaded {
                 retry_after: Duration::from_nanos(0),
+                limit: 1,
+                remaining: 0,
+                reset_after: Duration::from_nanos(0),
+                policy: "instant_recovery",
             })
         }
     }
+
+    fn state(&self) -> State {
+        let remaining = usize::from(self.already_blocked.load(Ordering::SeqCst));
+        State {
+            limit: 1,
+            remaining,
+            reset_after: Duration::ZERO,
+            retry_after: Duration::ZERO,
+        }
+    }
 }
 
 macro_rules! test_limiter_service {
@@ -128,6 +149,130 @@ test_limiter_service!(
 
 test_limiter_service!(gcra_tests, Gcra, Gcra::new);
 
+test_limiter_service!(
+    fixed_window_tokio_tests,
+    FixedWindow<TokioClock>,
+    |cap, int| FixedWindow::with_clock(cap, int, TokioClock)
+);
+
+test_limiter_service!(
+    sliding_window_tokio_tests,
+    SlidingWindow<TokioClock>,
+    |cap, int| SlidingWindow::with_clock(cap, int, TokioClock)
+);
+
+test_limiter_service!(
+    token_bucket_tokio_tests,
+    TokenBucket<TokioClock>,
+    |cap, int| TokenBucket::with_clock(cap, NonZeroUsize::new(1).unwrap(), int, TokioClock)
+);
+
+test_limiter_service!(gcra_tokio_tests, Gcra<TokioClock>, |cap, int| {
+    Gcra::with_clock(cap, int, TokioClock)
+});
+
+#[tokio::test]
+async fn test_paused_time_advances_in_lockstep() {
+    tokio::time::pause();
+
+    let strategy = Gcra::with_clock(
+        NonZeroUsize::new(1).unwrap(),
+        Duration::from_millis(100),
+        TokioClock,
+    );
+    let mock = MockService {
+        count: Arc::new(AtomicUsize::new(0)),
+    };
+    let mut service = RateLimitService::new(mock, Arc::new(strategy));
+
+    service.ready().await.unwrap().call(()).await.unwrap();
+
+    // The strategy asks us to wait exactly 100ms of virtual time
+    assert!(futures::poll!(service.ready()).is_pending());
+    tokio::time::advance(Duration::from_millis(99)).await;
+    assert!(futures::poll!(service.ready()).is_pending());
+
+    // Once tokio time passes the retry point, both the sleep and the strategy agree.
+    // (tokio timers have millisecond granularity, so allow for rounding up.)
+    tokio::time::advance(Duration::from_millis(2)).await;
+    assert!(futures::poll!(service.ready()).is_ready());
+}
+
+// Records the (virtual) time of every call
+#[derive(Clone, Debug)]
+struct RecordingService {
+    calls: Arc<std::sync::Mutex<Vec<tokio::time::Instant>>>,
+}
+
+impl Service<()> for RecordingService {
+    type Response = ();
+    type Error = BoxError;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: ()) -> Self::Future {
+        self.calls.lock().unwrap().push(tokio::time::Instant::now());
+        ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn test_leaky_bucket_spaces_calls_evenly() {
+    tokio::time::pause();
+
+    // One call every 100ms, with room for 4 to queue
+    let strategy = LeakyBucket::with_clock(
+        NonZeroUsize::new(10).unwrap(),
+        Duration::from_secs(1),
+        NonZeroUsize::new(4).unwrap(),
+        TokioClock,
+    );
+    let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let service = RateLimitService::new(
+        RecordingService {
+            calls: calls.clone(),
+        },
+        Arc::new(strategy),
+    )
+    .with_timeout(Duration::from_millis(250));
+
+    let start = tokio::time::Instant::now();
+    let handles: Vec<_> = (0..5)
+        .map(|_| {
+            let service = service.clone();
+            tokio::spawn(async move { service.oneshot(()).await })
+        })
+        .collect();
+
+    let mut timeouts = 0;
+    for handle in handles {
+        if let Err(e) = handle.await.unwrap() {
+            assert!(matches!(
+                e.downcast_ref::<ShotError>(),
+                Some(ShotError::Timeout)
+            ));
+            timeouts += 1;
+        }
+    }
+
+    // The burst reaches the inner service evenly spaced, and the requests which
+    // would have had to wait beyond the timeout never join the queue.
+    // (tokio timers have millisecond granularity, so allow for rounding up.)
+    let offsets: Vec<_> = calls.lock().unwrap().iter().map(|t| *t - start).collect();
+    assert_eq!(offsets.len(), 3);
+    for (offset, expected) in offsets.iter().zip([0, 100, 200].map(Duration::from_millis)) {
+        assert!(
+            *offset >= expected && *offset <= expected + Duration::from_millis(1),
+            "Calls should be evenly spaced: {:?}",
+            offsets
+        );
+    }
+    assert_eq!(timeouts, 2);
+}
+
 #[tokio::test]
 async fn test_layer_integration() {
     let limiter = SlidingWindow::new(NonZeroUsize::new(100).unwrap(), Duration::from_secs(1));
@@ -465,10 +610,269 @@ async fn test_fail_fast() {
 
     // Verify it is ShotError::RateLimited
     match err.downcast_ref::<ShotError>() {
-        Some(ShotError::RateLimited { retry_after }) => {
+        Some(ShotError::RateLimited {
+            retry_after,
+            limit,
+            remaining,
+            policy,
+            ..
+        }) => {
             assert!(*retry_after > Duration::from_secs(0));
             assert!(*retry_after <= Duration::from_secs(60));
+            assert_eq!(*limit, 1);
+            assert_eq!(*remaining, 0);
+            assert_eq!(*policy, "fixed_window");
         }
         _ => panic!("Expected ShotError::RateLimited, got {:?}", err),
     }
 }
+
+#[tokio::test]
+async fn test_concurrency_permit_held_until_response() {
+    let strategy = Arc::new(ConcurrencyLimit::new(NonZeroUsize::new(1).unwrap()));
+
+    // The inner service only responds once told to
+    let inner = tower::service_fn(|rx: tokio::sync::oneshot::Receiver<()>| async move {
+        let _ = rx.await;
+        Ok::<_, BoxError>(())
+    });
+    let mut service = RateLimitService::new(inner, Arc::clone(&strategy)).with_fail_fast(true);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let response = service.ready().await.unwrap().call(rx);
+    assert_eq!(strategy.in_flight(), 1);
+
+    // Returning from `call` is not enough, the response is still being worked on
+    let err = service.ready().await.expect_err("Should be at capacity");
+    match err.downcast_ref::<ShotError>() {
+        Some(ShotError::RateLimited { policy, .. }) => assert_eq!(*policy, "concurrency"),
+        _ => panic!("Expected ShotError::RateLimited, got {:?}", err),
+    }
+
+    tx.send(()).unwrap();
+    response.await.unwrap();
+    assert_eq!(strategy.in_flight(), 0);
+
+    // A permit acquired by poll_ready but never used is released with the service
+    service.ready().await.unwrap();
+    assert_eq!(strategy.in_flight(), 1);
+    drop(service);
+    assert_eq!(strategy.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn test_unused_permit_refunded_on_drop() {
+    let strategy = Arc::new(TokenBucket::new(
+        NonZeroUsize::new(1).unwrap(),
+        NonZeroUsize::new(1).unwrap(),
+        Duration::from_secs(3600),
+    ));
+    let mock = MockService {
+        count: Arc::new(AtomicUsize::new(0)),
+    };
+    let mut service = RateLimitService::new(mock, Arc::clone(&strategy)).with_fail_fast(true);
+
+    // The caller is cancelled between poll_ready and call
+    service.ready().await.unwrap();
+    assert_eq!(strategy.state().remaining, 0);
+    let mut clone = service.clone();
+    drop(service);
+
+    // The token comes back, so the next caller isn't rate limited
+    assert_eq!(strategy.state().remaining, 1);
+    clone.ready().await.unwrap().call(()).await.unwrap();
+    assert!(clone.ready().await.is_err());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_aimd_layer_adapts_to_errors_and_latency() {
+    let limit = NonZeroUsize::new(8).unwrap();
+    let period = Duration::from_secs(1);
+    let strategy = Arc::new(
+        Aimd::new(
+            Gcra::with_clock(limit, period, TokioClock),
+            Quota::new(limit.get(), period).unwrap(),
+        )
+        .with_latency_threshold(Duration::from_millis(100)),
+    );
+
+    // The inner service fails on request, or takes as long as it's asked to
+    let inner = tower::service_fn(|(fail, latency): (bool, Duration)| async move {
+        tokio::time::sleep(latency).await;
+        if fail {
+            Err::<(), BoxError>("downstream failed".into())
+        } else {
+            Ok(())
+        }
+    });
+    let mut service = AimdLayer::new(Arc::clone(&strategy))
+        .with_fail_fast(true)
+        .layer(inner);
+
+    let call = service
+        .ready()
+        .await
+        .unwrap()
+        .call((false, Duration::from_millis(10)));
+    call.await.unwrap();
+    assert_eq!(strategy.limit(), 8);
+
+    let call = service.ready().await.unwrap().call((true, Duration::ZERO));
+    assert!(call.await.is_err());
+    assert_eq!(strategy.limit(), 4);
+
+    let call = service
+        .ready()
+        .await
+        .unwrap()
+        .call((false, Duration::from_millis(150)));
+    call.await.unwrap();
+    assert_eq!(strategy.limit(), 2);
+    assert_eq!(strategy.state().limit, 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_aimd_layer_backs_off_on_timeouts_and_cancellations() {
+    let limit = NonZeroUsize::new(8).unwrap();
+    let period = Duration::from_secs(1);
+    let strategy = Arc::new(Aimd::new(
+        Gcra::with_clock(limit, period, TokioClock),
+        Quota::new(limit.get(), period).unwrap(),
+    ));
+
+    let inner = tower::service_fn(|latency: Duration| async move {
+        tokio::time::sleep(latency).await;
+        Ok::<_, BoxError>(())
+    });
+    let mut service = AimdLayer::new(Arc::clone(&strategy))
+        .with_fail_fast(true)
+        .with_timeout(Duration::from_millis(100))
+        .layer(inner);
+
+    // The timeout drops the response future before it completes
+    let call = service.ready().await.unwrap().call(Duration::from_secs(1));
+    let err = call.await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ShotError>(),
+        Some(ShotError::Timeout)
+    ));
+    assert_eq!(strategy.limit(), 4);
+
+    // So does a caller which gives up on the request
+    let call = service.ready().await.unwrap().call(Duration::from_secs(1));
+    drop(call);
+    assert_eq!(strategy.limit(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_adaptive_concurrency_sheds_and_grows() {
+    let strategy = Arc::new(Vegas::new(NonZeroUsize::new(1).unwrap()));
+
+    let inner = tower::service_fn(|rx: tokio::sync::oneshot::Receiver<()>| async move {
+        let _ = rx.await;
+        Ok::<_, BoxError>(())
+    });
+    let mut service = AdaptiveConcurrencyLayer::new(Arc::clone(&strategy)).layer(inner);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let response = service.ready().await.unwrap().call(rx);
+
+    // The limit is full, so the next request is shed rather than queued
+    let err = service.ready().await.expect_err("Should be shed");
+    assert!(matches!(
+        err.downcast_ref::<ShotError>(),
+        Some(ShotError::Overloaded)
+    ));
+
+    // The response came back with nothing queueing, so the limit grows
+    tx.send(()).unwrap();
+    response.await.unwrap();
+    assert_eq!(strategy.in_flight(), 0);
+    assert_eq!(strategy.limit(), 7);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_adaptive_concurrency_shrinks_on_timeouts() {
+    let strategy = Arc::new(Vegas::new(NonZeroUsize::new(8).unwrap()));
+
+    let inner = tower::service_fn(|latency: Duration| async move {
+        tokio::time::sleep(latency).await;
+        Ok::<_, BoxError>(())
+    });
+    let mut service = AdaptiveConcurrencyLayer::new(Arc::clone(&strategy))
+        .with_timeout(Duration::from_millis(100))
+        .layer(inner);
+
+    let call = service.ready().await.unwrap().call(Duration::from_secs(1));
+    let err = call.await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ShotError>(),
+        Some(ShotError::Timeout)
+    ));
+    assert_eq!(strategy.limit(), 7);
+    assert_eq!(strategy.in_flight(), 0);
+
+    // A cancelled request shrinks the limit, and doesn't pass for a fast response
+    let call = service.ready().await.unwrap().call(Duration::from_secs(1));
+    drop(call);
+    assert_eq!(strategy.limit(), 6);
+    assert_eq!(strategy.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn test_hierarchy_layer_reports_the_rejecting_level() {
+    let n = |n| NonZeroUsize::new(n).unwrap();
+    let period = Duration::from_secs(60);
+    let limiter = Arc::new(
+        shot_limit::Hierarchy::new(n(100), period)
+            .with_level("tenant", move || FixedWindow::new(n(3), period))
+            .with_level("user", move || FixedWindow::new(n(2), period)),
+    );
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&count);
+    let mut service =
+        HierarchyLayer::new(limiter, |(tenant, user): &(&str, &str)| [*tenant, *user]).layer(
+            tower::service_fn(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                ready(Ok::<_, BoxError>(()))
+            }),
+        );
+
+    let mut call = async |req| service.ready().await.unwrap().call(req).await;
+    call(("acme", "alice")).await.unwrap();
+    call(("acme", "alice")).await.unwrap();
+
+    let err = call(("acme", "alice")).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ShotError>(),
+        Some(ShotError::RateLimited { policy: "user", .. })
+    ));
+
+    call(("acme", "bob")).await.unwrap();
+    let err = call(("acme", "bob")).await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ShotError>(),
+        Some(ShotError::RateLimited {
+            policy: "tenant",
+            ..
+        })
+    ));
+
+    // Rejected requests never reach the inner service
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+#[should_panic(expected = "a key per level")]
+fn test_hierarchy_layer_needs_a_key_per_level() {
+    let n = |n| NonZeroUsize::new(n).unwrap();
+    let period = Duration::from_secs(60);
+    let limiter = Arc::new(
+        shot_limit::Hierarchy::new(n(100), period)
+            .with_level("tenant", move || FixedWindow::new(n(3), period))
+            .with_level("user", move || FixedWindow::new(n(2), period)),
+    );
+
+    let _ = HierarchyLayer::new(limiter, |tenant: &&str| [*tenant]);
+}
@@ -80,6 +80,11 @@ fn map_overloaded(e: BoxError) -> BoxError {
     }
 }
 
+type LatencyRateLimitStack<L> = Stack<
+    RateLimitLayer<dyn Strategy>,
+    Stack<LoadShedLayer, Stack<MapErrLayer<fn(BoxError) -> BoxError>, L>>,
+>;
+
 /// Service Builder Extension with additional useful functions for tower::ServiceBuilder.
 pub trait ServiceBuilderExt<L> {
     /// Add a high throughput layer (see [`make_timeout_svc`])
@@ -94,12 +99,7 @@ pub trait ServiceBuilderExt<L> {
         self,
         limiter: Arc<dyn Strategy>,
         timeout: Duration,
-    ) -> ServiceBuilder<
-        Stack<
-            RateLimitLayer<dyn Strategy>,
-            Stack<LoadShedLayer, Stack<MapErrLayer<fn(BoxError) -> BoxError>, L>>,
-        >,
-    >;
+    ) -> ServiceBuilder<LatencyRateLimitStack<L>>;
 }
 
 impl<L> ServiceBuilderExt<L> for ServiceBuilder<L> {
@@ -115,12 +115,7 @@ impl<L> ServiceBuilderExt<L> for ServiceBuilder<L> {
         self,
         limiter: Arc<dyn Strategy>,
         timeout: Duration,
-    ) -> ServiceBuilder<
-        Stack<
-            RateLimitLayer<dyn Strategy>,
-            Stack<LoadShedLayer, Stack<MapErrLayer<fn(BoxError) -> BoxError>, L>>,
-        >,
-    > {
+    ) -> ServiceBuilder<LatencyRateLimitStack<L>> {
         self.map_err(map_overloaded as fn(BoxError) -> BoxError)
             .load_shed()
             .layer(